@@ -0,0 +1,602 @@
+use std::fmt;
+use std::error::Error;
+use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use BlobHash;
+use ChatUser;
+use time::Tm;
+use json::{self, Field, FieldSpec, FromJsonnable, JsonOptions, JsonSchema, ToJsonnable, UnknownFields};
+use serde::de::{Error as SerdeError, Type};
+use serde_json;
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+use serde_json::builder::ObjectBuilder;
+
+
+/// What kind of moderation or administration happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditKind {
+	Ban,
+	Unban,
+	Kick,
+	Mute,
+	Unmute,
+	/// A message was deleted
+	Delete,
+	/// A message was edited by someone other than its sender
+	Edit,
+	RoleChange,
+	/// A room's topic or other setting was changed
+	RoomConfig,
+}
+
+/// What an [`AuditEvent`](struct.AuditEvent.html) was done to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AuditTarget {
+	User(String),
+	/// A message, by ID
+	Message(u64),
+	Room(String),
+}
+
+/// A single moderation or administration action, as recorded in the audit log.
+///
+/// `before` and `after` hold whatever changed, e.g. the old and new [`Role`](enum.Role.html),
+/// the deleted message, or the [`Moderation`](struct.Moderation.html) applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+	pub kind: AuditKind,
+	pub actor: ChatUser,
+	pub target: AuditTarget,
+	/// The room the action was taken in, if it wasn't server-wide
+	pub room: Option<String>,
+	pub time: Tm,
+	pub before: Option<Value>,
+	pub after: Option<Value>,
+}
+
+/// An [`AuditEvent`](struct.AuditEvent.html) linked into the audit log's hash chain.
+///
+/// `hash` is the SHA-256 of the `previous` record's hash, the `sequence` number and the event's JSON exactly as written in the log,
+/// so changing, removing or reordering any record breaks the chain from that point on.
+/// Removing records from the end, or rewriting the whole log, is only detected against an [`AuditHead`](struct.AuditHead.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+	/// Position in the log, starting at `0`
+	pub sequence: u64,
+	/// Hash of the record before this one, or all zeroes for the first one
+	pub previous: BlobHash,
+	pub hash: BlobHash,
+	pub event: AuditEvent,
+	/// The event's JSON the hash covers
+	event_json: String,
+}
+
+/// Where an audit log ends: how many records it has and the hash of the last one.
+///
+/// The hash chain alone can't tell an intact log from a truncated one, or one rewritten with recomputed hashes,
+/// so keep the head of every log somewhere its writer can't tamper with, and verify the log against it.
+/// The `Default` head is that of an empty log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AuditHead {
+	pub records: u64,
+	/// Hash of the last record, or all zeroes for an empty log
+	pub last_hash: BlobHash,
+}
+
+/// Append-only audit log, writing one JSON [`AuditRecord`](struct.AuditRecord.html) per line,
+/// with the default [`JsonOptions`](json/struct.JsonOptions.html) unless set with [`with_options()`](#method.with_options).
+///
+/// Check a log's integrity with [`verify_audit_log()`](fn.verify_audit_log.html),
+/// against the [`head()`](#method.head) saved after the last append.
+#[derive(Debug)]
+pub struct AuditSink<W: Write> {
+	writer: W,
+	sequence: u64,
+	last_hash: BlobHash,
+	options: JsonOptions,
+}
+
+/// Why an audit log couldn't be read or failed verification
+#[derive(Debug)]
+pub enum AuditError {
+	Io(io::Error),
+	/// Line `line`, counting from 1, isn't a valid record
+	Malformed{
+		line: u64,
+		error: JsonError,
+	},
+	/// A record is missing or out of place
+	OutOfSequence{
+		expected: u64,
+		actual: u64,
+	},
+	/// The record's `previous` hash doesn't match the record before it
+	BrokenChain{
+		sequence: u64,
+	},
+	/// The record's `hash` doesn't match its contents
+	HashMismatch{
+		sequence: u64,
+	},
+	/// The log is intact, but doesn't end where expected, so it was truncated, extended or rewritten
+	HeadMismatch{
+		expected: AuditHead,
+		actual: AuditHead,
+	},
+}
+
+
+static GENESIS: BlobHash = BlobHash([0; 32]);
+
+static ALL_AUDIT_KINDS: &[AuditKind] = &[AuditKind::Ban, AuditKind::Unban, AuditKind::Kick, AuditKind::Mute, AuditKind::Unmute, AuditKind::Delete,
+                                         AuditKind::Edit, AuditKind::RoleChange, AuditKind::RoomConfig];
+
+const KIND: Field<AuditKind> = Field::required("kind");
+const ACTOR: Field<ChatUser> = Field::required("actor");
+const TARGET: Field<AuditTarget> = Field::required("target");
+const ROOM: Field<Option<String>> = Field::optional("room");
+const TIME: Field<Tm> = Field::required("time");
+const BEFORE: Field<Option<Value>> = Field::optional("before");
+const AFTER: Field<Option<Value>> = Field::optional("after");
+
+static EVENT_FIELDS: [FieldSpec; 7] = [KIND.spec(), ACTOR.spec(), TARGET.spec(), ROOM.spec(), TIME.spec(),
+                                      BEFORE.spec_with(json::any_schema), AFTER.spec_with(json::any_schema)];
+
+const SEQUENCE: Field<u64> = Field::required("sequence");
+const PREVIOUS: Field<BlobHash> = Field::required("previous");
+const HASH: Field<BlobHash> = Field::required("hash");
+const EVENT: Field<AuditEvent> = Field::required("event");
+
+static RECORD_FIELDS: [FieldSpec; 4] = [SEQUENCE.spec(), PREVIOUS.spec(), HASH.spec(), EVENT.spec()];
+
+// Records are written as `{"event":…,"hash":…}`, so the event's exact JSON can be found in a line
+static EVENT_START: &str = "{\"event\":";
+static EVENT_END: &str = ",\"hash\":";
+
+
+/// Read and verify a whole audit log written by an [`AuditSink`](struct.AuditSink.html), which must end at `head`, returning its records
+pub fn verify_audit_log<R: BufRead>(reader: R, head: &AuditHead) -> Result<Vec<AuditRecord>, AuditError> {
+	let mut records: Vec<AuditRecord> = Vec::new();
+	for (idx, line) in reader.lines().enumerate() {
+		let line = try!(line);
+		if line.is_empty() {
+			continue;
+		}
+
+		let record = try!(AuditRecord::from_line(&line).map_err(|e| AuditError::Malformed{
+			line: idx as u64 + 1,
+			error: e,
+		}));
+		try!(record.verify(records.last()));
+		records.push(record);
+	}
+
+	let actual = records.last().map(AuditRecord::head).unwrap_or_default();
+	if actual == *head {
+		Ok(records)
+	} else {
+		Err(AuditError::HeadMismatch{
+			expected: *head,
+			actual: actual,
+		})
+	}
+}
+
+
+impl AuditEvent {
+	/// Create an event with no room or before/after values
+	pub fn new(kind: AuditKind, actor: ChatUser, target: AuditTarget, time: Tm) -> AuditEvent {
+		AuditEvent{
+			kind: kind,
+			actor: actor,
+			target: target,
+			room: None,
+			time: time,
+			before: None,
+			after: None,
+		}
+	}
+
+	pub fn in_room(mut self, room: &str) -> AuditEvent {
+		self.room = Some(room.to_string());
+		self
+	}
+
+	pub fn before<T: ToJsonnable>(mut self, before: &T) -> AuditEvent {
+		self.before = Some(before.to_json());
+		self
+	}
+
+	pub fn after<T: ToJsonnable>(mut self, after: &T) -> AuditEvent {
+		self.after = Some(after.to_json());
+		self
+	}
+}
+
+impl AuditRecord {
+	/// Link `event` after the record with `previous` hash, at position `sequence`
+	pub fn new(sequence: u64, previous: BlobHash, event: AuditEvent) -> AuditRecord {
+		AuditRecord::new_with(sequence, previous, event, &JsonOptions::default())
+	}
+
+	/// Link `event`, serialized with `options`, after the record with `previous` hash, at position `sequence`
+	pub fn new_with(sequence: u64, previous: BlobHash, event: AuditEvent, options: &JsonOptions) -> AuditRecord {
+		let event_json = event.to_json_string_with(options).unwrap();
+		AuditRecord{
+			sequence: sequence,
+			previous: previous,
+			hash: AuditRecord::chain_hash(sequence, &previous, &event_json),
+			event: event,
+			event_json: event_json,
+		}
+	}
+
+	/// The head of a log ending with this record
+	pub fn head(&self) -> AuditHead {
+		AuditHead{
+			records: self.sequence + 1,
+			last_hash: self.hash,
+		}
+	}
+
+	/// Check that the record is intact and directly follows `previous`, or is the first one for `None`
+	pub fn verify(&self, previous: Option<&AuditRecord>) -> Result<(), AuditError> {
+		let (expected_sequence, expected_previous) =
+			match previous {
+				Some(previous) => (previous.sequence + 1, previous.hash),
+				None           => (0, GENESIS),
+			};
+
+		if self.sequence != expected_sequence {
+			Err(AuditError::OutOfSequence{
+				expected: expected_sequence,
+				actual: self.sequence,
+			})
+		} else if self.previous != expected_previous {
+			Err(AuditError::BrokenChain{sequence: self.sequence})
+		} else if self.hash != AuditRecord::chain_hash(self.sequence, &self.previous, &self.event_json) {
+			Err(AuditError::HashMismatch{sequence: self.sequence})
+		} else {
+			Ok(())
+		}
+	}
+
+
+	fn chain_hash(sequence: u64, previous: &BlobHash, event_json: &str) -> BlobHash {
+		BlobHash::of(format!("{}\n{}\n{}", previous, sequence, event_json).as_bytes())
+	}
+
+	/// The record as a line of the log, with the event exactly as hashed
+	fn line(&self) -> String {
+		format!("{}{}{}\"{}\",\"previous\":\"{}\",\"sequence\":{}}}", EVENT_START, self.event_json, EVENT_END, self.hash, self.previous, self.sequence)
+	}
+
+	/// Read a line of the log, hashing and decoding the event from its exact JSON there rather than reserializing it
+	fn from_line(line: &str) -> Result<AuditRecord, JsonError> {
+		let options = read_options();
+		let mut record = try!(AuditRecord::from_json_string_with(line, &options));
+		let event_json =
+			match line.rfind(EVENT_END) {
+				Some(end) if line.starts_with(EVENT_START) && end >= EVENT_START.len() => &line[EVENT_START.len()..end],
+				_ => return Err(JsonError::custom("audit record doesn't start with its event")),
+			};
+		record.event = try!(AuditEvent::from_json_string_with(event_json, &options));
+		record.event_json = event_json.to_string();
+		Ok(record)
+	}
+}
+
+impl<W: Write> AuditSink<W> {
+	/// Start a new log in `writer`
+	pub fn new(writer: W) -> AuditSink<W> {
+		AuditSink{
+			writer: writer,
+			sequence: 0,
+			last_hash: GENESIS,
+			options: JsonOptions::default(),
+		}
+	}
+
+	/// Continue a log ending at `head` in `writer`
+	pub fn resume(writer: W, head: &AuditHead) -> AuditSink<W> {
+		AuditSink{
+			writer: writer,
+			sequence: head.records,
+			last_hash: head.last_hash,
+			options: JsonOptions::default(),
+		}
+	}
+
+	/// Write events with `options`, e.g. to write times in another format.
+	///
+	/// Hashes cover the events as written, so the same events written with different options make different logs.
+	pub fn with_options(mut self, options: JsonOptions) -> AuditSink<W> {
+		self.options = options;
+		self
+	}
+
+	/// Link `event` into the chain and write it out, returning the written record
+	pub fn append(&mut self, event: AuditEvent) -> Result<AuditRecord, AuditError> {
+		let record = AuditRecord::new_with(self.sequence, self.last_hash, event, &self.options);
+		// Refuse events that couldn't be read back, like ones by users named under a looser NamePolicy, rather than break the log
+		try!(AuditEvent::from_json_string_with(&record.event_json, &read_options()).map_err(|e| AuditError::Malformed{
+			line: record.sequence + 1,
+			error: e,
+		}));
+		try!(writeln!(self.writer, "{}", record.line()));
+		try!(self.writer.flush());
+
+		self.sequence += 1;
+		self.last_hash = record.hash;
+		Ok(record)
+	}
+
+	/// Sequence number the next record will get
+	pub fn next_sequence(&self) -> u64 {
+		self.sequence
+	}
+
+	/// Where the log written so far ends, to be saved after every append
+	pub fn head(&self) -> AuditHead {
+		AuditHead{
+			records: self.sequence,
+			last_hash: self.last_hash,
+		}
+	}
+
+	pub fn into_inner(self) -> W {
+		self.writer
+	}
+}
+
+impl AuditSink<File> {
+	/// Open the log at `path`, which must end at `head`, for appending, creating it if needed.
+	///
+	/// The existing log is verified first, so a tampered-with log is never extended.
+	/// A last record cut off by a crash while it was being appended is removed, since `head` can't include it.
+	pub fn open<P: AsRef<Path>>(path: P, head: &AuditHead) -> Result<AuditSink<File>, AuditError> {
+		let mut file = try!(OpenOptions::new().read(true).append(true).create(true).open(path));
+		let length = try!(file.seek(SeekFrom::End(0)));
+		let complete = try!(complete_length(&mut file));
+		try!(file.seek(SeekFrom::Start(0)));
+		try!(verify_audit_log(BufReader::new((&file).take(complete)), head));
+		if complete != length {
+			try!(file.set_len(complete));
+		}
+		Ok(AuditSink::resume(file, head))
+	}
+}
+
+
+// The length of the log up to its last newline, after which there can only be a record cut off mid-append
+fn complete_length(file: &mut File) -> io::Result<u64> {
+	let mut end = try!(file.seek(SeekFrom::End(0)));
+	let mut chunk = [0; 4096];
+	while end > 0 {
+		let start = end.saturating_sub(chunk.len() as u64);
+		let chunk = &mut chunk[..(end - start) as usize];
+		try!(file.seek(SeekFrom::Start(start)));
+		try!(file.read_exact(chunk));
+		if let Some(newline) = chunk.iter().rposition(|&b| b == b'\n') {
+			return Ok(start + newline as u64 + 1);
+		}
+		end = start;
+	}
+	Ok(0)
+}
+
+// Keep actors' unknown fields, since their hashes cover them
+fn read_options() -> JsonOptions {
+	JsonOptions::default().with_unknown_fields::<ChatUser>(UnknownFields::Preserve)
+}
+
+
+impl AuditKind {
+	/// The name the kind is serialized as
+	pub fn name(&self) -> &'static str {
+		match *self {
+			AuditKind::Ban        => "ban",
+			AuditKind::Unban      => "unban",
+			AuditKind::Kick       => "kick",
+			AuditKind::Mute       => "mute",
+			AuditKind::Unmute     => "unmute",
+			AuditKind::Delete     => "delete",
+			AuditKind::Edit       => "edit",
+			AuditKind::RoleChange => "role_change",
+			AuditKind::RoomConfig => "room_config",
+		}
+	}
+}
+
+
+impl Default for AuditHead {
+	fn default() -> AuditHead {
+		AuditHead{
+			records: 0,
+			last_hash: GENESIS,
+		}
+	}
+}
+
+
+impl fmt::Display for AuditError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			AuditError::Io(ref e)                       => write!(f, "I/O error: {}", e),
+			AuditError::Malformed{line, ref error}      => write!(f, "malformed audit record on line {}: {}", line, error),
+			AuditError::OutOfSequence{expected, actual} => write!(f, "expected audit record {}, found {}", expected, actual),
+			AuditError::BrokenChain{sequence}           => write!(f, "audit record {} doesn't follow the previous record", sequence),
+			AuditError::HashMismatch{sequence}          => write!(f, "audit record {} was tampered with", sequence),
+			AuditError::HeadMismatch{expected, actual}  => write!(f, "audit log has {} records ending in {}, expected {} ending in {}",
+			                                                      actual.records, actual.last_hash, expected.records, expected.last_hash),
+		}
+	}
+}
+
+impl Error for AuditError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self {
+			AuditError::Io(ref e)                => Some(e),
+			AuditError::Malformed{ref error, ..} => Some(error),
+			_                                    => None,
+		}
+	}
+}
+
+impl From<io::Error> for AuditError {
+	fn from(e: io::Error) -> AuditError {
+		AuditError::Io(e)
+	}
+}
+
+
+impl FromJsonnable for AuditKind {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(name) => ALL_AUDIT_KINDS.iter().find(|kind| kind.name() == name).cloned().ok_or_else(|| JsonError::unknown_variant(&name)),
+			_                   => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for AuditKind {
+	fn to_json(&self) -> Value {
+		Value::String(self.name().to_string())
+	}
+}
+
+impl JsonSchema for AuditKind {
+	fn json_schema() -> Value {
+		json::enum_schema(&ALL_AUDIT_KINDS.iter().map(AuditKind::name).collect::<Vec<_>>())
+	}
+}
+
+impl FromJsonnable for AuditTarget {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				if map.len() != 1 {
+					return Err(JsonError::invalid_length(map.len()));
+				}
+				if map.contains_key("user") {
+					Ok(AuditTarget::User(try!(json::string_field(&map, "user"))))
+				} else if map.contains_key("message") {
+					Ok(AuditTarget::Message(try!(json::field(&map, "message", &JsonOptions::default()))))
+				} else if map.contains_key("room") {
+					Ok(AuditTarget::Room(try!(json::string_field(&map, "room"))))
+				} else {
+					Err(JsonError::unknown_variant(map.keys().next().unwrap()))
+				}
+			},
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for AuditTarget {
+	fn to_json(&self) -> Value {
+		match *self {
+			AuditTarget::User(ref name) => ObjectBuilder::new().insert("user", name),
+			AuditTarget::Message(id)    => ObjectBuilder::new().insert("message", id),
+			AuditTarget::Room(ref room) => ObjectBuilder::new().insert("room", room),
+		}.build()
+	}
+}
+
+impl JsonSchema for AuditTarget {
+	fn json_schema() -> Value {
+		json::any_of_schema(vec![json::single_field_schema("user", String::json_schema()),
+		                         json::single_field_schema("message", u64::json_schema()),
+		                         json::single_field_schema("room", String::json_schema())])
+	}
+}
+
+impl FromJsonnable for AuditEvent {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
+				Ok(AuditEvent{
+					kind: try!(KIND.decode(&map, options)),
+					actor: try!(ACTOR.decode(&map, options)),
+					target: try!(TARGET.decode(&map, options)),
+					room: try!(ROOM.decode(&map, options)),
+					time: try!(TIME.decode(&map, options)),
+					before: try!(BEFORE.decode(&map, options)),
+					after: try!(AFTER.decode(&map, options)),
+				}),
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for AuditEvent {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		KIND.encode(&mut map, &self.kind, options);
+		ACTOR.encode(&mut map, &self.actor, options);
+		TARGET.encode(&mut map, &self.target, options);
+		ROOM.encode(&mut map, &self.room, options);
+		TIME.encode(&mut map, &self.time, options);
+		BEFORE.encode(&mut map, &self.before, options);
+		AFTER.encode(&mut map, &self.after, options);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for AuditEvent {
+	fn json_schema() -> Value {
+		json::fields_schema(&EVENT_FIELDS)
+	}
+}
+
+impl FromJsonnable for AuditRecord {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
+				Ok(AuditRecord{
+					sequence: try!(SEQUENCE.decode(&map, options)),
+					previous: try!(PREVIOUS.decode(&map, options)),
+					hash: try!(HASH.decode(&map, options)),
+					event: try!(EVENT.decode(&map, options)),
+					// Only a line of the log has the event's exact JSON, see from_line()
+					event_json: try!(serde_json::to_string(&map[EVENT.name])),
+				}),
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for AuditRecord {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		SEQUENCE.encode(&mut map, &self.sequence, options);
+		PREVIOUS.encode(&mut map, &self.previous, options);
+		HASH.encode(&mut map, &self.hash, options);
+		EVENT.encode(&mut map, &self.event, options);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for AuditRecord {
+	fn json_schema() -> Value {
+		json::fields_schema(&RECORD_FIELDS)
+	}
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+use std::error::Error;
+use std::str::FromStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use sha2::{Digest, Sha256};
+use json::{FromJsonnable, JsonSchema, ToJsonnable};
+use serde::de::{Error as SerdeError, Type};
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+use serde_json::builder::ObjectBuilder;
+
+
+/// SHA-256 hash of a blob's contents, also used as its address in a [`BlobStore`](struct.BlobStore.html)
+/// and to chain [`AuditRecord`](struct.AuditRecord.html)s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobHash(pub [u8; 32]);
+
+/// Content-addressed blob storage on the local filesystem.
+///
+/// Blobs are stored under `root/<first two hex digits>/<remaining hex digits>`,
+/// with the reference count kept next to them in a `.refs` file.
+///
+/// Every [`put()`](#method.put) adds a reference to the stored blob, [`release()`](#method.release) removes one,
+/// and [`gc()`](#method.gc) deletes all blobs that are no longer referenced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobStore {
+	root: PathBuf,
+	quota: Option<u64>,
+}
+
+/// A reader over a stored blob, verifying its hash once the end is reached
+///
+/// Returns an `InvalidData` error instead of EOF if the contents don't hash to what they were stored under.
+#[derive(Debug)]
+pub struct BlobReader {
+	file: File,
+	hasher: Sha256,
+	expected: BlobHash,
+	verified: bool,
+}
+
+/// Everything that can go wrong when using a [`BlobStore`](struct.BlobStore.html)
+#[derive(Debug)]
+pub enum BlobError {
+	/// Underlying filesystem error
+	Io(io::Error),
+	/// No blob with the specified hash is stored
+	NotFound(BlobHash),
+	/// The stored data hashes to something other than its address
+	HashMismatch{
+		expected: BlobHash,
+		actual: BlobHash,
+	},
+	/// Storing the blob would make the store exceed its quota
+	QuotaExceeded{
+		quota: u64,
+		required: u64,
+	},
+}
+
+
+impl BlobHash {
+	/// Hash the specified bytes
+	pub fn of(data: &[u8]) -> BlobHash {
+		BlobHash::from_hasher(Sha256::new().chain_update(data))
+	}
+
+	/// Lowercase hexadecimal representation, as used in paths and JSON
+	pub fn to_hex(&self) -> String {
+		self.0.iter().map(|b| format!("{:02x}", b)).collect()
+	}
+
+	/// Parse a 64-digit hexadecimal string, case-insensitively
+	pub fn from_hex(hex: &str) -> Option<BlobHash> {
+		if hex.len() != 64 {
+			return None;
+		}
+
+		// from_str_radix() takes a leading sign
+		if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+			return None;
+		}
+
+		let mut bytes = [0u8; 32];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+				Ok(b) => *byte = b,
+				Err(_) => return None,
+			}
+		}
+		Some(BlobHash(bytes))
+	}
+
+
+	fn from_hasher(hasher: Sha256) -> BlobHash {
+		let mut bytes = [0u8; 32];
+		bytes.copy_from_slice(&hasher.finalize());
+		BlobHash(bytes)
+	}
+}
+
+impl fmt::Display for BlobHash {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.to_hex())
+	}
+}
+
+impl FromStr for BlobHash {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<BlobHash, ()> {
+		BlobHash::from_hex(s).ok_or(())
+	}
+}
+
+impl FromJsonnable for BlobHash {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(hex) =>
+				match BlobHash::from_hex(&hex) {
+					Some(hash) => Ok(hash),
+					None       => Err(JsonError::invalid_value("Expected 64 hex digits")),
+				},
+			_ => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for BlobHash {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_hex())
+	}
+}
+
+impl JsonSchema for BlobHash {
+	fn json_schema() -> Value {
+		ObjectBuilder::new().insert("type", "string")
+		                    .insert("pattern", "^[0-9A-Fa-f]{64}$")
+		                    .build()
+	}
+}
+
+
+impl BlobStore {
+	/// Open a store rooted at the specified directory, creating it if needed
+	pub fn open<P: AsRef<Path>>(root: P) -> Result<BlobStore, BlobError> {
+		let root = root.as_ref().to_path_buf();
+		try!(fs::create_dir_all(root.join("tmp")));
+
+		Ok(BlobStore{
+			root: root,
+			quota: None,
+		})
+	}
+
+	/// Limit the total size of stored blobs to `quota` bytes
+	pub fn with_quota(mut self, quota: u64) -> BlobStore {
+		self.quota = Some(quota);
+		self
+	}
+
+	/// The current quota, if any, in bytes
+	pub fn quota(&self) -> Option<u64> {
+		self.quota
+	}
+
+	/// Stream `data` into the store, returning the hash it's addressable by.
+	///
+	/// Storing already-present data only adds a reference to it, and doesn't count against the quota.
+	pub fn put<R: Read>(&self, mut data: R) -> Result<BlobHash, BlobError> {
+		let tmp_path = TempPath(self.root.join("tmp").join(format!("{}-{}", std::process::id(), self.tmp_counter())));
+		let (hash, size) = {
+			let mut tmp = try!(File::create(&tmp_path.0));
+			let mut hasher = Sha256::new();
+			let mut size = 0u64;
+			let mut buf = [0u8; 8192];
+
+			loop {
+				let read = match data.read(&mut buf) {
+					Ok(0) => break,
+					Ok(read) => read,
+					Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+					Err(e) => return Err(BlobError::Io(e)),
+				};
+				hasher.update(&buf[..read]);
+				size += read as u64;
+				try!(tmp.write_all(&buf[..read]));
+			}
+			try!(tmp.sync_all());
+
+			(BlobHash::from_hasher(hasher), size)
+		};
+
+		let blob_path = self.blob_path(&hash);
+		if !blob_path.exists() {
+			if let Some(quota) = self.quota {
+				let required = try!(self.usage()) + size;
+				if required > quota {
+					return Err(BlobError::QuotaExceeded{
+						quota: quota,
+						required: required,
+					});
+				}
+			}
+
+			try!(fs::create_dir_all(blob_path.parent().unwrap()));
+			try!(fs::rename(&tmp_path.0, &blob_path));
+		}
+
+		let refs = try!(self.refcount(&hash));
+		try!(self.write_refcount(&hash, refs + 1));
+		Ok(hash)
+	}
+
+	/// Convenience function for storing an in-memory buffer
+	pub fn put_bytes(&self, data: &[u8]) -> Result<BlobHash, BlobError> {
+		self.put(data)
+	}
+
+	/// Open the blob stored under `hash` for streaming, verifying its hash as it's read
+	pub fn get(&self, hash: &BlobHash) -> Result<BlobReader, BlobError> {
+		match File::open(self.blob_path(hash)) {
+			Ok(file) =>
+				Ok(BlobReader{
+					file: file,
+					hasher: Sha256::new(),
+					expected: *hash,
+					verified: false,
+				}),
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(BlobError::NotFound(*hash)),
+			Err(e)                                           => Err(BlobError::Io(e)),
+		}
+	}
+
+	/// Read the whole blob stored under `hash` into memory, verifying its hash
+	pub fn get_bytes(&self, hash: &BlobHash) -> Result<Vec<u8>, BlobError> {
+		let mut file = match File::open(self.blob_path(hash)) {
+			Ok(file) => file,
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(BlobError::NotFound(*hash)),
+			Err(e)                                           => return Err(BlobError::Io(e)),
+		};
+
+		let mut data = Vec::new();
+		try!(file.read_to_end(&mut data));
+
+		let actual = BlobHash::of(&data);
+		if actual == *hash {
+			Ok(data)
+		} else {
+			Err(BlobError::HashMismatch{
+				expected: *hash,
+				actual: actual,
+			})
+		}
+	}
+
+	/// Check whether a blob is stored under `hash`, regardless of its reference count
+	pub fn contains(&self, hash: &BlobHash) -> bool {
+		self.blob_path(hash).is_file()
+	}
+
+	/// Add a reference to an already-stored blob
+	pub fn retain(&self, hash: &BlobHash) -> Result<u64, BlobError> {
+		if !self.contains(hash) {
+			return Err(BlobError::NotFound(*hash));
+		}
+
+		let refs = try!(self.refcount(hash)) + 1;
+		try!(self.write_refcount(hash, refs));
+		Ok(refs)
+	}
+
+	/// Remove a reference to a stored blob, returning the remaining reference count.
+	///
+	/// Unreferenced blobs aren't deleted until the next [`gc()`](#method.gc).
+	pub fn release(&self, hash: &BlobHash) -> Result<u64, BlobError> {
+		if !self.contains(hash) {
+			return Err(BlobError::NotFound(*hash));
+		}
+
+		let refs = try!(self.refcount(hash)).saturating_sub(1);
+		try!(self.write_refcount(hash, refs));
+		Ok(refs)
+	}
+
+	/// Get the current reference count of the blob stored under `hash`.
+	///
+	/// A corrupt `.refs` file is an `InvalidData` error rather than zero references, so it can't get a blob collected.
+	pub fn refcount(&self, hash: &BlobHash) -> Result<u64, BlobError> {
+		let mut refs = String::new();
+		match File::open(self.refs_path(hash)) {
+			Ok(mut file) => { try!(file.read_to_string(&mut refs)); },
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+			Err(e)                                           => return Err(BlobError::Io(e)),
+		}
+
+		refs.trim().parse().map_err(|_| BlobError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("corrupt reference count of blob {}", hash))))
+	}
+
+	/// Delete all unreferenced blobs, returning the amount of bytes freed
+	pub fn gc(&self) -> Result<u64, BlobError> {
+		let mut freed = 0;
+		for (hash, size) in try!(self.blobs()) {
+			if try!(self.refcount(&hash)) == 0 {
+				try!(fs::remove_file(self.blob_path(&hash)));
+				match fs::remove_file(self.refs_path(&hash)) {
+					Ok(()) => {},
+					Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+					Err(e) => return Err(BlobError::Io(e)),
+				}
+				freed += size;
+			}
+		}
+		Ok(freed)
+	}
+
+	/// Total size of all stored blobs, in bytes
+	pub fn usage(&self) -> Result<u64, BlobError> {
+		Ok(try!(self.blobs()).into_iter().map(|(_, size)| size).sum())
+	}
+
+	/// List all stored blobs with their sizes
+	pub fn blobs(&self) -> Result<Vec<(BlobHash, u64)>, BlobError> {
+		let mut blobs = Vec::new();
+		for dir in try!(fs::read_dir(&self.root)) {
+			let dir = try!(dir);
+			let prefix = dir.file_name().to_string_lossy().into_owned();
+			if prefix.len() != 2 || !try!(dir.file_type()).is_dir() {
+				continue;
+			}
+
+			for file in try!(fs::read_dir(dir.path())) {
+				let file = try!(file);
+				if let Some(hash) = BlobHash::from_hex(&(prefix.clone() + &file.file_name().to_string_lossy())) {
+					blobs.push((hash, try!(file.metadata()).len()));
+				}
+			}
+		}
+		blobs.sort();
+		Ok(blobs)
+	}
+
+
+	fn blob_path(&self, hash: &BlobHash) -> PathBuf {
+		let hex = hash.to_hex();
+		self.root.join(&hex[..2]).join(&hex[2..])
+	}
+
+	fn refs_path(&self, hash: &BlobHash) -> PathBuf {
+		let hex = hash.to_hex();
+		self.root.join(&hex[..2]).join(format!("{}.refs", &hex[2..]))
+	}
+
+	// Written to tmp/ first and renamed into place, so the count is never seen half-written
+	fn write_refcount(&self, hash: &BlobHash, refs: u64) -> Result<(), BlobError> {
+		let tmp_path = self.root.join("tmp").join(format!("{}-{}.refs", std::process::id(), self.tmp_counter()));
+		let written = File::create(&tmp_path).and_then(|mut file| {
+			try!(write!(file, "{}", refs));
+			file.sync_all()
+		});
+		if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, self.refs_path(hash))) {
+			let _ = fs::remove_file(&tmp_path);
+			return Err(BlobError::Io(e));
+		}
+		Ok(())
+	}
+
+	fn tmp_counter(&self) -> u64 {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+		COUNTER.fetch_add(1, Ordering::SeqCst) as u64
+	}
+}
+
+
+// A file being written to tmp/, removed when dropped unless it's been renamed into place already
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.0);
+	}
+}
+
+
+impl BlobReader {
+	/// The hash the blob is expected to have
+	pub fn hash(&self) -> &BlobHash {
+		&self.expected
+	}
+}
+
+impl Read for BlobReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = try!(self.file.read(buf));
+		if read != 0 {
+			self.hasher.update(&buf[..read]);
+		} else if !buf.is_empty() && !self.verified {
+			let actual = BlobHash::from_hasher(self.hasher.clone());
+			if actual != self.expected {
+				return Err(io::Error::new(io::ErrorKind::InvalidData,
+				                          BlobError::HashMismatch{
+					                          expected: self.expected,
+					                          actual: actual,
+				                          }));
+			}
+			self.verified = true;
+		}
+		Ok(read)
+	}
+}
+
+
+impl fmt::Display for BlobError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			BlobError::Io(ref e)                              => write!(f, "I/O error: {}", e),
+			BlobError::NotFound(ref hash)                     => write!(f, "blob {} not found", hash),
+			BlobError::HashMismatch{ref expected, ref actual} => write!(f, "blob {} hashes to {}", expected, actual),
+			BlobError::QuotaExceeded{quota, required}         => write!(f, "storing blob would need {}B, quota is {}B", required, quota),
+		}
+	}
+}
+
+impl Error for BlobError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self {
+			BlobError::Io(ref e) => Some(e),
+			_                    => None,
+		}
+	}
+}
+
+impl From<io::Error> for BlobError {
+	fn from(e: io::Error) -> BlobError {
+		BlobError::Io(e)
+	}
+}
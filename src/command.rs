@@ -0,0 +1,317 @@
+use std::fmt;
+use std::error::Error;
+use ChatUser;
+use ChatMessage;
+use Payload;
+use NickChange;
+use NameError;
+use ValidatedName;
+
+
+/// A parsed line of chat input, see [`parse_command()`](fn.parse_command.html)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Command {
+	/// Not a command, just text to send
+	Say(String),
+	/// `/nick <name>`
+	Nick(ValidatedName),
+	/// `/me <action>`
+	Me(String),
+	/// `/join <#room>`
+	Join(String),
+	/// `/part [#room] [reason]`
+	Part{
+		room: Option<String>,
+		reason: Option<String>,
+	},
+	/// `/topic [#room] <topic>`
+	Topic{
+		room: Option<String>,
+		topic: String,
+	},
+	/// `/msg <user> <text>`
+	Msg{
+		to: ValidatedName,
+		text: String,
+	},
+	/// `/quit [reason]`
+	Quit(Option<String>),
+}
+
+/// Reasons chat input couldn't be parsed into a [`Command`](enum.Command.html)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CommandError {
+	/// The input was empty or all whitespace
+	Empty,
+	/// No such command, name included sans the slash
+	Unknown(String),
+	/// A required argument wasn't specified
+	MissingArgument{
+		command: &'static str,
+		argument: &'static str,
+	},
+	/// More arguments than the command takes were specified
+	TooManyArguments{
+		command: &'static str,
+	},
+	/// A quoted argument was never closed
+	UnterminatedQuote{
+		command: &'static str,
+	},
+	/// A user name argument isn't a valid name under the default [`NamePolicy`](struct.NamePolicy.html)
+	InvalidName{
+		command: &'static str,
+		error: NameError,
+	},
+}
+
+
+static USAGES: &[(&str, &str)] = &[
+	("nick", "/nick <name>"),
+	("me", "/me <action>"),
+	("join", "/join <#room>"),
+	("part", "/part [#room] [reason]"),
+	("topic", "/topic [#room] <topic>"),
+	("msg", "/msg <user> <text>"),
+	("quit", "/quit [reason]"),
+];
+
+
+/// Parse a line of chat input.
+///
+/// Input not starting with a `/` is sent as-is, and a leading `//` sends a literal `/`.
+///
+/// Names of users and rooms can be enclosed in `"double"` or `'single'` quotes to include whitespace,
+/// and backslash-escapes are honoured in them, while free-form text (reasons, topics, messages) is taken verbatim.
+///
+/// # Examples
+///
+/// ```
+/// use chattium_oxide_lib::{parse_command, Command, ValidatedName};
+///
+/// assert_eq!(parse_command("/msg \"John Doe\" hi, \"John\"").unwrap(),
+///            Command::Msg{to: ValidatedName::new("John Doe").unwrap(), text: "hi, \"John\"".to_string()});
+/// assert_eq!(parse_command("//shrug").unwrap(), Command::Say("/shrug".to_string()));
+/// ```
+pub fn parse_command(input: &str) -> Result<Command, CommandError> {
+	let input = input.trim_end_matches(['\r', '\n']);
+	if input.trim().is_empty() {
+		return Err(CommandError::Empty);
+	}
+	if input.starts_with("//") || !input.starts_with('/') {
+		return Ok(Command::Say(input.strip_prefix('/').unwrap_or(input).to_string()));
+	}
+
+	let name_end = input.find(char::is_whitespace).unwrap_or(input.len());
+	let name = input[1..name_end].to_lowercase();
+	let args = &input[name_end..];
+
+	let command = match USAGES.iter().find(|&&(cmd, _)| cmd == name) {
+		Some(&(command, _)) => command,
+		None                => return Err(CommandError::Unknown(name)),
+	};
+
+	match command {
+		"nick" => {
+			let (name, rest) = try!(required_arg(command, "name", args));
+			try!(no_more_args(command, rest));
+			Ok(Command::Nick(try!(valid_name(command, &name))))
+		},
+		"me" => Ok(Command::Me(try!(required_text(command, "action", args)))),
+		"join" => {
+			let (room, rest) = try!(required_arg(command, "#room", args));
+			try!(no_more_args(command, rest));
+			match strip_room(room) {
+				ref room if room.is_empty() => Err(CommandError::MissingArgument{command: command, argument: "#room"}),
+				room                        => Ok(Command::Join(room)),
+			}
+		},
+		"part" => {
+			let (room, rest) = try!(optional_room(command, args));
+			Ok(Command::Part{
+				room: room,
+				reason: optional_text(rest),
+			})
+		},
+		"topic" => {
+			let (room, rest) = try!(optional_room(command, args));
+			Ok(Command::Topic{
+				room: room,
+				topic: try!(required_text(command, "topic", rest)),
+			})
+		},
+		"msg" => {
+			let (to, rest) = try!(required_arg(command, "user", args));
+			Ok(Command::Msg{
+				to: try!(valid_name(command, &to)),
+				text: try!(required_text(command, "text", rest)),
+			})
+		},
+		_ => Ok(Command::Quit(optional_text(args))),
+	}
+}
+
+/// Get the usage hint for a command, by its name sans the slash
+pub fn command_usage(command: &str) -> Option<&'static str> {
+	USAGES.iter().find(|&&(cmd, _)| cmd.eq_ignore_ascii_case(command)).map(|&(_, usage)| usage)
+}
+
+
+impl Command {
+	/// Convert the command into what's sent to the server, on behalf of `sender`,
+	/// with `room` being the room the input was entered in
+	pub fn into_payload(self, sender: &ChatUser, room: &str) -> Payload {
+		match self {
+			Command::Say(text) => Payload::Message(ChatMessage::new(sender.clone(), text)),
+			Command::Me(action) => Payload::Action(ChatMessage::new(sender.clone(), action)),
+			Command::Nick(name) => {
+				Payload::Nick(NickChange{
+					old: sender.name().to_string(),
+					new: name.into_inner(),
+				})
+			},
+			Command::Join(room) => Payload::Join{room: room},
+			Command::Part{room: part_room, reason} => {
+				Payload::Part{
+					room: part_room.unwrap_or_else(|| room.to_string()),
+					reason: reason,
+				}
+			},
+			Command::Topic{room: topic_room, topic} => {
+				Payload::Topic{
+					room: topic_room.unwrap_or_else(|| room.to_string()),
+					topic: topic,
+				}
+			},
+			Command::Msg{to, text} => {
+				Payload::Private{
+					to: ChatUser::me(to),
+					message: ChatMessage::new(sender.clone(), text),
+				}
+			},
+			Command::Quit(reason) => Payload::Quit{reason: reason},
+		}
+	}
+}
+
+
+impl CommandError {
+	/// Usage hint for the command that failed to parse, if it exists
+	pub fn usage(&self) -> Option<&'static str> {
+		match *self {
+			CommandError::Empty | CommandError::Unknown(_) => None,
+			CommandError::MissingArgument{command, ..} |
+			CommandError::TooManyArguments{command} |
+			CommandError::UnterminatedQuote{command} |
+			CommandError::InvalidName{command, ..} => command_usage(command),
+		}
+	}
+}
+
+impl fmt::Display for CommandError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(match *self {
+			CommandError::Empty                              => write!(f, "nothing to send"),
+			CommandError::Unknown(ref command)               => write!(f, "unknown command /{}", command),
+			CommandError::MissingArgument{command, argument} => write!(f, "/{} is missing {}", command, argument),
+			CommandError::TooManyArguments{command}          => write!(f, "too many arguments to /{} (quote names with spaces)", command),
+			CommandError::UnterminatedQuote{command}         => write!(f, "unterminated quote in /{}", command),
+			CommandError::InvalidName{command, ref error}    => write!(f, "invalid name for /{}: {}", command, error),
+		});
+
+		match self.usage() {
+			Some(usage) => write!(f, "; usage: {}", usage),
+			None        => Ok(()),
+		}
+	}
+}
+
+impl Error for CommandError {}
+
+
+/// Split the first, possibly quoted, argument off of `args`
+fn next_arg<'a>(command: &'static str, args: &'a str) -> Result<Option<(String, &'a str)>, CommandError> {
+	let args = args.trim_start();
+	let quote = match args.chars().next() {
+		Some(c) if c == '"' || c == '\'' => Some(c),
+		Some(_)                          => None,
+		None                             => return Ok(None),
+	};
+
+	let mut arg = String::new();
+	let mut chars = args.char_indices().skip(if quote.is_some() { 1 } else { 0 });
+	while let Some((idx, c)) = chars.next() {
+		match c {
+			'\\' => {
+				match chars.next() {
+					Some((_, escaped)) => arg.push(escaped),
+					None               => arg.push('\\'),
+				}
+			},
+			c if Some(c) == quote => return Ok(Some((arg, &args[idx + 1..]))),
+			c if quote.is_none() && c.is_whitespace() => return Ok(Some((arg, &args[idx..]))),
+			c => arg.push(c),
+		}
+	}
+
+	if quote.is_some() {
+		Err(CommandError::UnterminatedQuote{command: command})
+	} else {
+		Ok(Some((arg, "")))
+	}
+}
+
+fn required_arg<'a>(command: &'static str, argument: &'static str, args: &'a str) -> Result<(String, &'a str), CommandError> {
+	match try!(next_arg(command, args)) {
+		Some((ref arg, _)) if arg.is_empty() => Err(CommandError::MissingArgument{command: command, argument: argument}),
+		Some(arg)                            => Ok(arg),
+		None                                 => Err(CommandError::MissingArgument{command: command, argument: argument}),
+	}
+}
+
+fn no_more_args(command: &'static str, rest: &str) -> Result<(), CommandError> {
+	if rest.trim().is_empty() {
+		Ok(())
+	} else {
+		Err(CommandError::TooManyArguments{command: command})
+	}
+}
+
+fn optional_room<'a>(command: &'static str, args: &'a str) -> Result<(Option<String>, &'a str), CommandError> {
+	let trimmed = args.trim_start();
+	if trimmed.starts_with('#') || trimmed.starts_with("\"#") || trimmed.starts_with("'#") {
+		let (room, rest) = try!(required_arg(command, "#room", trimmed));
+		Ok((Some(strip_room(room)), rest))
+	} else {
+		Ok((None, args))
+	}
+}
+
+fn required_text(command: &'static str, argument: &'static str, args: &str) -> Result<String, CommandError> {
+	optional_text(args).ok_or(CommandError::MissingArgument{command: command, argument: argument})
+}
+
+fn optional_text(args: &str) -> Option<String> {
+	let text = args.trim();
+	if text.is_empty() {
+		None
+	} else {
+		Some(text.to_string())
+	}
+}
+
+fn valid_name(command: &'static str, name: &str) -> Result<ValidatedName, CommandError> {
+	ValidatedName::new(name).map_err(|e| {
+		CommandError::InvalidName{
+			command: command,
+			error: e,
+		}
+	})
+}
+
+fn strip_room(room: String) -> String {
+	match room.strip_prefix('#') {
+		Some(stripped) => stripped.to_string(),
+		None           => room,
+	}
+}
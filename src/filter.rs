@@ -0,0 +1,338 @@
+use std::collections::{HashMap, VecDeque};
+use ChatMessage;
+use TimestampBackend;
+use fold_name;
+use mention::{parse_spans, SpanKind};
+use time::{Duration, Tm};
+
+
+/// What a [`MessageFilter`](trait.MessageFilter.html) decided to do with a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FilterVerdict {
+	/// Let the message through unchanged
+	Accept,
+	/// Refuse the message, for the given reason
+	Reject(String),
+	/// Replace the message's contents, keeping everything else
+	Rewrite(String),
+	/// Let the message through, but mark it for review by a moderator, for the given reason
+	Flag(String),
+}
+
+/// What a [`FilterChain`](struct.FilterChain.html) decided to do with a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterOutcome {
+	/// Let the message, possibly rewritten, through
+	Accepted(ChatMessage),
+	/// Let the message, possibly rewritten, through, but mark it for review for the given reasons
+	Flagged(ChatMessage, Vec<String>),
+	/// Refuse the message, for the given reason
+	Rejected(String),
+}
+
+/// A single step of spam and content filtering.
+///
+/// Filters take `&mut self`, so they can keep track of what they've seen.
+/// Any `FnMut(&ChatMessage) -> FilterVerdict` closure is a filter.
+pub trait MessageFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict;
+}
+
+/// Filters run one after another over incoming messages.
+///
+/// Each filter sees the message as rewritten by the filters before it.
+/// The first rejection stops the chain, flags accumulate.
+#[derive(Default)]
+pub struct FilterChain {
+	filters: Vec<Box<dyn MessageFilter>>,
+}
+
+/// What [`WordListFilter`](struct.WordListFilter.html) does with messages containing listed words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WordAction {
+	Reject,
+	Flag,
+	/// Replace the offending words with asterisks
+	Censor,
+}
+
+/// Acts on messages containing any of the listed words.
+///
+/// Words are compared after case folding, undoing leet-speak (`"h4x0r"` is `"haxor"`),
+/// and dropping punctuation.
+/// Runs of three or more of the same letter are stretched out, and match the letter once or twice
+/// (`"spaaam!"` is `"spam"`, `"passss"` is `"pass"` and `"pas"`, but `"pass"` is just `"pass"`).
+/// Messages are split into words on whitespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordListFilter {
+	/// The listed words' letters with runs squeezed to one, to the lengths of the runs in each listed word
+	words: HashMap<String, Vec<Vec<usize>>>,
+	action: WordAction,
+}
+
+/// Rejects a user sending the same message again too many times in a row within a time window.
+///
+/// Messages are compared after case folding and whitespace normalization, users by folded name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatFilter {
+	/// Maximal amount of identical messages within `within`
+	pub max_repeats: usize,
+	pub within: Duration,
+	/// Folded user names to their recent identical messages, normalized, and when they were posted
+	recent: HashMap<String, VecDeque<(String, Tm)>>,
+}
+
+/// Acts on messages that are mostly in capital letters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapsFilter {
+	/// Messages with fewer letters than this are never acted on, so `"OK"` and `"LOL"` get through
+	pub min_letters: usize,
+	/// Maximal percentage of letters that can be uppercase
+	pub max_percent: u8,
+	/// Whether to lowercase offending messages instead of rejecting them
+	pub lowercase: bool,
+}
+
+/// Rejects messages containing more links than allowed, see [`parse_spans()`](fn.parse_spans.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LinkFilter {
+	pub max_links: usize,
+}
+
+
+impl<F: FnMut(&ChatMessage) -> FilterVerdict> MessageFilter for F {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		self(message)
+	}
+}
+
+
+impl FilterChain {
+	/// Create a chain accepting everything
+	pub fn new() -> FilterChain {
+		FilterChain::default()
+	}
+
+	/// Add `filter` to the end of the chain
+	pub fn with<F: MessageFilter + 'static>(mut self, filter: F) -> FilterChain {
+		self.push(filter);
+		self
+	}
+
+	/// Add `filter` to the end of the chain
+	pub fn push<F: MessageFilter + 'static>(&mut self, filter: F) {
+		self.filters.push(Box::new(filter));
+	}
+
+	/// Run `message` through all filters.
+	///
+	/// Rewrites only ever change the message's contents, so `id`, `time_posted` and `sender` stay intact.
+	pub fn run(&mut self, mut message: ChatMessage) -> FilterOutcome {
+		let mut flags = Vec::new();
+		for filter in &mut self.filters {
+			match filter.filter(&message) {
+				FilterVerdict::Accept            => {},
+				FilterVerdict::Reject(reason)    => return FilterOutcome::Rejected(reason),
+				FilterVerdict::Rewrite(contents) => message.value = contents,
+				FilterVerdict::Flag(reason)      => flags.push(reason),
+			}
+		}
+
+		if flags.is_empty() {
+			FilterOutcome::Accepted(message)
+		} else {
+			FilterOutcome::Flagged(message, flags)
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.filters.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.filters.is_empty()
+	}
+}
+
+impl MessageFilter for FilterChain {
+	/// A chain is itself a filter, so chains can be nested
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		match self.run(message.clone()) {
+			FilterOutcome::Rejected(reason) => FilterVerdict::Reject(reason),
+			FilterOutcome::Flagged(_, flags) => FilterVerdict::Flag(flags.join("; ")),
+			FilterOutcome::Accepted(ref filtered) if filtered.value != message.value => FilterVerdict::Rewrite(filtered.value.clone()),
+			FilterOutcome::Accepted(_) => FilterVerdict::Accept,
+		}
+	}
+}
+
+
+impl WordListFilter {
+	/// Act on messages containing any of `words` by `action`
+	pub fn new<S: AsRef<str>>(words: &[S], action: WordAction) -> WordListFilter {
+		let mut listed = HashMap::<String, Vec<Vec<usize>>>::new();
+		for word in words {
+			let (letters, runs) = normalize_word(word.as_ref());
+			if !letters.is_empty() {
+				// Listed words are spelled, not stretched out
+				listed.entry(letters).or_default().push(runs.into_iter().map(|run| run.min(2)).collect());
+			}
+		}
+		WordListFilter{
+			words: listed,
+			action: action,
+		}
+	}
+
+	/// Check whether `word` is listed
+	pub fn is_listed(&self, word: &str) -> bool {
+		let (letters, runs) = normalize_word(word);
+		match self.words.get(&letters) {
+			Some(listed) => listed.iter().any(|listed| listed.iter().zip(&runs).all(|(&listed, &run)| run == listed || run > 2)),
+			None         => false,
+		}
+	}
+}
+
+impl MessageFilter for WordListFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		let listed = message.value.split_whitespace().filter(|word| self.is_listed(word)).count();
+		if listed == 0 {
+			return FilterVerdict::Accept;
+		}
+
+		let reason = format!("contains {} listed word{}", listed, if listed == 1 {""} else {"s"});
+		match self.action {
+			WordAction::Reject => FilterVerdict::Reject(reason),
+			WordAction::Flag   => FilterVerdict::Flag(reason),
+			WordAction::Censor => {
+				let mut censored = String::with_capacity(message.value.len());
+				let mut rest = &message.value[..];
+				while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+					censored.push_str(&rest[..start]);
+					rest = &rest[start..];
+					let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+					let word = &rest[..end];
+					if self.is_listed(word) {
+						censored.extend(word.chars().map(|_| '*'));
+					} else {
+						censored.push_str(word);
+					}
+					rest = &rest[end..];
+				}
+				censored.push_str(rest);
+				FilterVerdict::Rewrite(censored)
+			},
+		}
+	}
+}
+
+impl RepeatFilter {
+	/// Allow at most `max_repeats` identical messages from a user within `within`
+	pub fn new(max_repeats: usize, within: Duration) -> RepeatFilter {
+		RepeatFilter{
+			max_repeats: max_repeats,
+			within: within,
+			recent: HashMap::new(),
+		}
+	}
+}
+
+impl MessageFilter for RepeatFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		// Forget everything outside the window, including users who've stopped posting
+		let since = (message.time_posted.to_tm() - self.within).to_timespec();
+		self.recent.retain(|_, recent| {
+			while recent.front().map(|(_, posted)| posted.to_timespec() <= since).unwrap_or(false) {
+				recent.pop_front();
+			}
+			!recent.is_empty()
+		});
+
+		let recent = self.recent.entry(fold_name(message.sender.name())).or_default();
+
+		let contents = normalize_repeat(&message.value);
+		if recent.back().map(|(last, _)| *last != contents).unwrap_or(false) {
+			recent.clear();
+		}
+		if recent.len() >= self.max_repeats {
+			return FilterVerdict::Reject(format!("same message sent more than {} times in a row", self.max_repeats));
+		}
+
+		recent.push_back((contents, message.time_posted.to_tm()));
+		FilterVerdict::Accept
+	}
+}
+
+impl Default for CapsFilter {
+	/// Lowercase messages of at least 8 letters, more than 70% of which are uppercase
+	fn default() -> CapsFilter {
+		CapsFilter{
+			min_letters: 8,
+			max_percent: 70,
+			lowercase: true,
+		}
+	}
+}
+
+impl MessageFilter for CapsFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		let letters = message.value.chars().filter(|c| c.is_alphabetic()).count();
+		let upper = message.value.chars().filter(|c| c.is_uppercase()).count();
+
+		if letters < self.min_letters || upper * 100 <= letters * self.max_percent as usize {
+			FilterVerdict::Accept
+		} else if self.lowercase {
+			FilterVerdict::Rewrite(message.value.to_lowercase())
+		} else {
+			FilterVerdict::Reject(format!("{}% of the message is in capital letters", upper * 100 / letters))
+		}
+	}
+}
+
+impl MessageFilter for LinkFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		let links = parse_spans(&message.value).into_iter().filter(|span| span.kind == SpanKind::Url).count();
+		if links > self.max_links {
+			FilterVerdict::Reject(format!("message has {} links, can have at most {}", links, self.max_links))
+		} else {
+			FilterVerdict::Accept
+		}
+	}
+}
+
+
+// The word's letters with runs of the same letter squeezed to one, and the length of each run
+fn normalize_word(word: &str) -> (String, Vec<usize>) {
+	let mut normalized = String::with_capacity(word.len());
+	let mut runs = Vec::new();
+	for c in fold_name(word).trim_matches(|c: char| !c.is_alphanumeric() && c != '$' && c != '@').chars() {
+		let c =
+			match c {
+				'0'       => 'o',
+				'1' | '!' => 'i',
+				'3'       => 'e',
+				'4' | '@' => 'a',
+				'5' | '$' => 's',
+				'7' | '+' => 't',
+				'8'       => 'b',
+				'9'       => 'g',
+				c         => c,
+			};
+		if !c.is_alphabetic() {
+			continue;
+		}
+
+		if normalized.ends_with(c) {
+			*runs.last_mut().unwrap() += 1;
+		} else {
+			normalized.push(c);
+			runs.push(1);
+		}
+	}
+	(normalized, runs)
+}
+
+fn normalize_repeat(text: &str) -> String {
+	fold_name(&text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
@@ -0,0 +1,166 @@
+//! Adapters between [`FromJsonnable`](../trait.FromJsonnable.html)/[`ToJsonnable`](../trait.ToJsonnable.html)
+//! and current `serde`'s `Serialize`/`Deserialize`, enabled by the `modern-serde` feature.
+//!
+//! All protocol types also implement `Serialize` and `Deserialize` directly, producing the exact same JSON as
+//! [`to_json_string()`](../trait.ToJsonnable.html#method.to_json_string), so they can be embedded in structs using
+//! `#[derive(Serialize, Deserialize)]`. Other `Jsonnable` types can be embedded via [`Modern`](struct.Modern.html)
+//! or `#[serde(with = "chattium_oxide_lib::json::bridge")]`, and `serde` types used where a `Jsonnable` one is expected
+//! via [`Legacy`](struct.Legacy.html).
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate serde_json1;
+//! extern crate chattium_oxide_lib;
+//!
+//! use chattium_oxide_lib::{ChatMessage, ChatUser, ValidatedName};
+//! use chattium_oxide_lib::json::ToJsonnable;
+//!
+//! fn main() {
+//! 	let message = ChatMessage::new(ChatUser::me(ValidatedName::new("Bridger").unwrap()), "hello".to_string());
+//! 	assert_eq!(serde_json1::to_string(&message).unwrap(), message.to_json_string().unwrap());
+//! }
+//! ```
+
+use std::ops::{Deref, DerefMut};
+use json::{FromJsonnable, ToJsonnable};
+use serde::de::Error;
+use serde1::{Serialize, Serializer, Deserialize, Deserializer};
+use serde1::de::{DeserializeOwned, Error as DeError};
+use serde_json1;
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+use {ChatMessage, ChatUser, Payload, NickChange, BlobHash, Moderation, Target, AddressRange, ModerationList, Permission, Role, AuditKind,
+     AuditTarget, AuditEvent, AuditRecord};
+
+
+/// Makes any `Jsonnable` type `Serialize` and `Deserialize`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Modern<T>(pub T);
+
+/// Makes any `Serialize` and `Deserialize` type `Jsonnable`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Legacy<T>(pub T);
+
+
+/// Convert a `serde_json` 0.8 value to a current `serde_json` one
+pub fn to_modern(json: Value) -> serde_json1::Value {
+	match json {
+		Value::Null         => serde_json1::Value::Null,
+		Value::Bool(b)      => serde_json1::Value::Bool(b),
+		Value::I64(i)       => serde_json1::Value::from(i),
+		Value::U64(u)       => serde_json1::Value::from(u),
+		Value::F64(f)       => serde_json1::Number::from_f64(f).map(serde_json1::Value::Number).unwrap_or(serde_json1::Value::Null),
+		Value::String(s)    => serde_json1::Value::String(s),
+		Value::Array(arr)   => serde_json1::Value::Array(arr.into_iter().map(to_modern).collect()),
+		Value::Object(map)  => serde_json1::Value::Object(map.into_iter().map(|(k, v)| (k, to_modern(v))).collect()),
+	}
+}
+
+/// Convert a current `serde_json` value to a `serde_json` 0.8 one
+pub fn from_modern(json: serde_json1::Value) -> Value {
+	match json {
+		serde_json1::Value::Null        => Value::Null,
+		serde_json1::Value::Bool(b)     => Value::Bool(b),
+		serde_json1::Value::Number(n)   =>
+			if let Some(u) = n.as_u64() {
+				Value::U64(u)
+			} else if let Some(i) = n.as_i64() {
+				Value::I64(i)
+			} else {
+				Value::F64(n.as_f64().unwrap_or(0f64))
+			},
+		serde_json1::Value::String(s)   => Value::String(s),
+		serde_json1::Value::Array(arr)  => Value::Array(arr.into_iter().map(from_modern).collect()),
+		serde_json1::Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (k, from_modern(v))).collect()),
+	}
+}
+
+
+/// Serialize a `ToJsonnable` value, for use with `#[serde(serialize_with)]` or `#[serde(with)]`
+pub fn serialize<T: ToJsonnable, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+	to_modern(value.to_json()).serialize(serializer)
+}
+
+/// Deserialize a `FromJsonnable` value, for use with `#[serde(deserialize_with)]` or `#[serde(with)]`
+pub fn deserialize<'de, T: FromJsonnable, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+	let json = try!(serde_json1::Value::deserialize(deserializer));
+	T::from_json(from_modern(json)).map_err(D::Error::custom)
+}
+
+
+impl<T> Deref for Modern<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T> DerefMut for Modern<T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.0
+	}
+}
+
+impl<T: ToJsonnable> Serialize for Modern<T> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serialize(&self.0, serializer)
+	}
+}
+
+impl<'de, T: FromJsonnable> Deserialize<'de> for Modern<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserialize(deserializer).map(Modern)
+	}
+}
+
+
+impl<T> Deref for Legacy<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T> DerefMut for Legacy<T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.0
+	}
+}
+
+impl<T: DeserializeOwned> FromJsonnable for Legacy<T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		serde_json1::from_value(to_modern(json)).map(Legacy).map_err(|e| JsonError::custom(e.to_string()))
+	}
+}
+
+impl<T: Serialize> ToJsonnable for Legacy<T> {
+	/// Values that can't be represented in JSON, like maps with non-string keys, serialize to `null`
+	fn to_json(&self) -> Value {
+		serde_json1::to_value(&self.0).map(from_modern).unwrap_or(Value::Null)
+	}
+}
+
+
+macro_rules! modern_serde_via_jsonnable {
+	($($t:ty),*) => {
+		$(
+			impl Serialize for $t {
+				fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+					serialize(self, serializer)
+				}
+			}
+
+			impl<'de> Deserialize<'de> for $t {
+				fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+					deserialize(deserializer)
+				}
+			}
+		)*
+	}
+}
+
+modern_serde_via_jsonnable!(ChatMessage, ChatUser, Payload, NickChange, BlobHash, Moderation, Target, AddressRange, ModerationList, Permission, Role,
+                            AuditKind, AuditTarget, AuditEvent, AuditRecord);
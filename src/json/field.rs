@@ -0,0 +1,100 @@
+use std::marker::PhantomData;
+use json::{FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use serde::de::{Error, Type};
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+
+/// A field of a JSON object: its name, whether it may be missing, and the type it decodes to.
+///
+/// Each field is declared once, as a constant, and used both to decode and encode it.
+/// The [`spec()`](#method.spec)s of a type's fields make up its field table, from which its
+/// [schema](fn.fields_schema.html) and known fields for [`extensions()`](fn.extensions.html) are derived.
+pub struct Field<T> {
+	pub name: &'static str,
+	/// Whether the field must be present, otherwise it decodes as if it were `null`
+	pub required: bool,
+	_type: PhantomData<fn() -> T>,
+}
+
+/// An entry in a type's field table, see [`Field`](struct.Field.html)
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSpec {
+	pub name: &'static str,
+	pub required: bool,
+	pub schema: fn() -> Value,
+}
+
+
+impl<T> Field<T> {
+	/// A field that must be present
+	pub const fn required(name: &'static str) -> Field<T> {
+		Field{
+			name: name,
+			required: true,
+			_type: PhantomData,
+		}
+	}
+
+	/// The field's table entry, with a schema more specific than `T`'s
+	pub const fn spec_with(&self, schema: fn() -> Value) -> FieldSpec {
+		FieldSpec{
+			name: self.name,
+			required: self.required,
+			schema: schema,
+		}
+	}
+}
+
+impl<T> Field<Option<T>> {
+	/// A field that may be missing, the same as `null`
+	pub const fn optional(name: &'static str) -> Field<Option<T>> {
+		Field{
+			name: name,
+			required: false,
+			_type: PhantomData,
+		}
+	}
+}
+
+impl<T: JsonSchema> Field<T> {
+	/// The field's table entry
+	pub const fn spec(&self) -> FieldSpec {
+		self.spec_with(T::json_schema)
+	}
+}
+
+impl<T: FromJsonnable> Field<T> {
+	/// Decode the field from `map` with `options`
+	pub fn decode(&self, map: &Map<String, Value>, options: &JsonOptions) -> Result<T, JsonError> {
+		match map.get(self.name) {
+			Some(value)            => T::from_json_with(value.clone(), options),
+			None if !self.required => T::from_json_with(Value::Null, options),
+			None                   => Err(JsonError::missing_field(self.name)),
+		}
+	}
+}
+
+impl<T: ToJsonnable> Field<T> {
+	/// Encode `value` as the field into `map` with `options`
+	pub fn encode(&self, map: &mut Map<String, Value>, value: &T, options: &JsonOptions) {
+		map.insert(self.name.to_string(), value.to_json_with(options));
+	}
+}
+
+
+/// Deserialize the required field `key` of `map` with `options`
+pub(crate) fn field<T: FromJsonnable>(map: &Map<String, Value>, key: &'static str, options: &JsonOptions) -> Result<T, JsonError> {
+	match map.get(key) {
+		Some(value) => T::from_json_with(value.clone(), options),
+		None        => Err(JsonError::missing_field(key)),
+	}
+}
+
+/// Get the required string field `key` of `map`
+pub(crate) fn string_field(map: &Map<String, Value>, key: &'static str) -> Result<String, JsonError> {
+	match map.get(key) {
+		Some(&Value::String(ref value)) => Ok(value.clone()),
+		Some(_)                         => Err(JsonError::invalid_type(Type::String)),
+		None                            => Err(JsonError::missing_field(key)),
+	}
+}
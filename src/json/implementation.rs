@@ -1,5 +1,15 @@
-use time::{at_utc, Tm, Timespec};
-use json::{FromJsonnable, ToJsonnable};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::str::FromStr;
+use std::hash::Hash;
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration as StdDuration;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use time::{at_utc, Duration, Tm};
+use json::{field, FromJsonnable, JsonOptions, ToJsonnable};
+use json::integer::integer_from_json;
+use json::time_format::{checked_timespec, decode_time, TimeRangeError};
 use serde::de::{Error, Type};
 use serde_json::value::Value;
 use serde_json::error::Error as JsonError;
@@ -7,7 +17,7 @@ use serde_json::builder::ObjectBuilder;
 
 
 impl FromJsonnable for Tm {
-	// Deserialize via Timespec
+	// Deserialize via Timespec, accepting any TimeFormat
 	fn from_json(json: Value) -> Result<Self, JsonError> {
 		match json {
 			Value::Object(map) => {
@@ -16,7 +26,7 @@ impl FromJsonnable for Tm {
 						Some(sec) =>
 							match sec {
 								&Value::I64(sec) => sec,
-								&Value::U64(sec) => sec as i64,  // The types get weird here
+								&Value::U64(sec) => try!(i64::try_from(sec).map_err(|_| TimeRangeError::Seconds(i64::MAX))),
 								_                => return Err(JsonError::invalid_type(Type::I64)),
 							},
 						None => return Err(JsonError::missing_field("Missing \"sec\"")),
@@ -25,37 +35,42 @@ impl FromJsonnable for Tm {
 					match map.get("nsec") {
 						Some(nsec) =>
 							match nsec {
-								&Value::I64(nsec) => nsec as i32,
-								&Value::U64(nsec) => nsec as i32,
+								&Value::I64(nsec) => nsec,
+								&Value::U64(nsec) => try!(i64::try_from(nsec).map_err(|_| TimeRangeError::Nanoseconds(i64::MAX))),
 								_                 => return Err(JsonError::invalid_type(Type::I32)),
 							},
 						None => return Err(JsonError::missing_field("Missing \"nsec\"")),
 					};
 
-				Ok(at_utc(Timespec::new(sec, nsec)))
+				Ok(at_utc(try!(checked_timespec(sec, nsec))))
 			},
-			_ => Err(JsonError::invalid_type(Type::Struct)),
+			json => Ok(at_utc(try!(decode_time(json)))),
 		}
 	}
 }
 
 impl ToJsonnable for Tm {
-	// Serialize via Timespec
 	fn to_json(&self) -> Value {
-		let spec = self.to_timespec();
-		ObjectBuilder::new().insert("sec", &spec.sec)
-		                    .insert("nsec", &spec.nsec)
-		                    .build()
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	// Serialize via Timespec, in the options' TimeFormat
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		options.time_format().encode(self)
 	}
 }
 
 impl<T: FromJsonnable> FromJsonnable for Vec<T> {
 	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
 		match json {
 			Value::Array(arr) => {
 				let mut elems: Vec<T> = Vec::with_capacity(arr.len());
 				for elem in arr {
-					match T::from_json(elem) {
+					match T::from_json_with(elem, options) {
 						Ok(elem) => elems.push(elem),
 						Err(e)   => return Err(e),
 					}
@@ -69,10 +84,365 @@ impl<T: FromJsonnable> FromJsonnable for Vec<T> {
 
 impl<T: ToJsonnable> ToJsonnable for Vec<T> {
 	fn to_json(&self) -> Value {
-		Value::Array(self.iter().map(|ref elem| elem.to_json()).collect())
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Array(self.iter().map(|ref elem| elem.to_json_with(options)).collect())
 	}
 }
 
+impl<T: FromJsonnable, const N: usize> FromJsonnable for [T; N] {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		let elems: Vec<T> = try!(Vec::from_json_with(json, options));
+		<[T; N]>::try_from(elems).map_err(|elems| JsonError::invalid_length(elems.len()))
+	}
+}
+
+impl<T: ToJsonnable, const N: usize> ToJsonnable for [T; N] {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Array(self.iter().map(|elem| elem.to_json_with(options)).collect())
+	}
+}
+
+impl<T: FromJsonnable + Eq + Hash> FromJsonnable for HashSet<T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		Vec::from_json_with(json, options).map(|elems| elems.into_iter().collect())
+	}
+}
+
+impl<T: ToJsonnable + Eq + Hash> ToJsonnable for HashSet<T> {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Array(self.iter().map(|elem| elem.to_json_with(options)).collect())
+	}
+}
+
+impl<T: FromJsonnable + Ord> FromJsonnable for BTreeSet<T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		Vec::from_json_with(json, options).map(|elems| elems.into_iter().collect())
+	}
+}
+
+impl<T: ToJsonnable + Ord> ToJsonnable for BTreeSet<T> {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Array(self.iter().map(|elem| elem.to_json_with(options)).collect())
+	}
+}
+
+impl<T: FromJsonnable> FromJsonnable for HashMap<String, T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		BTreeMap::from_json_with(json, options).map(|map| map.into_iter().collect())
+	}
+}
+
+impl<T: ToJsonnable> ToJsonnable for HashMap<String, T> {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Object(self.iter().map(|(key, value)| (key.clone(), value.to_json_with(options))).collect())
+	}
+}
+
+impl<T: FromJsonnable> FromJsonnable for BTreeMap<String, T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				let mut elems = BTreeMap::new();
+				for (key, value) in map {
+					elems.insert(key, try!(T::from_json_with(value, options)));
+				}
+				Ok(elems)
+			},
+			_ => Err(JsonError::invalid_type(Type::Map)),
+		}
+	}
+}
+
+impl<T: ToJsonnable> ToJsonnable for BTreeMap<String, T> {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		Value::Object(self.iter().map(|(key, value)| (key.clone(), value.to_json_with(options))).collect())
+	}
+}
+
+impl<T: FromJsonnable> FromJsonnable for Option<T> {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	// null is None, anything else is Some
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Null => Ok(None),
+			json        => T::from_json_with(json, options).map(Some),
+		}
+	}
+}
+
+impl<T: ToJsonnable> ToJsonnable for Option<T> {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		match *self {
+			Some(ref value) => value.to_json_with(options),
+			None            => Value::Null,
+		}
+	}
+}
+
+impl FromJsonnable for String {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(s) => Ok(s),
+			_                => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for String {
+	fn to_json(&self) -> Value {
+		Value::String(self.clone())
+	}
+}
+
+impl FromJsonnable for Value {
+	// Kept as-is
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Ok(json)
+	}
+}
+
+impl ToJsonnable for Value {
+	fn to_json(&self) -> Value {
+		self.clone()
+	}
+}
+
+impl FromJsonnable for char {
+	// A single-character string
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(s) => {
+				let mut chars = s.chars();
+				match (chars.next(), chars.next()) {
+					(Some(c), None) => Ok(c),
+					_               => Err(JsonError::invalid_length(s.chars().count())),
+				}
+			},
+			_ => Err(JsonError::invalid_type(Type::Char)),
+		}
+	}
+}
+
+impl ToJsonnable for char {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+impl FromJsonnable for bool {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::Bool(b) => Ok(b),
+			_              => Err(JsonError::invalid_type(Type::Bool)),
+		}
+	}
+}
+
+impl ToJsonnable for bool {
+	fn to_json(&self) -> Value {
+		Value::Bool(*self)
+	}
+}
+
+impl FromJsonnable for Duration {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	// Deserialize like a legacy Tm, nsec is always positive
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				let sec: i64 = try!(field(&map, "sec", options));
+				let nsec = try!(checked_nanoseconds(try!(field(&map, "nsec", options))));
+
+				// Duration::seconds() panics out of range, so go through the smallest in-range whole second
+				let (whole, nsec) = if sec < i64::MIN / 1000 {(sec + 1, nsec as i64 - 1_000_000_000)} else {(sec, nsec as i64)};
+				if !(i64::MIN / 1000..=i64::MAX / 1000).contains(&whole) {
+					return Err(TimeRangeError::Seconds(sec).into());
+				}
+				Duration::seconds(whole).checked_add(&Duration::nanoseconds(nsec)).ok_or_else(|| TimeRangeError::Seconds(sec).into())
+			},
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for Duration {
+	// Serialize like a legacy Tm: whole seconds, rounded down, and the remaining nanoseconds
+	fn to_json(&self) -> Value {
+		let sec = self.num_seconds();
+		let nsec = (*self - Duration::seconds(sec)).num_nanoseconds().unwrap_or(0);
+		let (sec, nsec) = if nsec < 0 {(sec - 1, nsec + 1_000_000_000)} else {(sec, nsec)};
+		ObjectBuilder::new().insert("sec", &sec)
+		                    .insert("nsec", &nsec)
+		                    .build()
+	}
+}
+
+impl FromJsonnable for StdDuration {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	// Deserialize like a legacy Tm, refusing negative durations
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				let sec: u64 = try!(field(&map, "sec", options));
+				let nsec = try!(checked_nanoseconds(try!(field(&map, "nsec", options))));
+				Ok(StdDuration::new(sec, nsec))
+			},
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for StdDuration {
+	fn to_json(&self) -> Value {
+		ObjectBuilder::new().insert("sec", &self.as_secs())
+		                    .insert("nsec", &self.subsec_nanos())
+		                    .build()
+	}
+}
+
+impl FromJsonnable for IpAddr {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		parse_string(json, "Expected an IP address")
+	}
+}
+
+impl ToJsonnable for IpAddr {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+impl FromJsonnable for SocketAddr {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		parse_string(json, "Expected a socket address")
+	}
+}
+
+impl ToJsonnable for SocketAddr {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+
+macro_rules! pointer_json {
+	($($p:ident)+) => {
+		$(
+			impl<T: FromJsonnable> FromJsonnable for $p<T> {
+				fn from_json(json: Value) -> Result<Self, JsonError> {
+					T::from_json(json).map($p::new)
+				}
+
+				fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+					T::from_json_with(json, options).map($p::new)
+				}
+			}
+
+			impl<T: ToJsonnable> ToJsonnable for $p<T> {
+				fn to_json(&self) -> Value {
+					(**self).to_json()
+				}
+
+				fn to_json_with(&self, options: &JsonOptions) -> Value {
+					(**self).to_json_with(options)
+				}
+			}
+		)+
+	};
+}
+
+macro_rules! tuple_json {
+	($($len:expr => ($($t:ident $idx:tt)+))+) => {
+		$(
+			impl<$($t: FromJsonnable),+> FromJsonnable for ($($t,)+) {
+				fn from_json(json: Value) -> Result<Self, JsonError> {
+					Self::from_json_with(json, &JsonOptions::default())
+				}
+
+				// A JSON array of exactly the tuple's length
+				fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+					match json {
+						Value::Array(arr) => {
+							if arr.len() != $len {
+								return Err(JsonError::invalid_length(arr.len()));
+							}
+							let mut elems = arr.into_iter();
+							Ok(($(try!($t::from_json_with(elems.next().unwrap(), options))),+,))
+						},
+						_ => Err(JsonError::invalid_type(Type::Tuple)),
+					}
+				}
+			}
+
+			impl<$($t: ToJsonnable),+> ToJsonnable for ($($t,)+) {
+				fn to_json(&self) -> Value {
+					self.to_json_with(&JsonOptions::default())
+				}
+
+				fn to_json_with(&self, options: &JsonOptions) -> Value {
+					Value::Array(vec![$(self.$idx.to_json_with(options)),+])
+				}
+			}
+		)+
+	};
+}
+
 
 macro_rules! primitive_to_json {
 	($t:ty, $v:ident, $d:ty) => {
@@ -99,6 +469,21 @@ macro_rules! primitive_from_json {
 	};
 }
 
+macro_rules! integer_from_json {
+	($t:ty, $expected:ident) => {
+		impl FromJsonnable for $t {
+			fn from_json(json: Value) -> Result<Self, JsonError> {
+				Self::from_json_with(json, &JsonOptions::default())
+			}
+
+			// Either signedness, checked against $t's range
+			fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+				integer_from_json(json, Type::$expected, stringify!($t), options)
+			}
+		}
+	};
+}
+
 primitive_to_json!(i8,  I64, i64);
 primitive_to_json!(i16, I64, i64);
 primitive_to_json!(i32, I64, i64);
@@ -113,15 +498,49 @@ primitive_to_json!(f32, F64, f64);
 primitive_to_json!(f64, F64, f64);
 
 
-primitive_from_json!(i8,  I64, I64 U64);  // Non-signed numbers are interpreted as unsigned
-primitive_from_json!(i16, I64, I64 U64);
-primitive_from_json!(i32, I64, I64 U64);
-primitive_from_json!(i64, I64, I64 U64);
+integer_from_json!(i8,  I8);
+integer_from_json!(i16, I16);
+integer_from_json!(i32, I32);
+integer_from_json!(i64, I64);
 
-primitive_from_json!(u8,  U64, U64);
-primitive_from_json!(u16, U64, U64);
-primitive_from_json!(u32, U64, U64);
-primitive_from_json!(u64, U64, U64);
+integer_from_json!(u8,  U8);
+integer_from_json!(u16, U16);
+integer_from_json!(u32, U32);
+integer_from_json!(u64, U64);
 
 primitive_from_json!(f32, F64, F64);
 primitive_from_json!(f64, F64, F64);
+
+
+pointer_json!(Box Rc Arc);
+
+tuple_json! {
+	1  => (A 0)
+	2  => (A 0 B 1)
+	3  => (A 0 B 1 C 2)
+	4  => (A 0 B 1 C 2 D 3)
+	5  => (A 0 B 1 C 2 D 3 E 4)
+	6  => (A 0 B 1 C 2 D 3 E 4 F 5)
+	7  => (A 0 B 1 C 2 D 3 E 4 F 5 G 6)
+	8  => (A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7)
+	9  => (A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8)
+	10 => (A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9)
+	11 => (A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10)
+	12 => (A 0 B 1 C 2 D 3 E 4 F 5 G 6 H 7 I 8 J 9 K 10 L 11)
+}
+
+
+fn parse_string<T: FromStr>(json: Value, expected: &str) -> Result<T, JsonError> {
+	match json {
+		Value::String(s) => s.parse().map_err(|_| JsonError::invalid_value(expected)),
+		_                => Err(JsonError::invalid_type(Type::String)),
+	}
+}
+
+fn checked_nanoseconds(nsec: u64) -> Result<u32, JsonError> {
+	if nsec < 1_000_000_000 {
+		Ok(nsec as u32)
+	} else {
+		Err(TimeRangeError::Nanoseconds(i64::try_from(nsec).unwrap_or(i64::MAX)).into())
+	}
+}
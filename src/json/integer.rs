@@ -0,0 +1,29 @@
+use std::convert::TryFrom;
+use json::JsonOptions;
+use serde::de::{Error, Type};
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+
+
+/// Decode an integer of type `T`, refusing values out of its range
+pub(crate) fn integer_from_json<T: TryFrom<i64> + TryFrom<u64>>(json: Value, expected: Type, name: &str, options: &JsonOptions) -> Result<T, JsonError> {
+	match json {
+		Value::I64(value) => T::try_from(value).map_err(|_| out_of_range(value, name)),
+		Value::U64(value) => T::try_from(value).map_err(|_| out_of_range(value, name)),
+		Value::F64(value) if options.lenient_integers() && value.is_finite() && value.fract() == 0f64 => {
+			// 2^63 and 2^64 are exact in f64, so these casts never saturate
+			if (-9223372036854775808f64..9223372036854775808f64).contains(&value) {
+				T::try_from(value as i64).map_err(|_| out_of_range(value, name))
+			} else if (0f64..18446744073709551616f64).contains(&value) {
+				T::try_from(value as u64).map_err(|_| out_of_range(value, name))
+			} else {
+				Err(out_of_range(value, name))
+			}
+		},
+		_ => Err(JsonError::invalid_type(expected)),
+	}
+}
+
+fn out_of_range<V: ToString>(value: V, name: &str) -> JsonError {
+	JsonError::invalid_value(&format!("{} out of range for {}", value.to_string(), name))
+}
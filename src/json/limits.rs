@@ -0,0 +1,253 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::error::Error as StdError;
+use serde_json::error::Error as JsonError;
+
+
+/// Limits on JSON input, enforced on the raw bytes before they're parsed, so hostile input is refused
+/// before it can exhaust the stack or memory.
+///
+/// Applied by [`from_json_string_with()`](trait.FromJsonnable.html#method.from_json_string_with),
+/// [`from_json_reader_with()`](trait.FromJsonnable.html#method.from_json_reader_with) and [`JsonStream`](struct.JsonStream.html),
+/// see [`JsonOptions::with_limits()`](struct.JsonOptions.html#method.with_limits).
+///
+/// `None` means unlimited.
+/// The default limits are generous for chat traffic but keep hostile input in check,
+/// and apply to the plain, option-less decoding functions too; opt out with [`unlimited()`](#method.unlimited).
+///
+/// # Examples
+///
+/// ```
+/// # use chattium_oxide_lib::json::{limit_exceeded, DecodeLimits, FromJsonnable, JsonOptions, LimitError};
+/// let options = JsonOptions::default().with_limits(DecodeLimits{max_depth: Some(2), ..DecodeLimits::default()});
+/// let nested = "[[[1]]]";
+///
+/// assert!(Vec::<Vec<Vec<u8>>>::from_json_string_with(nested, &JsonOptions::default()).is_ok());
+/// let error = Vec::<Vec<Vec<u8>>>::from_json_string_with(nested, &options).unwrap_err();
+/// assert_eq!(limit_exceeded(&error), Some(LimitError::TooDeep(2)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeLimits {
+	/// Maximal length of the whole input in bytes, or of each value in a `JsonStream`
+	pub max_bytes: Option<usize>,
+	/// Maximal number of arrays and objects nested in each other, `0` allows only scalars
+	pub max_depth: Option<usize>,
+	/// Maximal length of strings, including object keys, in bytes as written in the input, escapes included
+	pub max_string_length: Option<usize>,
+	/// Maximal number of elements in a single array
+	pub max_array_length: Option<usize>,
+}
+
+/// Which of the [`DecodeLimits`](struct.DecodeLimits.html) input exceeded, and its value.
+///
+/// Returned wrapped in a `JsonError::Io` of kind `InvalidData`, get it back with [`limit_exceeded()`](fn.limit_exceeded.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitError {
+	TooLarge(usize),
+	TooDeep(usize),
+	StringTooLong(usize),
+	ArrayTooLong(usize),
+}
+
+/// Checks bytes against [`DecodeLimits`](struct.DecodeLimits.html) one at a time
+#[derive(Debug, Clone)]
+pub(crate) struct LimitScanner {
+	limits: DecodeLimits,
+	bytes: usize,
+	containers: Vec<Container>,
+	in_string: bool,
+	escaped: bool,
+	string_length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+	Object,
+	Array{
+		elements: usize,
+		expecting: bool,
+	},
+}
+
+/// Passes reads through a `LimitScanner`, failing them once a limit is exceeded
+pub(crate) struct LimitedReader<R: Read> {
+	reader: R,
+	scanner: LimitScanner,
+}
+
+
+/// Get the limit `error` was caused by exceeding, if any
+pub fn limit_exceeded(error: &JsonError) -> Option<LimitError> {
+	match *error {
+		JsonError::Io(ref e) => e.get_ref().and_then(|inner| inner.downcast_ref::<LimitError>()).cloned(),
+		_                    => None,
+	}
+}
+
+
+impl DecodeLimits {
+	/// No limits at all, for trusted input only
+	pub fn unlimited() -> DecodeLimits {
+		DecodeLimits{
+			max_bytes: None,
+			max_depth: None,
+			max_string_length: None,
+			max_array_length: None,
+		}
+	}
+
+	/// Check whether any limit is set
+	pub fn is_limited(&self) -> bool {
+		*self != DecodeLimits::unlimited()
+	}
+
+	/// Check the whole of `json` against the limits.
+	///
+	/// Malformed JSON isn't detected, but doesn't cause spurious errors either.
+	pub fn check(&self, json: &[u8]) -> Result<(), LimitError> {
+		if self.is_limited() {
+			let mut scanner = LimitScanner::new(*self);
+			for &b in json {
+				try!(scanner.feed(b));
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Default for DecodeLimits {
+	/// 16 MiB in all, 64 levels of nesting, 1 MiB strings and 65536-element arrays
+	fn default() -> DecodeLimits {
+		DecodeLimits{
+			max_bytes: Some(16 << 20),
+			max_depth: Some(64),
+			max_string_length: Some(1 << 20),
+			max_array_length: Some(1 << 16),
+		}
+	}
+}
+
+impl LimitScanner {
+	pub fn new(limits: DecodeLimits) -> LimitScanner {
+		LimitScanner{
+			limits: limits,
+			bytes: 0,
+			containers: Vec::new(),
+			in_string: false,
+			escaped: false,
+			string_length: 0,
+		}
+	}
+
+	/// Start checking a new value
+	pub fn reset(&mut self) {
+		*self = LimitScanner::new(self.limits);
+	}
+
+	/// Check the next byte of input
+	pub fn feed(&mut self, b: u8) -> Result<(), LimitError> {
+		self.bytes += 1;
+		try!(check(self.bytes, self.limits.max_bytes, LimitError::TooLarge));
+
+		if self.in_string {
+			if self.escaped {
+				self.escaped = false;
+			} else if b == b'\\' {
+				self.escaped = true;
+			} else if b == b'"' {
+				self.in_string = false;
+				return Ok(());
+			}
+			self.string_length += 1;
+			return check(self.string_length, self.limits.max_string_length, LimitError::StringTooLong);
+		}
+
+		match b {
+			b' ' | b'\n' | b'\r' | b'\t' | b':' => {},
+			b',' => {
+				if let Some(&mut Container::Array{ref mut expecting, ..}) = self.containers.last_mut() {
+					*expecting = true;
+				}
+			},
+			b']' | b'}' => {
+				self.containers.pop();
+			},
+			b => {
+				// The first byte of a value, or any byte of a number or literal
+				if let Some(&mut Container::Array{ref mut elements, ref mut expecting}) = self.containers.last_mut() {
+					if *expecting {
+						*elements += 1;
+						*expecting = false;
+						try!(check(*elements, self.limits.max_array_length, LimitError::ArrayTooLong));
+					}
+				}
+
+				match b {
+					b'"' => {
+						self.in_string = true;
+						self.string_length = 0;
+					},
+					b'[' | b'{' => {
+						self.containers.push(if b == b'[' {Container::Array{elements: 0, expecting: true}} else {Container::Object});
+						try!(check(self.containers.len(), self.limits.max_depth, LimitError::TooDeep));
+					},
+					_ => {},
+				}
+			},
+		}
+		Ok(())
+	}
+}
+
+impl<R: Read> LimitedReader<R> {
+	pub fn new(reader: R, limits: DecodeLimits) -> LimitedReader<R> {
+		LimitedReader{
+			reader: reader,
+			scanner: LimitScanner::new(limits),
+		}
+	}
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = try!(self.reader.read(buf));
+		for &b in &buf[..read] {
+			try!(self.scanner.feed(b));
+		}
+		Ok(read)
+	}
+}
+
+
+impl fmt::Display for LimitError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			LimitError::TooLarge(max)      => write!(f, "input longer than {} bytes", max),
+			LimitError::TooDeep(max)       => write!(f, "input nested deeper than {} levels", max),
+			LimitError::StringTooLong(max) => write!(f, "string longer than {} bytes", max),
+			LimitError::ArrayTooLong(max)  => write!(f, "array longer than {} elements", max),
+		}
+	}
+}
+
+impl StdError for LimitError {}
+
+impl From<LimitError> for io::Error {
+	fn from(e: LimitError) -> io::Error {
+		io::Error::new(io::ErrorKind::InvalidData, e)
+	}
+}
+
+impl From<LimitError> for JsonError {
+	fn from(e: LimitError) -> JsonError {
+		JsonError::Io(e.into())
+	}
+}
+
+
+fn check(value: usize, limit: Option<usize>, error: fn(usize) -> LimitError) -> Result<(), LimitError> {
+	match limit {
+		Some(limit) if value > limit => Err(error(limit)),
+		_                            => Ok(()),
+	}
+}
@@ -103,8 +103,26 @@
 //! ```
 
 mod implementation;
+mod field;
+mod time_format;
+mod integer;
+mod stream;
+mod unknown;
+mod schema;
+mod limits;
+mod options;
+#[cfg(feature = "modern-serde")]
+pub mod bridge;
 pub use self::implementation::*;
+pub use self::time_format::*;
+pub use self::stream::*;
+pub use self::unknown::*;
+pub use self::schema::*;
+pub use self::limits::*;
+pub use self::options::*;
+pub use self::field::*;
 
+use std::io::{Read, Write};
 use serde_json;
 use serde_json::value::Value;
 
@@ -118,10 +136,42 @@ pub trait FromJsonnable: Sized {
 	/// Returns `Err()` if the Value cannot be deserialized into `Self`
 	fn from_json(json: Value) -> Result<Self, JsonError>;
 
+	/// Deserialize a JSON value to `Self` with `options`.
+	///
+	/// Defaults to ignoring `options`; types whose decoding they affect, or that contain such types, override it
+	/// and implement [`from_json()`](#tymethod.from_json) as this with the default options.
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		let _ = options;
+		Self::from_json(json)
+	}
+
 	/// Convenience function for deserializing a JSON string representation directly into `Self`
 	fn from_json_string(string: &String) -> Result<Self, JsonError> {
-		let value: Value = try!(serde_json::from_str(&*&string));
-		Self::from_json(value)
+		Self::from_json_string_with(&*&string, &JsonOptions::default())
+	}
+
+	/// Convenience function for deserializing a JSON string representation directly into `Self` with `options`,
+	/// refusing it if it exceeds their [`DecodeLimits`](struct.DecodeLimits.html)
+	fn from_json_string_with(string: &str, options: &JsonOptions) -> Result<Self, JsonError> {
+		try!(options.limits().check(string.as_bytes()));
+		let value: Value = try!(serde_json::from_str(string));
+		Self::from_json_with(value, options)
+	}
+
+	/// Convenience function for deserializing JSON read from `reader` directly into `Self`, without buffering it in a `String`.
+	///
+	/// `reader` is read byte-by-byte, so wrap it in a `BufReader` if that's slow.
+	/// To read many values from one reader, see [`JsonStream`](struct.JsonStream.html).
+	fn from_json_reader<R: Read>(reader: R) -> Result<Self, JsonError> {
+		Self::from_json_reader_with(reader, &JsonOptions::default())
+	}
+
+	/// Like [`from_json_reader()`](#method.from_json_reader), but with `options`.
+	///
+	/// Reading stops as soon as their [`DecodeLimits`](struct.DecodeLimits.html) are exceeded.
+	fn from_json_reader_with<R: Read>(reader: R, options: &JsonOptions) -> Result<Self, JsonError> {
+		let value: Value = try!(serde_json::from_reader(LimitedReader::new(reader, options.limits())));
+		Self::from_json_with(value, options)
 	}
 }
 
@@ -133,10 +183,36 @@ pub trait ToJsonnable: Sized {
 	/// that `self.from_json(self.to_json())` never returns `Err()`.
 	fn to_json(&self) -> Value;
 
+	/// Serialize `self` to a JSON `Value` with `options`.
+	///
+	/// Defaults to ignoring `options`; types whose encoding they affect, or that contain such types, override it
+	/// and implement [`to_json()`](#tymethod.to_json) as this with the default options.
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let _ = options;
+		self.to_json()
+	}
+
 	/// Convenience function for converting `self` to a JSON ugly-string representation.
 	///
 	/// Returns `Err()` if `serde_json` couldn't convert the Value to a String
 	fn to_json_string(&self) -> Result<String, JsonError> {
-		serde_json::to_string(&self.to_json())
+		self.to_json_string_with(&JsonOptions::default())
+	}
+
+	/// Convenience function for converting `self` to a JSON ugly-string representation with `options`
+	fn to_json_string_with(&self, options: &JsonOptions) -> Result<String, JsonError> {
+		serde_json::to_string(&self.to_json_with(options))
+	}
+
+	/// Convenience function for writing `self`'s JSON ugly-string representation to `writer`, without buffering it in a `String`.
+	///
+	/// Returns `Err()` if `serde_json` couldn't write the Value
+	fn to_json_writer<W: Write>(&self, writer: W) -> Result<(), JsonError> {
+		self.to_json_writer_with(writer, &JsonOptions::default())
+	}
+
+	/// Convenience function for writing `self`'s JSON ugly-string representation with `options` to `writer`
+	fn to_json_writer_with<W: Write>(&self, mut writer: W, options: &JsonOptions) -> Result<(), JsonError> {
+		serde_json::to_writer(&mut writer, &self.to_json_with(options))
 	}
 }
@@ -0,0 +1,82 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use json::{DecodeLimits, Extensible, TimeFormat, UnknownFields};
+
+
+/// Everything configurable about encoding and decoding JSON, passed explicitly to the `_with` variants of
+/// [`FromJsonnable`](trait.FromJsonnable.html) and [`ToJsonnable`](trait.ToJsonnable.html)'s functions
+/// and to [`JsonStream::with_options()`](struct.JsonStream.html#method.with_options).
+///
+/// The plain functions use the defaults: legacy times, strict integers, the default [`DecodeLimits`](struct.DecodeLimits.html)
+/// and unknown fields ignored.
+///
+/// # Examples
+///
+/// ```
+/// # use std::io::Cursor;
+/// # use chattium_oxide_lib::json::{FromJsonnable, JsonOptions, JsonStream};
+/// let lenient = JsonOptions::default().with_lenient_integers(true);
+///
+/// assert!(Vec::<u8>::from_json_string_with("[3.0]", &JsonOptions::default()).is_err());
+/// assert_eq!(Vec::<u8>::from_json_string_with("[3.0]", &lenient).unwrap(), [3]);
+///
+/// let stream = JsonStream::<_, u8>::new(Cursor::new("1 2.0 3.5")).with_options(lenient);
+/// assert_eq!(stream.map(Result::ok).collect::<Vec<_>>(), [Some(1), Some(2), None]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonOptions {
+	time_format: TimeFormat,
+	lenient_integers: bool,
+	limits: DecodeLimits,
+	unknown_fields: HashMap<TypeId, UnknownFields>,
+}
+
+
+impl JsonOptions {
+	/// Serialize `Tm`s with `format`
+	pub fn with_time_format(mut self, format: TimeFormat) -> JsonOptions {
+		self.time_format = format;
+		self
+	}
+
+	/// Accept floats with no fractional part, like `3.0`, where integers are expected.
+	///
+	/// Off by default, so only integer literals decode to integer types.
+	/// Integers out of the target type's range are always refused, lenient or not.
+	pub fn with_lenient_integers(mut self, lenient: bool) -> JsonOptions {
+		self.lenient_integers = lenient;
+		self
+	}
+
+	/// Decode JSON strings, readers and streams within `limits`
+	pub fn with_limits(mut self, limits: DecodeLimits) -> JsonOptions {
+		self.limits = limits;
+		self
+	}
+
+	/// Decode `T`s with the unknown field `policy`
+	pub fn with_unknown_fields<T: Extensible>(mut self, policy: UnknownFields) -> JsonOptions {
+		self.unknown_fields.insert(TypeId::of::<T>(), policy);
+		self
+	}
+
+	/// The format `Tm`s are serialized with
+	pub fn time_format(&self) -> TimeFormat {
+		self.time_format
+	}
+
+	/// Whether integers are decoded leniently, see [`with_lenient_integers()`](#method.with_lenient_integers)
+	pub fn lenient_integers(&self) -> bool {
+		self.lenient_integers
+	}
+
+	/// The limits JSON input is decoded within
+	pub fn limits(&self) -> DecodeLimits {
+		self.limits
+	}
+
+	/// The unknown field policy for decoding `T`s
+	pub fn unknown_fields<T: Extensible>(&self) -> UnknownFields {
+		self.unknown_fields.get(&TypeId::of::<T>()).cloned().unwrap_or(UnknownFields::Ignore)
+	}
+}
@@ -0,0 +1,322 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration as StdDuration;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use time::{Duration, Tm};
+use json::{FieldSpec, ToJsonnable, TimeFormat};
+use serde_json::value::Value;
+use serde_json::builder::ObjectBuilder;
+use {ChatMessage, ChatUser, Payload, NickChange, BlobHash, Moderation, Target, AddressRange, ModerationList, Permission, Role, AuditKind,
+     AuditTarget, AuditEvent, AuditRecord};
+
+
+/// The JSON Schema dialect all schemas are written in
+pub static SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+
+/// A type whose JSON representation can be described by a [JSON Schema](https://json-schema.org).
+///
+/// The schema matches everything [`to_json()`](trait.ToJsonnable.html#tymethod.to_json) can return,
+/// and should match as little else as possible that [`from_json()`](trait.FromJsonnable.html#tymethod.from_json) refuses.
+/// Limits that JSON Schema can't express, like names' lengths in grapheme clusters, are left out.
+///
+/// # Examples
+///
+/// ```
+/// # use chattium_oxide_lib::NickChange;
+/// # use chattium_oxide_lib::json::JsonSchema;
+/// assert_eq!(NickChange::json_schema().to_string(),
+///            r#"{"properties":{"new":{"type":"string"},"old":{"type":"string"}},"required":["old","new"],"type":"object"}"#);
+/// ```
+pub trait JsonSchema {
+	/// The schema of `Self`'s JSON representation, without the `"$schema"` keyword
+	fn json_schema() -> Value;
+}
+
+
+/// A standalone schema document for `T`, declaring the [dialect](static.SCHEMA_DIALECT.html) and titled `title`
+pub fn schema_document<T: JsonSchema>(title: &str) -> Value {
+	let mut schema = T::json_schema();
+	if let Value::Object(ref mut map) = schema {
+		map.insert("$schema".to_string(), Value::String(SCHEMA_DIALECT.to_string()));
+		map.insert("title".to_string(), Value::String(title.to_string()));
+	}
+	schema
+}
+
+/// A single schema document with every protocol type under `"$defs"`, keyed by type name.
+///
+/// `Tm` and the other [`Timestamp`](../type.Timestamp.html) backends are under `"Time"`.
+/// Refer to a single type with `"$ref": "#/$defs/ChatMessage"` and the like.
+pub fn protocol_schema() -> Value {
+	let mut defs = BTreeMap::new();
+	defs.insert("ChatMessage".to_string(), ChatMessage::json_schema());
+	defs.insert("ChatUser".to_string(), ChatUser::json_schema());
+	defs.insert("Time".to_string(), Tm::json_schema());
+	defs.insert("Payload".to_string(), Payload::json_schema());
+	defs.insert("NickChange".to_string(), NickChange::json_schema());
+	defs.insert("BlobHash".to_string(), BlobHash::json_schema());
+	defs.insert("Moderation".to_string(), Moderation::json_schema());
+	defs.insert("Target".to_string(), Target::json_schema());
+	defs.insert("AddressRange".to_string(), AddressRange::json_schema());
+	defs.insert("ModerationList".to_string(), ModerationList::json_schema());
+	defs.insert("Permission".to_string(), Permission::json_schema());
+	defs.insert("Role".to_string(), Role::json_schema());
+	defs.insert("AuditKind".to_string(), AuditKind::json_schema());
+	defs.insert("AuditTarget".to_string(), AuditTarget::json_schema());
+	defs.insert("AuditEvent".to_string(), AuditEvent::json_schema());
+	defs.insert("AuditRecord".to_string(), AuditRecord::json_schema());
+
+	ObjectBuilder::new().insert("$schema", SCHEMA_DIALECT)
+	                    .insert("title", "chattium-oxide protocol")
+	                    .insert("$defs", Value::Object(defs))
+	                    .build()
+}
+
+
+/// Schema of any JSON value
+pub fn any_schema() -> Value {
+	Value::Object(BTreeMap::new())
+}
+
+/// Schema of any string
+pub fn string_schema() -> Value {
+	ObjectBuilder::new().insert("type", "string").build()
+}
+
+/// Schema of integers in `minimum..=maximum`
+pub fn integer_schema<T: ToJsonnable>(minimum: T, maximum: T) -> Value {
+	ObjectBuilder::new().insert("type", "integer")
+	                    .insert("minimum", minimum.to_json())
+	                    .insert("maximum", maximum.to_json())
+	                    .build()
+}
+
+/// Schema of arrays of `items`
+pub fn array_schema(items: Value) -> Value {
+	ObjectBuilder::new().insert("type", "array")
+	                    .insert("items", items)
+	                    .build()
+}
+
+/// Schema of exactly one of `names`
+pub fn enum_schema(names: &[&str]) -> Value {
+	ObjectBuilder::new().insert("enum", names).build()
+}
+
+/// Schema of values matching any of `schemas`
+pub fn any_of_schema(schemas: Vec<Value>) -> Value {
+	ObjectBuilder::new().insert("anyOf", schemas).build()
+}
+
+/// Schema of objects with all the `required` properties and any of the `optional` ones.
+///
+/// Other properties are allowed, since they can be ignored or preserved, see [`UnknownFields`](enum.UnknownFields.html).
+pub fn object_schema(required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+	let properties = required.iter().chain(optional).map(|&(name, ref schema)| (name.to_string(), schema.clone())).collect();
+	ObjectBuilder::new().insert("type", "object")
+	                    .insert("properties", Value::Object(properties))
+	                    .insert("required", required.iter().map(|&(name, _)| name).collect::<Vec<_>>())
+	                    .build()
+}
+
+/// Schema of objects with the fields in a type's field table, see [`Field`](struct.Field.html)
+pub fn fields_schema(fields: &[FieldSpec]) -> Value {
+	let required: Vec<_> = fields.iter().filter(|field| field.required).map(|field| (field.name, (field.schema)())).collect();
+	let optional: Vec<_> = fields.iter().filter(|field| !field.required).map(|field| (field.name, (field.schema)())).collect();
+	object_schema(&required, &optional)
+}
+
+/// Schema of objects with the single property `name`, like externally tagged enum variants
+pub fn single_field_schema(name: &str, schema: Value) -> Value {
+	let mut schema = object_schema(&[(name, schema)], &[]);
+	if let Value::Object(ref mut map) = schema {
+		map.insert("additionalProperties".to_string(), Value::Bool(false));
+	}
+	schema
+}
+
+
+impl JsonSchema for Tm {
+	// Any TimeFormat, since all are accepted regardless of the current one
+	fn json_schema() -> Value {
+		any_of_schema(vec![TimeFormat::Legacy.schema(), TimeFormat::Rfc3339.schema(), TimeFormat::EpochMillis.schema()])
+	}
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+	fn json_schema() -> Value {
+		array_schema(T::json_schema())
+	}
+}
+
+impl<T: JsonSchema, const N: usize> JsonSchema for [T; N] {
+	fn json_schema() -> Value {
+		let mut schema = array_schema(T::json_schema());
+		if let Value::Object(ref mut map) = schema {
+			map.insert("minItems".to_string(), Value::U64(N as u64));
+			map.insert("maxItems".to_string(), Value::U64(N as u64));
+		}
+		schema
+	}
+}
+
+impl<T: JsonSchema + Eq + Hash> JsonSchema for HashSet<T> {
+	fn json_schema() -> Value {
+		array_schema(T::json_schema())
+	}
+}
+
+impl<T: JsonSchema + Ord> JsonSchema for BTreeSet<T> {
+	fn json_schema() -> Value {
+		array_schema(T::json_schema())
+	}
+}
+
+impl<T: JsonSchema> JsonSchema for HashMap<String, T> {
+	fn json_schema() -> Value {
+		BTreeMap::<String, T>::json_schema()
+	}
+}
+
+impl<T: JsonSchema> JsonSchema for BTreeMap<String, T> {
+	fn json_schema() -> Value {
+		ObjectBuilder::new().insert("type", "object")
+		                    .insert("additionalProperties", T::json_schema())
+		                    .build()
+	}
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+	fn json_schema() -> Value {
+		any_of_schema(vec![T::json_schema(), ObjectBuilder::new().insert("type", "null").build()])
+	}
+}
+
+impl JsonSchema for String {
+	fn json_schema() -> Value {
+		string_schema()
+	}
+}
+
+impl JsonSchema for Value {
+	fn json_schema() -> Value {
+		any_schema()
+	}
+}
+
+impl JsonSchema for char {
+	fn json_schema() -> Value {
+		ObjectBuilder::new().insert("type", "string")
+		                    .insert("minLength", 1)
+		                    .insert("maxLength", 1)
+		                    .build()
+	}
+}
+
+impl JsonSchema for bool {
+	fn json_schema() -> Value {
+		ObjectBuilder::new().insert("type", "boolean").build()
+	}
+}
+
+impl JsonSchema for Duration {
+	fn json_schema() -> Value {
+		object_schema(&[("sec", i64::json_schema()), ("nsec", integer_schema(0, 999_999_999))], &[])
+	}
+}
+
+impl JsonSchema for StdDuration {
+	fn json_schema() -> Value {
+		object_schema(&[("sec", u64::json_schema()), ("nsec", integer_schema(0, 999_999_999))], &[])
+	}
+}
+
+impl JsonSchema for IpAddr {
+	fn json_schema() -> Value {
+		any_of_schema(vec![ObjectBuilder::new().insert("type", "string").insert("format", "ipv4").build(),
+		                   ObjectBuilder::new().insert("type", "string").insert("format", "ipv6").build()])
+	}
+}
+
+impl JsonSchema for SocketAddr {
+	fn json_schema() -> Value {
+		string_schema()
+	}
+}
+
+
+macro_rules! pointer_schema {
+	($($p:ident)+) => {
+		$(
+			impl<T: JsonSchema> JsonSchema for $p<T> {
+				fn json_schema() -> Value {
+					T::json_schema()
+				}
+			}
+		)+
+	};
+}
+
+macro_rules! tuple_schema {
+	($($len:expr => ($($t:ident)+))+) => {
+		$(
+			impl<$($t: JsonSchema),+> JsonSchema for ($($t,)+) {
+				fn json_schema() -> Value {
+					ObjectBuilder::new().insert("type", "array")
+					                    .insert("prefixItems", vec![$($t::json_schema()),+])
+					                    .insert("minItems", $len)
+					                    .insert("maxItems", $len)
+					                    .build()
+				}
+			}
+		)+
+	};
+}
+
+macro_rules! integer_schema {
+	($($t:ty)+) => {
+		$(
+			impl JsonSchema for $t {
+				fn json_schema() -> Value {
+					integer_schema(<$t>::MIN, <$t>::MAX)
+				}
+			}
+		)+
+	};
+}
+
+macro_rules! number_schema {
+	($($t:ty)+) => {
+		$(
+			impl JsonSchema for $t {
+				fn json_schema() -> Value {
+					ObjectBuilder::new().insert("type", "number").build()
+				}
+			}
+		)+
+	};
+}
+
+
+integer_schema!(i8 i16 i32 i64 u8 u16 u32 u64);
+number_schema!(f32 f64);
+
+pointer_schema!(Box Rc Arc);
+
+tuple_schema! {
+	1  => (A)
+	2  => (A B)
+	3  => (A B C)
+	4  => (A B C D)
+	5  => (A B C D E)
+	6  => (A B C D E F)
+	7  => (A B C D E F G)
+	8  => (A B C D E F G H)
+	9  => (A B C D E F G H I)
+	10 => (A B C D E F G H I J)
+	11 => (A B C D E F G H I J K)
+	12 => (A B C D E F G H I J K L)
+}
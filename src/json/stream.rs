@@ -0,0 +1,284 @@
+use std::marker::PhantomData;
+use std::io::{BufReader, Bytes, Read};
+use json::{FromJsonnable, JsonOptions};
+use json::limits::{DecodeLimits, LimitScanner};
+use serde_json;
+use serde_json::value::Value;
+use serde_json::error::{Error as JsonError, ErrorCode};
+
+
+/// Decodes a sequence of JSON values from a reader one at a time, holding at most one value in memory.
+///
+/// The values can either be separated by whitespace, as in [JSON Lines](http://jsonlines.org),
+/// or be the elements of a single top-level array, like a serialized `Vec` would be.
+///
+/// Values that are valid JSON but fail to decode into `T` are returned as errors and skipped over.
+/// Malformed JSON, I/O errors and values exceeding the [`DecodeLimits`](struct.DecodeLimits.html) end the stream,
+/// since the following values can't be reliably found.
+///
+/// Values are decoded with the default [`JsonOptions`](struct.JsonOptions.html), and so the default limits,
+/// unless set with [`with_options()`](#method.with_options) or [`limited()`](#method.limited).
+/// The limits apply to each value separately.
+///
+/// # Examples
+///
+/// ```
+/// # use std::io::Cursor;
+/// # use chattium_oxide_lib::{ChatMessage, ChatUser, ValidatedName};
+/// # use chattium_oxide_lib::json::{JsonStream, ToJsonnable};
+/// let mut history = Vec::new();
+/// for text in &["first", "second", "third"] {
+/// 	let message = ChatMessage::new(ChatUser::me(ValidatedName::new("Historian").unwrap()), text.to_string());
+/// 	message.to_json_writer(&mut history).unwrap();
+/// 	history.push(b'\n');
+/// }
+///
+/// let texts: Vec<_> = JsonStream::<_, ChatMessage>::new(Cursor::new(history)).map(|message| message.unwrap().value).collect();
+/// assert_eq!(texts, vec!["first", "second", "third"]);
+/// ```
+pub struct JsonStream<R: Read, T> {
+	bytes: Bytes<BufReader<R>>,
+	peeked: Option<u8>,
+	array: ArrayState,
+	line: usize,
+	column: usize,
+	buffer: Vec<u8>,
+	limits: LimitScanner,
+	options: JsonOptions,
+	failed: bool,
+	_marker: PhantomData<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArrayState {
+	/// Not in array mode, values are separated by whitespace
+	Values,
+	/// Before the opening `[`
+	Start,
+	/// After an element
+	Rest,
+	/// After the closing `]`
+	Done,
+}
+
+
+impl<R: Read, T: FromJsonnable> JsonStream<R, T> {
+	/// Decode whitespace-separated values from `reader`
+	pub fn new(reader: R) -> JsonStream<R, T> {
+		JsonStream::with_state(reader, ArrayState::Values)
+	}
+
+	/// Decode the elements of the single array in `reader`
+	pub fn array(reader: R) -> JsonStream<R, T> {
+		JsonStream::with_state(reader, ArrayState::Start)
+	}
+
+	/// Decode values with `options`, including their limits
+	pub fn with_options(mut self, options: JsonOptions) -> JsonStream<R, T> {
+		self.limits = LimitScanner::new(options.limits());
+		self.options = options;
+		self
+	}
+
+	/// Decode values within `limits`, keeping the other options
+	pub fn limited(self, limits: DecodeLimits) -> JsonStream<R, T> {
+		let options = self.options.clone().with_limits(limits);
+		self.with_options(options)
+	}
+
+	fn with_state(reader: R, array: ArrayState) -> JsonStream<R, T> {
+		JsonStream{
+			bytes: BufReader::new(reader).bytes(),
+			peeked: None,
+			array: array,
+			line: 1,
+			column: 0,
+			buffer: Vec::new(),
+			limits: LimitScanner::new(DecodeLimits::default()),
+			options: JsonOptions::default(),
+			failed: false,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Read the next value into `buffer`, returning whether there was one
+	fn read_value(&mut self) -> Result<bool, JsonError> {
+		self.buffer.clear();
+		try!(self.skip_whitespace());
+
+		match self.array {
+			ArrayState::Values => {
+				if try!(self.peek_byte()).is_none() {
+					return Ok(false);
+				}
+			},
+			ArrayState::Start => {
+				match try!(self.next_byte()) {
+					Some(b'[') => {},
+					Some(_)    => return Err(self.error(ErrorCode::ExpectedSomeValue)),
+					None       => return Err(self.error(ErrorCode::EOFWhileParsingValue)),
+				}
+				try!(self.skip_whitespace());
+				if try!(self.peek_byte()) == Some(b']') {
+					self.next_byte().ok();
+					return self.end_array();
+				}
+			},
+			ArrayState::Rest => {
+				match try!(self.next_byte()) {
+					Some(b',') => try!(self.skip_whitespace()),
+					Some(b']') => return self.end_array(),
+					Some(_)    => return Err(self.error(ErrorCode::ExpectedListCommaOrEnd)),
+					None       => return Err(self.error(ErrorCode::EOFWhileParsingList)),
+				}
+			},
+			ArrayState::Done => return Ok(false),
+		}
+
+		try!(self.capture_value());
+		if self.array != ArrayState::Values {
+			self.array = ArrayState::Rest;
+		}
+		Ok(true)
+	}
+
+	/// Copy the bytes of exactly one value into `buffer`
+	fn capture_value(&mut self) -> Result<(), JsonError> {
+		self.limits.reset();
+		match try!(self.peek_byte()) {
+			None => Err(self.error(ErrorCode::EOFWhileParsingValue)),
+			Some(b'{') | Some(b'[') | Some(b'"') => {
+				let mut depth = 0usize;
+				let mut in_string = false;
+				let mut escaped = false;
+				loop {
+					let b =
+						match try!(self.next_byte()) {
+							Some(b)                                    => b,
+							None if in_string                          => return Err(self.error(ErrorCode::EOFWhileParsingString)),
+							None if self.buffer.first() == Some(&b'{') => return Err(self.error(ErrorCode::EOFWhileParsingObject)),
+							None                                       => return Err(self.error(ErrorCode::EOFWhileParsingList)),
+						};
+					self.buffer.push(b);
+					try!(self.limits.feed(b));
+
+					if in_string {
+						if escaped {
+							escaped = false;
+						} else if b == b'\\' {
+							escaped = true;
+						} else if b == b'"' {
+							in_string = false;
+						}
+					} else {
+						match b {
+							b'"'        => in_string = true,
+							b'{' | b'[' => depth += 1,
+							b'}' | b']' => depth -= 1,
+							_           => {},
+						}
+					}
+
+					if depth == 0 && !in_string {
+						return Ok(());
+					}
+				}
+			},
+			Some(_) => {
+				while let Some(b) = try!(self.peek_byte()) {
+					if is_whitespace(b) || b"{}[],\":".contains(&b) {
+						break;
+					}
+					self.buffer.push(b);
+					try!(self.limits.feed(b));
+					self.next_byte().ok();
+				}
+				if self.buffer.is_empty() {
+					Err(self.error(ErrorCode::ExpectedSomeValue))
+				} else {
+					Ok(())
+				}
+			},
+		}
+	}
+
+	fn end_array(&mut self) -> Result<bool, JsonError> {
+		self.array = ArrayState::Done;
+		try!(self.skip_whitespace());
+		match try!(self.peek_byte()) {
+			Some(_) => Err(self.error(ErrorCode::TrailingCharacters)),
+			None    => Ok(false),
+		}
+	}
+
+	fn skip_whitespace(&mut self) -> Result<(), JsonError> {
+		while let Some(b) = try!(self.peek_byte()) {
+			if !is_whitespace(b) {
+				break;
+			}
+			self.next_byte().ok();
+		}
+		Ok(())
+	}
+
+	fn peek_byte(&mut self) -> Result<Option<u8>, JsonError> {
+		if self.peeked.is_none() {
+			self.peeked = match self.bytes.next() {
+				Some(Ok(b))  => Some(b),
+				Some(Err(e)) => return Err(JsonError::Io(e)),
+				None         => None,
+			};
+		}
+		Ok(self.peeked)
+	}
+
+	fn next_byte(&mut self) -> Result<Option<u8>, JsonError> {
+		let b = try!(self.peek_byte());
+		self.peeked = None;
+		match b {
+			Some(b'\n') => {
+				self.line += 1;
+				self.column = 0;
+			},
+			Some(_) => self.column += 1,
+			None    => {},
+		}
+		Ok(b)
+	}
+
+	fn error(&self, code: ErrorCode) -> JsonError {
+		JsonError::Syntax(code, self.line, self.column)
+	}
+}
+
+impl<R: Read, T: FromJsonnable> Iterator for JsonStream<R, T> {
+	type Item = Result<T, JsonError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+
+		match self.read_value() {
+			// A value of the wrong type leaves the stream intact, but one that doesn't parse means it's lost its place
+			Ok(true) =>
+				match serde_json::from_slice::<Value>(&self.buffer) {
+					Ok(value) => Some(T::from_json_with(value, &self.options)),
+					Err(e) => {
+						self.failed = true;
+						Some(Err(e))
+					},
+				},
+			Ok(false) => None,
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
+			},
+		}
+	}
+}
+
+
+fn is_whitespace(b: u8) -> bool {
+	b == b' ' || b == b'\n' || b == b'\r' || b == b'\t'
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::error::Error as StdError;
+use time::{at_utc, Tm, Timespec};
+use json::schema::{integer_schema, object_schema};
+use serde::de::{Error, Type};
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+use serde_json::builder::ObjectBuilder;
+
+
+/// How `Tm`s are serialized to JSON, selected with [`JsonOptions::with_time_format()`](struct.JsonOptions.html#method.with_time_format).
+///
+/// Deserialization accepts all formats regardless of the one selected.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate time;
+/// # extern crate chattium_oxide_lib;
+/// # use time::{at_utc, Timespec};
+/// # use chattium_oxide_lib::json::{JsonOptions, TimeFormat, ToJsonnable};
+/// # fn main() {
+/// let time = at_utc(Timespec::new(1500000000, 5000));
+/// assert_eq!(time.to_json_string_with(&JsonOptions::default().with_time_format(TimeFormat::Rfc3339)).unwrap(),
+///            "\"2017-07-14T02:40:00.000005000Z\"");
+/// assert_eq!(time.to_json_string().unwrap(), "{\"nsec\":5000,\"sec\":1500000000}");
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TimeFormat {
+	/// `{"sec": 1500000000, "nsec": 123456789}`, the default, understood by all peers
+	#[default]
+	Legacy,
+	/// `"2017-07-14T02:40:00.123456789Z"`
+	Rfc3339,
+	/// `1500000000123`, truncating to whole milliseconds
+	EpochMillis,
+}
+
+/// Why a decoded time was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeRangeError {
+	/// Nanoseconds outside `0..1_000_000_000`, saturated to `i64`
+	Nanoseconds(i64),
+	/// Seconds outside [`MIN_TIME_SEC`](constant.MIN_TIME_SEC.html)..=[`MAX_TIME_SEC`](constant.MAX_TIME_SEC.html), saturated to `i64`
+	Seconds(i64),
+}
+
+
+/// The earliest representable second, 0000-01-01T00:00:00Z
+pub const MIN_TIME_SEC: i64 = -62167219200;
+
+/// The latest representable second, 9999-12-31T23:59:59Z.
+///
+/// Years outside 0000..=9999 can't be written in RFC 3339, and may not be supported by the platform's `gmtime()`.
+pub const MAX_TIME_SEC: i64 = 253402300799;
+
+
+impl TimeFormat {
+	/// Serialize `time` in this format
+	pub fn encode(&self, time: &Tm) -> Value {
+		let spec = time.to_timespec();
+		match *self {
+			TimeFormat::Legacy =>
+				ObjectBuilder::new().insert("sec", &spec.sec)
+				                    .insert("nsec", &spec.nsec)
+				                    .build(),
+			TimeFormat::Rfc3339 => Value::String(format_rfc3339(spec)),
+			TimeFormat::EpochMillis => Value::I64(spec.sec.saturating_mul(1000).saturating_add(spec.nsec as i64 / 1_000_000)),
+		}
+	}
+
+	/// The [JSON Schema](trait.JsonSchema.html) of times in this format, within the range all formats support
+	pub fn schema(&self) -> Value {
+		match *self {
+			TimeFormat::Legacy => object_schema(&[("sec", integer_schema(MIN_TIME_SEC, MAX_TIME_SEC)), ("nsec", integer_schema(0, 999_999_999))], &[]),
+			TimeFormat::Rfc3339 =>
+				ObjectBuilder::new().insert("type", "string")
+				                    .insert("format", "date-time")
+				                    .build(),
+			TimeFormat::EpochMillis => integer_schema(MIN_TIME_SEC * 1000, MAX_TIME_SEC * 1000 + 999),
+		}
+	}
+}
+
+
+/// Create a `Timespec`, checking that `nsec` is in range, and `sec` in the range all formats support
+pub fn checked_timespec(sec: i64, nsec: i64) -> Result<Timespec, TimeRangeError> {
+	if !(0..1_000_000_000).contains(&nsec) {
+		Err(TimeRangeError::Nanoseconds(nsec))
+	} else if !(MIN_TIME_SEC..=MAX_TIME_SEC).contains(&sec) {
+		Err(TimeRangeError::Seconds(sec))
+	} else {
+		Ok(Timespec::new(sec, nsec as i32))
+	}
+}
+
+
+/// Parse `"YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)"`
+pub fn parse_rfc3339(s: &str) -> Option<Timespec> {
+	// Sliced by byte offset below, which can't split a character then
+	let b = s.as_bytes();
+	if !s.is_ascii() || b.len() < 20 || b[4] != b'-' || b[7] != b'-' || !(b[10] == b'T' || b[10] == b't' || b[10] == b' ') || b[13] != b':' || b[16] != b':' {
+		return None;
+	}
+
+	let year = digits(&s[0..4])?;
+	let month = digits(&s[5..7])?;
+	let day = digits(&s[8..10])?;
+	let hour = digits(&s[11..13])?;
+	let minute = digits(&s[14..16])?;
+	let second = digits(&s[17..19])?;
+	if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+		return None;
+	}
+
+	let mut rest = &s[19..];
+	let mut nsec = 0;
+	if rest.starts_with('.') {
+		let fraction_len = rest[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - 1);
+		if fraction_len == 0 || fraction_len > 9 {
+			return None;
+		}
+		nsec = digits(&rest[1..1 + fraction_len])? * 10i64.pow(9 - fraction_len as u32);
+		rest = &rest[1 + fraction_len..];
+	}
+
+	let offset =
+		match rest {
+			"Z" | "z" => 0,
+			_ if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) && &rest[3..4] == ":" => {
+				let hours = digits(&rest[1..3])?;
+				let minutes = digits(&rest[4..6])?;
+				if hours > 23 || minutes > 59 {
+					return None;
+				}
+				(hours * 3600 + minutes * 60) * if rest.starts_with('-') {-1} else {1}
+			},
+			_ => return None,
+		};
+
+	let sec = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+	checked_timespec(sec, nsec).ok()
+}
+
+fn format_rfc3339(spec: Timespec) -> String {
+	let tm = at_utc(Timespec::new(spec.sec, 0));
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+	        tm.tm_year + 1900,
+	        tm.tm_mon + 1,
+	        tm.tm_mday,
+	        tm.tm_hour,
+	        tm.tm_min,
+	        tm.tm_sec,
+	        spec.nsec)
+}
+
+fn digits(s: &str) -> Option<i64> {
+	if s.bytes().all(|b| b.is_ascii_digit()) {
+		s.parse().ok()
+	} else {
+		None
+	}
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+	match month {
+		2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// Days since 1970-01-01 of the specified proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 {year - 1} else {year};
+	let era = if year >= 0 {year} else {year - 399} / 400;
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+
+/// Decode any of the [`TimeFormat`](enum.TimeFormat.html)s
+pub(crate) fn decode_time(json: Value) -> Result<Timespec, JsonError> {
+	match json {
+		Value::String(s) => parse_rfc3339(&s).ok_or_else(|| JsonError::invalid_value("Expected an RFC 3339 timestamp")),
+		Value::I64(millis) => Ok(try!(checked_timespec(millis.div_euclid(1000), millis.rem_euclid(1000) * 1_000_000))),
+		Value::U64(millis) => Ok(try!(checked_timespec((millis / 1000).min(i64::MAX as u64) as i64, (millis % 1000 * 1_000_000) as i64))),
+		_ => Err(JsonError::invalid_type(Type::Struct)),
+	}
+}
+
+
+impl fmt::Display for TimeRangeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TimeRangeError::Nanoseconds(nsec) => write!(f, "nanoseconds {} not in 0..1000000000", nsec),
+			TimeRangeError::Seconds(sec)      => write!(f, "seconds {} not in {}..={}", sec, MIN_TIME_SEC, MAX_TIME_SEC),
+		}
+	}
+}
+
+impl StdError for TimeRangeError {}
+
+impl From<TimeRangeError> for JsonError {
+	fn from(e: TimeRangeError) -> JsonError {
+		JsonError::invalid_value(&e.to_string())
+	}
+}
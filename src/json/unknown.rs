@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use json::{FieldSpec, JsonOptions};
+use serde::de::Error;
+use serde_json;
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+
+
+/// What decoders of [`Extensible`](trait.Extensible.html) types do with object keys they don't know, set per type with
+/// [`JsonOptions::with_unknown_fields()`](struct.JsonOptions.html#method.with_unknown_fields)
+///
+/// # Examples
+///
+/// ```
+/// # use chattium_oxide_lib::ChatMessage;
+/// # use chattium_oxide_lib::json::{FromJsonnable, JsonOptions, ToJsonnable, UnknownFields};
+/// let json = r#"{"reactions":["+1"],"sender":"Relay","time_posted":{"nsec":0,"sec":1500000000},"value":"hi"}"#;
+/// let reject = JsonOptions::default().with_unknown_fields::<ChatMessage>(UnknownFields::Reject);
+/// let preserve = JsonOptions::default().with_unknown_fields::<ChatMessage>(UnknownFields::Preserve);
+///
+/// assert!(ChatMessage::from_json_string_with(json, &JsonOptions::default()).unwrap().extensions.is_empty());
+/// assert!(ChatMessage::from_json_string_with(json, &reject).is_err());
+///
+/// let message = ChatMessage::from_json_string_with(json, &preserve).unwrap();
+/// assert!(message.extensions.contains_key("reactions"));
+/// assert_eq!(message.to_json_string().unwrap(), json);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnknownFields {
+	/// Drop them, the default
+	Ignore,
+	/// Refuse the whole object
+	Reject,
+	/// Keep them in the value's [`Extensions`](struct.Extensions.html), to be serialized back as-is
+	Preserve,
+}
+
+/// Object keys a type didn't know when it was decoded, kept under the [`Preserve`](enum.UnknownFields.html) policy,
+/// so that relaying a message through an older version keeps newer fields intact.
+///
+/// Serialized alongside the known fields, which take precedence should a key be both,
+/// and compared as serialized, so equality is an equivalence even for values holding NaNs.
+#[derive(Debug, Clone, Default)]
+pub struct Extensions(pub BTreeMap<String, Value>);
+
+/// Types whose decoders honor every [`UnknownFields`](enum.UnknownFields.html) policy,
+/// keeping unknown keys in their [`Extensions`](struct.Extensions.html) under `Preserve`.
+///
+/// Only these can be given a policy; all other types ignore unknown keys.
+pub trait Extensible: 'static {
+	/// The unknown keys kept when the value was decoded
+	fn extensions(&self) -> &Extensions;
+}
+
+
+impl Extensions {
+	/// Add the extensions to `map`, without overwriting anything already there
+	pub fn fill(&self, map: &mut Map<String, Value>) {
+		for (key, value) in &self.0 {
+			map.entry(key.clone()).or_insert_with(|| value.clone());
+		}
+	}
+}
+
+impl Deref for Extensions {
+	type Target = BTreeMap<String, Value>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl DerefMut for Extensions {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.0
+	}
+}
+
+impl PartialEq for Extensions {
+	fn eq(&self, other: &Extensions) -> bool {
+		self.0.len() == other.0.len() && serde_json::to_string(&self.0).ok() == serde_json::to_string(&other.0).ok()
+	}
+}
+
+impl Eq for Extensions {}
+
+
+/// Apply `T`'s unknown field policy in `options` to the keys of `map` that aren't in its field table `known`
+pub fn extensions<T: Extensible>(mut map: Map<String, Value>, known: &[FieldSpec], options: &JsonOptions) -> Result<Extensions, JsonError> {
+	for field in known {
+		map.remove(field.name);
+	}
+
+	match options.unknown_fields::<T>() {
+		UnknownFields::Ignore => Ok(Extensions::default()),
+		UnknownFields::Reject =>
+			match map.keys().next() {
+				Some(key) => Err(JsonError::unknown_field(key)),
+				None      => Ok(Extensions::default()),
+			},
+		UnknownFields::Preserve => Ok(Extensions(map)),
+	}
+}
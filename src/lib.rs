@@ -6,10 +6,54 @@
 extern crate time;
 extern crate serde;
 extern crate serde_json;
+extern crate sha2;
+extern crate hmac;
+extern crate unicode_normalization;
+extern crate unicode_security;
+extern crate unicode_segmentation;
+#[cfg(feature = "time03")]
+extern crate time03;
+#[cfg(feature = "modern-serde")]
+extern crate serde1;
+#[cfg(feature = "modern-serde")]
+extern crate serde_json1;
 
 mod user;
+mod poster;
 mod message;
+mod blob;
+mod mention;
+mod payload;
+mod command;
+mod nick;
+mod name;
+mod policy;
+mod role;
+mod moderation;
+mod ratelimit;
+mod filter;
+mod audit;
+mod privacy;
+mod skew;
+mod timestamp;
 pub mod json;
+pub mod markup;
 
 pub use self::user::*;
+pub use self::poster::*;
 pub use self::message::*;
+pub use self::blob::*;
+pub use self::mention::*;
+pub use self::payload::*;
+pub use self::command::*;
+pub use self::nick::*;
+pub use self::name::*;
+pub use self::policy::*;
+pub use self::role::*;
+pub use self::moderation::*;
+pub use self::ratelimit::*;
+pub use self::filter::*;
+pub use self::audit::*;
+pub use self::privacy::*;
+pub use self::skew::*;
+pub use self::timestamp::*;
@@ -0,0 +1,682 @@
+//! Lightweight rich-text markup for message contents.
+//!
+//! The supported subset is:
+//!
+//! | Markup                        | Meaning                                  |
+//! |-------------------------------|------------------------------------------|
+//! | `**bold**`                    | [`Inline::Bold`](enum.Inline.html)       |
+//! | `*italic*`                    | [`Inline::Italic`](enum.Inline.html)     |
+//! | `~~strikethrough~~`           | [`Inline::Strike`](enum.Inline.html)     |
+//! | `` `code` ``                  | [`Inline::Code`](enum.Inline.html)       |
+//! | `[text](url)`                 | [`Inline::Link`](enum.Inline.html)       |
+//! | lines of `` ``` `` fences      | [`Block::CodeBlock`](enum.Block.html)    |
+//! | lines starting with `>`       | [`Block::Quote`](enum.Block.html)        |
+//!
+//! Any of ``\ * ~ ` [ ] >`` can be backslash-escaped to appear literally.
+//! Formatting delimiters can't be followed (when opening) or preceded (when closing) by whitespace,
+//! and unterminated markup is just text, so parsing never fails.
+//! Quotes, formatting and links nest at most [`MAX_NESTING`](constant.MAX_NESTING.html) levels deep, and markup any deeper is just text.
+//!
+//! Every client is expected to render messages with the renderers here,
+//! and [`Document::to_markup()`](struct.Document.html#method.to_markup) produces canonical markup that parses back to the same document.
+//!
+//! # Examples
+//!
+//! ```
+//! use chattium_oxide_lib::markup;
+//!
+//! let doc = markup::parse("**hi** <there>\n> `quoted`");
+//! assert_eq!(doc.to_plain(), "hi <there>\n> quoted");
+//! assert_eq!(doc.to_html(), "<strong>hi</strong> &lt;there&gt;\n<blockquote><code>quoted</code></blockquote>");
+//! assert_eq!(markup::parse(&doc.to_markup()), doc);
+//! ```
+
+
+/// Inline, i.e. in-line, formatting
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Inline {
+	Text(String),
+	Bold(Vec<Inline>),
+	Italic(Vec<Inline>),
+	Strike(Vec<Inline>),
+	Code(String),
+	Link{
+		text: Vec<Inline>,
+		url: String,
+	},
+}
+
+/// Block-level formatting, spanning whole lines
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Block {
+	/// A single line of text
+	Line(Vec<Inline>),
+	/// A fenced, verbatim block of code, optionally tagged with its language
+	CodeBlock{
+		lang: Option<String>,
+		code: String,
+	},
+	/// Consecutive `>`-prefixed lines
+	Quote(Vec<Block>),
+}
+
+/// Parsed message contents
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Document(pub Vec<Block>);
+
+/// Where the spans of a line end, found once for the whole line rather than again at every nesting level
+struct Spans {
+	/// See `find_closers()`
+	closers: Vec<Option<usize>>,
+	/// The `]` closing the `[` at each offset, once looked up by `match_bracket()`
+	brackets: Vec<Option<Option<usize>>>,
+	/// The `)` closing the `(` at each offset
+	parens: Vec<Option<usize>>,
+}
+
+
+/// How many levels deep quotes, formatting and links can nest
+pub const MAX_NESTING: usize = 32;
+
+
+static ESCAPABLE: &str = "\\*~`[]>";
+static DELIMITERS: &[&str] = &["**", "~~", "*"];
+static SAFE_SCHEMES: &[&str] = &["http://", "https://", "ftp://", "mailto:"];
+
+
+/// Parse markup into a [`Document`](struct.Document.html)
+pub fn parse(markup: &str) -> Document {
+	let lines: Vec<&str> = markup.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).collect();
+	Document(parse_blocks(&lines, 0))
+}
+
+
+impl Document {
+	/// Render to canonical markup, which parses back to `self`
+	pub fn to_markup(&self) -> String {
+		let mut out = String::new();
+		markup_blocks(&self.0, "", &mut out);
+		out
+	}
+
+	/// Render to plain text with all formatting removed, links as `text (url)`, and quotes `> `-prefixed
+	pub fn to_plain(&self) -> String {
+		let mut out = String::new();
+		plain_blocks(&self.0, "", &mut out);
+		out
+	}
+
+	/// Render to an HTML fragment.
+	///
+	/// All text is escaped and only `http`, `https`, `ftp` and `mailto` links are made into anchors,
+	/// so the output is safe to embed as-is.
+	pub fn to_html(&self) -> String {
+		let mut out = String::new();
+		html_blocks(&self.0, &mut out);
+		out
+	}
+
+	/// Render for an ANSI terminal, with all control characters from the message replaced by `U+FFFD`
+	pub fn to_ansi(&self) -> String {
+		let mut out = String::new();
+		ansi_blocks(&self.0, "", &mut out);
+		out
+	}
+}
+
+
+fn parse_blocks(lines: &[&str], depth: usize) -> Vec<Block> {
+	let mut blocks = Vec::new();
+	let mut i = 0;
+
+	while i < lines.len() {
+		let line = lines[i];
+		if let Some(lang) = line.strip_prefix("```").filter(|lang| !lang.contains('`')) {
+			let mut code = Vec::new();
+			i += 1;
+			while i < lines.len() && lines[i].trim_end() != "```" {
+				code.push(lines[i]);
+				i += 1;
+			}
+			i += 1;
+
+			let lang = lang.trim();
+			blocks.push(Block::CodeBlock{
+				lang: if lang.is_empty() { None } else { Some(lang.to_string()) },
+				code: code.join("\n"),
+			});
+		} else if line.starts_with('>') && depth < MAX_NESTING {
+			let mut quoted = Vec::new();
+			while i < lines.len() && lines[i].starts_with('>') {
+				let inner = &lines[i][1..];
+				quoted.push(inner.strip_prefix(' ').unwrap_or(inner));
+				i += 1;
+			}
+			blocks.push(Block::Quote(parse_blocks(&quoted, depth + 1)));
+		} else {
+			blocks.push(Block::Line(parse_inlines(line)));
+			i += 1;
+		}
+	}
+
+	blocks
+}
+
+fn parse_inlines(line: &str) -> Vec<Inline> {
+	let mut spans = Spans{
+		closers: find_closers(line),
+		brackets: vec![None; line.len() + 1],
+		parens: match_parens(line),
+	};
+	parse_span(line, 0, line.len(), 0, &mut spans)
+}
+
+/// Parse the inlines between `start` and `end` of `line`, nested `depth` spans deep
+fn parse_span(line: &str, start: usize, end: usize, depth: usize, spans: &mut Spans) -> Vec<Inline> {
+	let mut inlines = Vec::new();
+	let mut text = String::new();
+	let mut i = start;
+
+	while i < end {
+		let rest = &line[i..end];
+		let c = rest.chars().next().unwrap();
+
+		if c == '\\' {
+			if let Some(escaped) = rest[1..].chars().next().filter(|e| ESCAPABLE.contains(*e)) {
+				text.push(escaped);
+				i += 1 + escaped.len_utf8();
+				continue;
+			}
+		} else if c == '`' {
+			if let Some((code, len)) = parse_code(rest) {
+				push_inline(&mut inlines, &mut text, Inline::Code(code));
+				i += len;
+				continue;
+			}
+		} else if let Some(delim) = delimiter(rest).filter(|_| depth < MAX_NESTING) {
+			if let Some(close) = spans.closers[i].filter(|&close| close + delim.len() <= end) {
+				let inner = parse_span(line, i + delim.len(), close, depth + 1, spans);
+				push_inline(&mut inlines,
+				            &mut text,
+				            match delim {
+					            "**" => Inline::Bold(inner),
+					            "~~" => Inline::Strike(inner),
+					            _    => Inline::Italic(inner),
+				            });
+				i = close + delim.len();
+				continue;
+			}
+		} else if c == '[' && depth < MAX_NESTING {
+			if let Some((text_end, url_end)) = find_link(line, i, end, spans) {
+				push_inline(&mut inlines,
+				            &mut text,
+				            Inline::Link{
+					            text: parse_span(line, i + 1, text_end, depth + 1, spans),
+					            url: line[text_end + 2..url_end].to_string(),
+				            });
+				i = url_end + 1;
+				continue;
+			}
+		}
+
+		text.push(c);
+		i += c.len_utf8();
+	}
+
+	if !text.is_empty() {
+		inlines.push(Inline::Text(text));
+	}
+	inlines
+}
+
+fn push_inline(inlines: &mut Vec<Inline>, text: &mut String, inline: Inline) {
+	if !text.is_empty() {
+		inlines.push(Inline::Text(text.clone()));
+		text.clear();
+	}
+	inlines.push(inline);
+}
+
+/// Skip over an escape or a code span at the start of `s`, if any, returning its length
+fn skip_verbatim(s: &str) -> Option<usize> {
+	if let Some(escaped) = s.strip_prefix('\\') {
+		escaped.chars().next().filter(|e| ESCAPABLE.contains(*e)).map(|e| 1 + e.len_utf8())
+	} else if s.starts_with('`') {
+		parse_code(s).map(|(_, len)| len)
+	} else {
+		None
+	}
+}
+
+/// Find the byte offset where the span opened by the delimiter at each offset of `s` is closed, if it is.
+///
+/// Spans can't be empty nor start or end with whitespace.
+/// Nested spans are skipped over whole, so their delimiters can't close the outer one.
+///
+/// Where a span closes only depends on what follows it, so this is a single pass from the end;
+/// searching forward from each delimiter instead takes exponential time on unclosed nested ones.
+fn find_closers(s: &str) -> Vec<Option<usize>> {
+	let mut closers = vec![None; s.len() + 1];
+	// Where scanning continues from each offset, past escapes, code and whole nested spans
+	let mut skip = vec![s.len(); s.len() + 1];
+	// The first delimiter on the scan from each offset that can close each of DELIMITERS
+	let mut next = vec![[None; 3]; s.len() + 1];
+
+	for j in (0..s.len()).rev().filter(|&j| s.is_char_boundary(j)) {
+		let rest = &s[j..];
+		let token = delimiter(rest);
+
+		skip[j] = if let Some(len) = skip_verbatim(rest) {
+			j + len
+		} else if let Some(token) = token {
+			let from = j + token.len();
+			if opens(rest, token) {
+				closers[j] = next[skip[from]][DELIMITERS.iter().position(|&d| d == token).unwrap()];
+			}
+			closers[j].map(|end| end + token.len()).unwrap_or(from)
+		} else {
+			j + rest.chars().next().unwrap().len_utf8()
+		};
+
+		next[j] = next[skip[j]];
+		if let Some(token) = token.filter(|_| !s[..j].ends_with(char::is_whitespace)) {
+			for (closer, &delim) in next[j].iter_mut().zip(DELIMITERS) {
+				// A closing `*` may be directly followed by the end of an enclosing bold span, as in `**bold *italic***`
+				if token == delim || delim == "*" {
+					*closer = Some(j);
+				}
+			}
+		}
+	}
+
+	closers
+}
+
+/// The formatting delimiter at the start of `s`, if any
+fn delimiter(s: &str) -> Option<&'static str> {
+	if s.starts_with("**") {
+		Some("**")
+	} else if s.starts_with("~~") {
+		Some("~~")
+	} else if s.starts_with('*') {
+		Some("*")
+	} else {
+		None
+	}
+}
+
+fn opens(s: &str, delim: &str) -> bool {
+	s[delim.len()..].chars().next().map(|c| !c.is_whitespace()).unwrap_or(false)
+}
+
+/// Parse a run of N backticks, some code, and a closing run of exactly N backticks,
+/// returning the code and the total length
+fn parse_code(s: &str) -> Option<(String, usize)> {
+	let fence = s.len() - s.trim_start_matches('`').len();
+	let mut j = fence;
+	while let Some(off) = s[j..].find('`') {
+		let start = j + off;
+		let run = s[start..].len() - s[start..].trim_start_matches('`').len();
+		if run == fence {
+			let code = &s[fence..start];
+			if code.is_empty() {
+				return None;
+			}
+			let code = if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ') && code.trim() != "" {
+				&code[1..code.len() - 1]
+			} else {
+				code
+			};
+			return Some((code.to_string(), start + run));
+		}
+		j = start + run;
+	}
+	None
+}
+
+/// Find the offsets of the `]` and final `)` of a `[text](url)` link opened at `start` of `line`, if it ends before `end`
+fn find_link(line: &str, start: usize, end: usize, spans: &mut Spans) -> Option<(usize, usize)> {
+	let text_end = match_bracket(line, start, spans).filter(|&text_end| text_end < end)?;
+	if text_end == start + 1 || !line[text_end..end].starts_with("](") {
+		return None;
+	}
+
+	let url_end = spans.parens[text_end + 1].filter(|&url_end| url_end < end)?;
+	if url_end == text_end + 2 {
+		None
+	} else {
+		Some((text_end, url_end))
+	}
+}
+
+/// Find the `]` closing the `[` at `start` of `line`, skipping escapes and code.
+///
+/// The brackets nested in it are matched on the way, so each part of the line is scanned once, however deep the brackets nest.
+fn match_bracket(line: &str, start: usize, spans: &mut Spans) -> Option<usize> {
+	if let Some(found) = spans.brackets[start] {
+		return found;
+	}
+
+	let mut open = vec![start];
+	let mut j = start + 1;
+	while let Some(rest) = line.get(j..).filter(|r| !r.is_empty()) {
+		if let Some(len) = skip_verbatim(rest) {
+			j += len;
+			continue;
+		}
+
+		let c = rest.chars().next().unwrap();
+		if c == '[' {
+			open.push(j);
+		} else if c == ']' {
+			let opened = open.pop().unwrap();
+			spans.brackets[opened] = Some(Some(j));
+			if open.is_empty() {
+				return Some(j);
+			}
+		}
+		j += c.len_utf8();
+	}
+
+	for opened in open {
+		spans.brackets[opened] = Some(None);
+	}
+	None
+}
+
+/// Find the `)` closing the `(` at each offset of `line`, if it is
+fn match_parens(line: &str) -> Vec<Option<usize>> {
+	let mut parens = vec![None; line.len() + 1];
+	let mut open = Vec::new();
+	for (j, c) in line.char_indices() {
+		if c == '(' {
+			open.push(j);
+		} else if c == ')' {
+			if let Some(opened) = open.pop() {
+				parens[opened] = Some(j);
+			}
+		}
+	}
+	parens
+}
+
+
+fn markup_blocks(blocks: &[Block], prefix: &str, out: &mut String) {
+	for (i, block) in blocks.iter().enumerate() {
+		if i != 0 {
+			out.push('\n');
+		}
+		match *block {
+			Block::Line(ref inlines) => {
+				out.push_str(prefix);
+				let start = out.len();
+				markup_inlines(inlines, out);
+				if out[start..].starts_with('>') {
+					out.insert(start, '\\');
+				}
+			},
+			Block::CodeBlock{ref lang, ref code} => {
+				out.push_str(prefix);
+				out.push_str("```");
+				if let Some(ref lang) = *lang {
+					out.push_str(lang);
+				}
+				for line in code.split('\n') {
+					out.push('\n');
+					out.push_str(prefix);
+					out.push_str(line);
+				}
+				out.push('\n');
+				out.push_str(prefix);
+				out.push_str("```");
+			},
+			Block::Quote(ref quoted) => markup_blocks(quoted, &(prefix.to_string() + "> "), out),
+		}
+	}
+}
+
+fn markup_inlines(inlines: &[Inline], out: &mut String) {
+	for inline in inlines {
+		match *inline {
+			Inline::Text(ref text) => {
+				for c in text.chars() {
+					if ESCAPABLE.contains(c) {
+						out.push('\\');
+					}
+					out.push(c);
+				}
+			},
+			Inline::Bold(ref inner) => markup_delimited("**", inner, out),
+			Inline::Italic(ref inner) => markup_delimited("*", inner, out),
+			Inline::Strike(ref inner) => markup_delimited("~~", inner, out),
+			Inline::Code(ref code) => {
+				let longest_run = code.split(|c| c != '`').map(|run| run.len()).max().unwrap_or(0);
+				let fence = "`".repeat(longest_run + 1);
+				let pad = if code.starts_with('`') || code.ends_with('`') || (code.starts_with(' ') && code.ends_with(' ') && code.trim() != "") {
+					" "
+				} else {
+					""
+				};
+				out.push_str(&fence);
+				out.push_str(pad);
+				out.push_str(code);
+				out.push_str(pad);
+				out.push_str(&fence);
+			},
+			Inline::Link{ref text, ref url} => {
+				out.push('[');
+				markup_inlines(text, out);
+				out.push_str("](");
+				out.push_str(url);
+				out.push(')');
+			},
+		}
+	}
+}
+
+fn markup_delimited(delim: &str, inner: &[Inline], out: &mut String) {
+	out.push_str(delim);
+	markup_inlines(inner, out);
+	out.push_str(delim);
+}
+
+
+fn plain_blocks(blocks: &[Block], prefix: &str, out: &mut String) {
+	for (i, block) in blocks.iter().enumerate() {
+		if i != 0 {
+			out.push('\n');
+		}
+		match *block {
+			Block::Line(ref inlines) => {
+				out.push_str(prefix);
+				plain_inlines(inlines, out);
+			},
+			Block::CodeBlock{ref code, ..} => {
+				for (i, line) in code.split('\n').enumerate() {
+					if i != 0 {
+						out.push('\n');
+					}
+					out.push_str(prefix);
+					out.push_str(line);
+				}
+			},
+			Block::Quote(ref quoted) => plain_blocks(quoted, &(prefix.to_string() + "> "), out),
+		}
+	}
+}
+
+fn plain_inlines(inlines: &[Inline], out: &mut String) {
+	for inline in inlines {
+		match *inline {
+			Inline::Text(ref text) |
+			Inline::Code(ref text) => out.push_str(text),
+			Inline::Bold(ref inner) |
+			Inline::Italic(ref inner) |
+			Inline::Strike(ref inner) => plain_inlines(inner, out),
+			Inline::Link{ref text, ref url} => {
+				let start = out.len();
+				plain_inlines(text, out);
+				if out[start..] != url[..] {
+					out.push_str(" (");
+					out.push_str(url);
+					out.push(')');
+				}
+			},
+		}
+	}
+}
+
+
+fn html_blocks(blocks: &[Block], out: &mut String) {
+	let mut prev_line = false;
+	for (i, block) in blocks.iter().enumerate() {
+		match *block {
+			Block::Line(ref inlines) => {
+				if prev_line {
+					out.push_str("<br>");
+				}
+				if i != 0 {
+					out.push('\n');
+				}
+				html_inlines(inlines, out);
+			},
+			Block::CodeBlock{ref lang, ref code} => {
+				if i != 0 {
+					out.push('\n');
+				}
+				match *lang {
+					Some(ref lang) => {
+						out.push_str("<pre><code class=\"language-");
+						html_escape(lang, out);
+						out.push_str("\">");
+					},
+					None => out.push_str("<pre><code>"),
+				}
+				html_escape(code, out);
+				out.push_str("</code></pre>");
+			},
+			Block::Quote(ref quoted) => {
+				if i != 0 {
+					out.push('\n');
+				}
+				out.push_str("<blockquote>");
+				html_blocks(quoted, out);
+				out.push_str("</blockquote>");
+			},
+		}
+		prev_line = matches!(*block, Block::Line(_));
+	}
+}
+
+fn html_inlines(inlines: &[Inline], out: &mut String) {
+	for inline in inlines {
+		match *inline {
+			Inline::Text(ref text) => html_escape(text, out),
+			Inline::Bold(ref inner) => html_tagged("strong", inner, out),
+			Inline::Italic(ref inner) => html_tagged("em", inner, out),
+			Inline::Strike(ref inner) => html_tagged("del", inner, out),
+			Inline::Code(ref code) => {
+				out.push_str("<code>");
+				html_escape(code, out);
+				out.push_str("</code>");
+			},
+			Inline::Link{ref text, ref url} => {
+				if is_safe_url(url) {
+					out.push_str("<a href=\"");
+					html_escape(url, out);
+					out.push_str("\" rel=\"nofollow noopener noreferrer\">");
+					html_inlines(text, out);
+					out.push_str("</a>");
+				} else {
+					html_inlines(text, out);
+				}
+			},
+		}
+	}
+}
+
+fn html_tagged(tag: &str, inner: &[Inline], out: &mut String) {
+	out.push('<');
+	out.push_str(tag);
+	out.push('>');
+	html_inlines(inner, out);
+	out.push_str("</");
+	out.push_str(tag);
+	out.push('>');
+}
+
+fn html_escape(text: &str, out: &mut String) {
+	for c in text.chars() {
+		match c {
+			'&'  => out.push_str("&amp;"),
+			'<'  => out.push_str("&lt;"),
+			'>'  => out.push_str("&gt;"),
+			'"'  => out.push_str("&quot;"),
+			'\'' => out.push_str("&#39;"),
+			c    => out.push(c),
+		}
+	}
+}
+
+fn is_safe_url(url: &str) -> bool {
+	SAFE_SCHEMES.iter().any(|scheme| url.get(..scheme.len()).map(|pref| pref.eq_ignore_ascii_case(scheme)).unwrap_or(false))
+}
+
+
+fn ansi_blocks(blocks: &[Block], prefix: &str, out: &mut String) {
+	for (i, block) in blocks.iter().enumerate() {
+		if i != 0 {
+			out.push('\n');
+		}
+		match *block {
+			Block::Line(ref inlines) => {
+				out.push_str(prefix);
+				ansi_inlines(inlines, out);
+			},
+			Block::CodeBlock{ref code, ..} => {
+				for (i, line) in code.split('\n').enumerate() {
+					if i != 0 {
+						out.push('\n');
+					}
+					out.push_str(prefix);
+					out.push_str("\x1b[36m");
+					ansi_sanitize(line, out);
+					out.push_str("\x1b[39m");
+				}
+			},
+			Block::Quote(ref quoted) => ansi_blocks(quoted, &(prefix.to_string() + "\x1b[2m\u{2502}\x1b[22m "), out),
+		}
+	}
+}
+
+fn ansi_inlines(inlines: &[Inline], out: &mut String) {
+	for inline in inlines {
+		match *inline {
+			Inline::Text(ref text) => ansi_sanitize(text, out),
+			Inline::Bold(ref inner) => ansi_styled("\x1b[1m", "\x1b[22m", inner, out),
+			Inline::Italic(ref inner) => ansi_styled("\x1b[3m", "\x1b[23m", inner, out),
+			Inline::Strike(ref inner) => ansi_styled("\x1b[9m", "\x1b[29m", inner, out),
+			Inline::Code(ref code) => {
+				out.push_str("\x1b[36m");
+				ansi_sanitize(code, out);
+				out.push_str("\x1b[39m");
+			},
+			Inline::Link{ref text, ref url} => {
+				ansi_styled("\x1b[4m", "\x1b[24m", text, out);
+				out.push_str(" (");
+				ansi_sanitize(url, out);
+				out.push(')');
+			},
+		}
+	}
+}
+
+fn ansi_styled(on: &str, off: &str, inner: &[Inline], out: &mut String) {
+	out.push_str(on);
+	ansi_inlines(inner, out);
+	out.push_str(off);
+}
+
+fn ansi_sanitize(text: &str, out: &mut String) {
+	out.extend(text.chars().map(|c| if c.is_control() && c != '\t' { '\u{FFFD}' } else { c }));
+}
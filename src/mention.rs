@@ -0,0 +1,175 @@
+use ChatUser;
+
+
+/// What a [`Span`](struct.Span.html) of message text refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpanKind {
+	/// `@name`, refers to a user
+	Mention,
+	/// `#room`, refers to a room
+	Room,
+	/// `scheme://…` or `www.…`
+	Url,
+}
+
+/// A highlightable part of message text, as found by [`parse_spans()`](fn.parse_spans.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+	pub kind: SpanKind,
+	/// Byte offset of the first character, including the `@`/`#` sigil
+	pub start: usize,
+	/// Byte offset one past the last character
+	pub end: usize,
+}
+
+
+static URL_SCHEMES: &[&str] = &["https://", "http://", "ftp://", "www."];
+
+
+impl Span {
+	/// The spanned text, sigil included
+	pub fn text<'t>(&self, source: &'t str) -> &'t str {
+		&source[self.start..self.end]
+	}
+
+	/// The referenced name, i.e. the spanned text sans the `@`/`#` sigil
+	pub fn target<'t>(&self, source: &'t str) -> &'t str {
+		match self.kind {
+			SpanKind::Mention | SpanKind::Room => &source[self.start + 1..self.end],
+			SpanKind::Url                      => self.text(source),
+		}
+	}
+}
+
+
+/// Find all `@name` mentions, `#room` references and URLs in `text`, in order of appearance.
+///
+/// Sigils only count at the start of a word, so e-mail addresses and `C#` aren't matched,
+/// and trailing punctuation (as in `@name, hi` or `see http://example.com.`) is never part of a span.
+/// Names may contain any Unicode alphanumerics, as well as `_`, and `-` or `.` inside them.
+pub fn parse_spans(text: &str) -> Vec<Span> {
+	let mut spans = Vec::new();
+	let mut prev: Option<char> = None;
+	let mut idx = 0;
+
+	while idx < text.len() {
+		let rest = &text[idx..];
+		let c = rest.chars().next().unwrap();
+		let at_boundary = prev.map(|p| !is_name_char(p) && p != '@' && p != '#' && p != '/').unwrap_or(true);
+
+		if at_boundary {
+			let span = match c {
+				'@' => name_span(text, idx, SpanKind::Mention),
+				'#' => name_span(text, idx, SpanKind::Room),
+				_   => url_span(text, idx),
+			};
+			if let Some(span) = span {
+				idx = span.end;
+				prev = text[..idx].chars().next_back();
+				spans.push(span);
+				continue;
+			}
+		}
+
+		idx += c.len_utf8();
+		prev = Some(c);
+	}
+
+	spans
+}
+
+/// Pair every mention in `text` with the user it refers to, if any.
+///
+/// Names are compared case-insensitively; unresolved mentions are skipped.
+pub fn resolve_mentions<'u>(text: &str, users: &'u [ChatUser]) -> Vec<(Span, &'u ChatUser)> {
+	parse_spans(text).into_iter()
+	                 .filter(|span| span.kind == SpanKind::Mention)
+	                 .filter_map(|span| {
+		                 let name = span.target(text);
+		                 users.iter().find(|user| names_match(user.name(), name)).map(|user| (span, user))
+	                 })
+	                 .collect()
+}
+
+/// Check whether `text` mentions `user`, comparing names case-insensitively
+pub fn mentions(text: &str, user: &ChatUser) -> bool {
+	parse_spans(text).into_iter().any(|span| span.kind == SpanKind::Mention && names_match(user.name(), span.target(text)))
+}
+
+
+fn names_match(lhs: &str, rhs: &str) -> bool {
+	lhs.to_lowercase() == rhs.to_lowercase()
+}
+
+fn is_name_char(c: char) -> bool {
+	c.is_alphanumeric() || c == '_' || is_combining(c)
+}
+
+fn is_inner_name_char(c: char) -> bool {
+	c == '-' || c == '.'
+}
+
+// Combining marks have no Alphabetic property, but are part of the name they decorate
+fn is_combining(c: char) -> bool {
+	matches!(c as u32, 0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F)
+}
+
+fn name_span(text: &str, start: usize, kind: SpanKind) -> Option<Span> {
+	let mut end = start + 1;
+	for (off, c) in text[start + 1..].char_indices() {
+		if is_name_char(c) {
+			end = start + 1 + off + c.len_utf8();
+		} else if end == start + 1 || !is_inner_name_char(c) {
+			break;
+		}
+	}
+
+	if end == start + 1 {
+		None
+	} else {
+		Some(Span{
+			kind: kind,
+			start: start,
+			end: end,
+		})
+	}
+}
+
+fn url_span(text: &str, start: usize) -> Option<Span> {
+	let rest = &text[start..];
+	let scheme = URL_SCHEMES.iter().find(|scheme| rest.get(..scheme.len()).map(|pref| pref.eq_ignore_ascii_case(scheme)).unwrap_or(false))?;
+
+	let mut end = start + rest.find(char::is_whitespace).unwrap_or(rest.len());
+	let mut parens = 0i32;
+	let mut brackets = 0i32;
+	for c in text[start..end].chars() {
+		match c {
+			'(' => parens += 1,
+			')' => parens -= 1,
+			'[' => brackets += 1,
+			']' => brackets -= 1,
+			_   => {},
+		}
+	}
+
+	// Strip trailing punctuation, and closing brackets that weren't opened inside the URL
+	while let Some(c) = text[..end].chars().next_back() {
+		match c {
+			'.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | '>' | '\u{2019}' | '\u{201D}' => {},
+			')' if parens < 0 => parens += 1,
+			']' if brackets < 0 => brackets += 1,
+			_ => break,
+		}
+		end -= c.len_utf8();
+	}
+
+	if end - start <= scheme.len() {
+		None
+	} else {
+		Some(Span{
+			kind: SpanKind::Url,
+			start: start,
+			end: end,
+		})
+	}
+}
@@ -1,79 +1,141 @@
 use ChatUser;
+use ClockSkew;
+use {Timestamp, TimestampBackend};
+use {PosterMask, PosterPrivacy};
+use mention;
+use markup::{self, Document};
 use std::ops::DerefMut;
-use json::{FromJsonnable, ToJsonnable};
-use time::{now_utc, Tm};
+use json::{self, Extensible, Extensions, Field, FieldSpec, FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use time::Duration;
 use serde::de::{Error, Type};
-use serde_json::value::Value;
+use serde_json::value::{Map, Value};
 use serde_json::error::Error as JsonError;
-use serde_json::builder::ObjectBuilder;
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChatMessage {
 	pub sender: ChatUser,
 	pub value: String,
-	pub time_posted: Tm,
+	/// When the sender claims to have posted the message, by their clock
+	pub time_posted: Timestamp,
+	/// When the server received the message, by its clock, filled in server-side using [`receive()`](#method.receive)
+	pub time_received: Option<Timestamp>,
 	pub id: u64,
+	/// Fields this version doesn't know about, kept if decoded under [`UnknownFields::Preserve`](json/enum.UnknownFields.html)
+	pub extensions: Extensions,
 }
 
 
+const SENDER: Field<ChatUser> = Field::required("sender");
+const VALUE: Field<String> = Field::required("value");
+const TIME_POSTED: Field<Timestamp> = Field::required("time_posted");
+const TIME_RECEIVED: Field<Option<Timestamp>> = Field::optional("time_received");
+// Left out for 0
+const ID: Field<Option<u64>> = Field::optional("id");
+
+static FIELDS: [FieldSpec; 5] = [SENDER.spec(), VALUE.spec(), TIME_POSTED.spec(), TIME_RECEIVED.spec(), ID.spec()];
+
+
 impl ChatMessage {
 	pub fn new(by: ChatUser, contents: String) -> ChatMessage {
 		ChatMessage{
 			sender: by,
 			value: contents,
-			time_posted: now_utc(),
-			id: 0
+			time_posted: Timestamp::now(),
+			time_received: None,
+			id: 0,
+			extensions: Extensions::default(),
+		}
+	}
+
+	/// Server-side function to stamp the message as received at `now`.
+	///
+	/// Always call this on messages from clients, as it overwrites whatever `time_received` they claimed.
+	pub fn receive(&mut self, now: Timestamp) {
+		self.time_received = Some(now);
+	}
+
+	/// How far ahead of its receipt the message claims to have been posted, if it was received
+	pub fn claimed_skew(&self) -> Option<Duration> {
+		self.time_received.map(|received| self.time_posted.since(&received))
+	}
+
+	/// The time to order messages by: when the server received it, or when it was posted if it wasn't received yet
+	pub fn order_time(&self) -> Timestamp {
+		self.time_received.unwrap_or(self.time_posted)
+	}
+
+	/// Check whether the message claims to have been posted more than `threshold` before or after it was received,
+	/// correcting for the sender's clock `skew`, if known
+	pub fn is_skewed(&self, threshold: Duration, skew: Option<&ClockSkew>) -> bool {
+		match self.corrected_skew(skew) {
+			Some(difference) => difference > threshold || difference < -threshold,
+			None => false,
 		}
 	}
 
+	/// Like [`claimed_skew()`](#method.claimed_skew), but correcting for the sender's clock `skew`, if known
+	pub fn corrected_skew(&self, skew: Option<&ClockSkew>) -> Option<Duration> {
+		self.time_received.map(|received| {
+			let posted = self.time_posted.to_tm();
+			let posted = skew.map(|skew| skew.to_local(&posted)).unwrap_or(posted);
+			posted - received.to_tm()
+		})
+	}
+
 	/// Can be used with, say, `Rwlock<u64>.write().unwrap()`
 	pub fn fill_id<IdFiller: DerefMut<Target=u64>>(&mut self, mut curid: IdFiller) {
 		self.id = *curid;
 		*curid += 1;
 	}
+
+	/// Check whether the message's contents `@mention` the specified user, see [`parse_spans()`](fn.parse_spans.html)
+	pub fn mentions(&self, user: &ChatUser) -> bool {
+		mention::mentions(&self.value, user)
+	}
+
+	/// Parse the message's contents as [markup](markup/index.html)
+	pub fn markup(&self) -> Document {
+		markup::parse(&self.value)
+	}
+
+	/// Serialize with the sender's poster masked, see [`ChatUser::to_json_masked()`](struct.ChatUser.html#method.to_json_masked)
+	pub fn to_json_masked(&self, privacy: &PosterPrivacy, mask: PosterMask) -> Value {
+		self.to_json_masked_with(privacy, mask, &JsonOptions::default())
+	}
+
+	/// Serialize with the sender's poster masked, with `options`
+	pub fn to_json_masked_with(&self, privacy: &PosterPrivacy, mask: PosterMask, options: &JsonOptions) -> Value {
+		let mut json = self.to_json_with(options);
+		if let Value::Object(ref mut map) = json {
+			map.insert(SENDER.name.to_string(), self.sender.to_json_masked(privacy, mask));
+		}
+		json
+	}
+}
+
+impl Extensible for ChatMessage {
+	fn extensions(&self) -> &Extensions {
+		&self.extensions
+	}
 }
 
 impl FromJsonnable for ChatMessage {
 	fn from_json(json: Value) -> Result<Self, JsonError> {
-		match json {
-			Value::Object(map) => {
-				let sender = try!(
-					match map.get("sender") {
-						Some(sender) => ChatUser::from_json(sender.clone()),
-						None         => Err(JsonError::missing_field("Missing \"sender\"")),
-					});
-				let value =
-					match map.get("value") {
-						Some(value) =>
-							match value {
-								&Value::String(ref value) => value,
-								_                         => return Err(JsonError::invalid_type(Type::String)),
-							},
-						None => return Err(JsonError::missing_field("Missing \"value\"")),
-					};
-				let time_posted = try!(
-					match map.get("time_posted") {
-						Some(time_posted) => Tm::from_json(time_posted.clone()),
-						None              => Err(JsonError::missing_field("Missing \"time_posted\"")),
-					});
-				let id =
-					match map.get("id") {
-						Some(id) =>
-							match id {
-								&Value::U64(ref id) => *id,
-								_                   => return Err(JsonError::invalid_type(Type::U64)),
-							},
-						None => 0,
-					};
+		Self::from_json_with(json, &JsonOptions::default())
+	}
 
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
 				Ok(ChatMessage{
-					sender: sender,
-					value: value.clone(),
-					time_posted: time_posted,
-					id: id,
-				})
-			},
+					sender: try!(SENDER.decode(&map, options)),
+					value: try!(VALUE.decode(&map, options)),
+					time_posted: try!(TIME_POSTED.decode(&map, options)),
+					time_received: try!(TIME_RECEIVED.decode(&map, options)),
+					id: try!(ID.decode(&map, options)).unwrap_or(0),
+					extensions: try!(json::extensions::<ChatMessage>(map, &FIELDS, options)),
+				}),
 			_ => Err(JsonError::invalid_type(Type::Struct)),
 		}
 	}
@@ -81,14 +143,27 @@ impl FromJsonnable for ChatMessage {
 
 impl ToJsonnable for ChatMessage {
 	fn to_json(&self) -> Value {
-		let builder = ObjectBuilder::new().insert("sender"     , &self.sender.to_json())
-		                                  .insert("value"      , &self.value)
-		                                  .insert("time_posted", &self.time_posted.to_json());
+		self.to_json_with(&JsonOptions::default())
+	}
 
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		SENDER.encode(&mut map, &self.sender, options);
+		VALUE.encode(&mut map, &self.value, options);
+		TIME_POSTED.encode(&mut map, &self.time_posted, options);
+		if self.time_received.is_some() {
+			TIME_RECEIVED.encode(&mut map, &self.time_received, options);
+		}
 		if self.id != 0 {
-			builder.insert("id", &self.id)
-		} else {
-			builder
-		}.build()
+			ID.encode(&mut map, &Some(self.id), options);
+		}
+		self.extensions.fill(&mut map);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for ChatMessage {
+	fn json_schema() -> Value {
+		json::fields_schema(&FIELDS)
 	}
 }
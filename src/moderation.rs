@@ -0,0 +1,442 @@
+use std::fmt;
+use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use ChatUser;
+use {PosterMask, PosterPrivacy};
+use Payload;
+use fold_name;
+use time::Tm;
+use json::{self, Field, FieldSpec, FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use serde::de::{Error, Type};
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+use serde_json::builder::ObjectBuilder;
+
+
+/// A moderator's action against whoever `target` matches, carried by [`Payload::Kick`, `Payload::Ban` and `Payload::Mute`](enum.Payload.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moderation {
+	pub target: Target,
+	pub reason: Option<String>,
+	pub issuer: ChatUser,
+	/// When the action stops applying, or `None` for never
+	pub expires: Option<Tm>,
+}
+
+/// Who a [`Moderation`](struct.Moderation.html) applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+	/// A single user, by name, compared as by [`fold_name()`](fn.fold_name.html)
+	User(String),
+	/// Users whose folded name matches a glob pattern, where `*` matches any run of characters and `?` any single one
+	NamePattern(String),
+	/// Users posting from an address in the range
+	Address(AddressRange),
+}
+
+/// An IP address range in CIDR notation, like `"192.0.2.0/24"` or `"2001:db8::/32"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressRange {
+	network: IpAddr,
+	prefix: u8,
+}
+
+/// Bans and mutes currently in force.
+///
+/// Kicks are one-off, so they aren't tracked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModerationList {
+	bans: Vec<Moderation>,
+	mutes: Vec<Moderation>,
+}
+
+
+const TARGET: Field<Target> = Field::required("target");
+const REASON: Field<Option<String>> = Field::optional("reason");
+const ISSUER: Field<ChatUser> = Field::required("issuer");
+const EXPIRES: Field<Option<Tm>> = Field::optional("expires");
+
+static FIELDS: [FieldSpec; 4] = [TARGET.spec(), REASON.spec(), ISSUER.spec(), EXPIRES.spec()];
+
+const BANS: Field<Vec<Moderation>> = Field::required("bans");
+const MUTES: Field<Vec<Moderation>> = Field::required("mutes");
+
+static LIST_FIELDS: [FieldSpec; 2] = [BANS.spec(), MUTES.spec()];
+
+
+impl Moderation {
+	/// Check whether the action is still in force at `now`
+	pub fn is_active(&self, now: &Tm) -> bool {
+		match self.expires {
+			Some(ref expires) => now.to_timespec() < expires.to_timespec(),
+			None              => true,
+		}
+	}
+
+	/// Check whether the action is in force against `user` at `now`
+	pub fn applies_to(&self, user: &ChatUser, now: &Tm) -> bool {
+		self.is_active(now) && self.target.matches(user)
+	}
+
+	pub(crate) fn json_fields(&self, map: &mut Map<String, Value>, options: &JsonOptions) {
+		TARGET.encode(map, &self.target, options);
+		REASON.encode(map, &self.reason, options);
+		ISSUER.encode(map, &self.issuer, options);
+		EXPIRES.encode(map, &self.expires, options);
+	}
+
+	pub(crate) fn mask_issuer(&self, map: &mut Map<String, Value>, privacy: &PosterPrivacy, mask: PosterMask) {
+		map.insert(ISSUER.name.to_string(), self.issuer.to_json_masked(privacy, mask));
+	}
+}
+
+impl Target {
+	/// Check whether `user` is targeted
+	pub fn matches(&self, user: &ChatUser) -> bool {
+		match *self {
+			Target::User(ref name)        => fold_name(name) == fold_name(user.name()),
+			Target::NamePattern(ref glob) => glob_matches(&fold_name(glob).chars().collect::<Vec<_>>(), &fold_name(user.name()).chars().collect::<Vec<_>>()),
+			Target::Address(ref range)    => user.poster().map(|poster| range.contains(poster.address)).unwrap_or(false),
+		}
+	}
+}
+
+impl AddressRange {
+	/// Create a range of addresses sharing the first `prefix` bits with `address`.
+	///
+	/// IPv4-mapped IPv6 addresses are turned into IPv4, with 96 taken off the prefix,
+	/// the same way [`contains()`](#method.contains) treats them.
+	///
+	/// Returns `None` if the prefix is longer than the address, or shorter than 96 for an IPv4-mapped address.
+	pub fn new(address: IpAddr, prefix: u8) -> Option<AddressRange> {
+		let (address, prefix) =
+			match address {
+				IpAddr::V6(v6) =>
+					match v6.to_ipv4_mapped() {
+						Some(v4) if prefix >= 96 => (IpAddr::V4(v4), prefix - 96),
+						Some(_)                  => return None,
+						None                     => (address, prefix),
+					},
+				IpAddr::V4(_) => (address, prefix),
+			};
+		let network =
+			match address {
+				IpAddr::V4(v4) if prefix <= 32 => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask(prefix, 32) as u32)),
+				IpAddr::V6(v6) if prefix <= 128 => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask(prefix, 128))),
+				_ => return None,
+			};
+		Some(AddressRange{
+			network: network,
+			prefix: prefix,
+		})
+	}
+
+	/// A range containing only `address`
+	pub fn single(address: IpAddr) -> AddressRange {
+		let prefix = if address.is_ipv4() {32} else {128};
+		AddressRange::new(address, prefix).unwrap()
+	}
+
+	/// The first address of the range
+	pub fn network(&self) -> IpAddr {
+		self.network
+	}
+
+	/// The amount of leading bits fixed by the range
+	pub fn prefix(&self) -> u8 {
+		self.prefix
+	}
+
+	/// Check whether `address` is in the range, treating IPv4-mapped IPv6 addresses as IPv4
+	pub fn contains(&self, address: IpAddr) -> bool {
+		let address =
+			match address {
+				IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+				IpAddr::V4(_)  => address,
+			};
+		AddressRange::new(address, self.prefix).map(|range| range.network == self.network).unwrap_or(false)
+	}
+}
+
+impl ModerationList {
+	/// Create a list with nothing in force
+	pub fn new() -> ModerationList {
+		ModerationList::default()
+	}
+
+	pub fn ban(&mut self, ban: Moderation) {
+		self.bans.push(ban);
+	}
+
+	pub fn mute(&mut self, mute: Moderation) {
+		self.mutes.push(mute);
+	}
+
+	/// Lift all bans with exactly `target`, returning how many there were
+	pub fn unban(&mut self, target: &Target) -> usize {
+		remove_target(&mut self.bans, target)
+	}
+
+	/// Lift all mutes with exactly `target`, returning how many there were
+	pub fn unmute(&mut self, target: &Target) -> usize {
+		remove_target(&mut self.mutes, target)
+	}
+
+	/// Record a [`Payload::Ban` or `Payload::Mute`](enum.Payload.html), returning whether the payload was either
+	pub fn record(&mut self, payload: &Payload) -> bool {
+		match *payload {
+			Payload::Ban(ref ban)   => self.ban(ban.clone()),
+			Payload::Mute(ref mute) => self.mute(mute.clone()),
+			_ => return false,
+		}
+		true
+	}
+
+	/// Get a ban in force against `user` at `now`, if any
+	pub fn banned(&self, user: &ChatUser, now: &Tm) -> Option<&Moderation> {
+		self.bans.iter().find(|ban| ban.applies_to(user, now))
+	}
+
+	/// Get a mute in force against `user` at `now`, if any
+	pub fn muted(&self, user: &ChatUser, now: &Tm) -> Option<&Moderation> {
+		self.mutes.iter().find(|mute| mute.applies_to(user, now))
+	}
+
+	/// Check whether `user` is banned at `now`
+	pub fn is_banned(&self, user: &ChatUser, now: &Tm) -> bool {
+		self.banned(user, now).is_some()
+	}
+
+	/// Check whether `user` is muted at `now`
+	pub fn is_muted(&self, user: &ChatUser, now: &Tm) -> bool {
+		self.muted(user, now).is_some()
+	}
+
+	/// Check whether `address` is banned by address at `now`, e.g. to refuse connections before a name is known
+	pub fn is_address_banned(&self, address: IpAddr, now: &Tm) -> bool {
+		self.bans.iter().any(|ban|
+			ban.is_active(now) &&
+			match ban.target {
+				Target::Address(ref range) => range.contains(address),
+				_ => false,
+			})
+	}
+
+	/// Forget everything expired at `now`, returning how many entries were removed
+	pub fn prune(&mut self, now: &Tm) -> usize {
+		let before = self.bans.len() + self.mutes.len();
+		self.bans.retain(|ban| ban.is_active(now));
+		self.mutes.retain(|mute| mute.is_active(now));
+		before - self.bans.len() - self.mutes.len()
+	}
+
+	pub fn bans(&self) -> &[Moderation] {
+		&self.bans
+	}
+
+	pub fn mutes(&self) -> &[Moderation] {
+		&self.mutes
+	}
+}
+
+
+fn mask(prefix: u8, bits: u8) -> u128 {
+	match prefix {
+		0 => 0,
+		prefix => (!0u128 << (bits - prefix)) & (!0u128 >> (128 - bits as u32)),
+	}
+}
+
+// Iterative, going back only to the character after the last `*` and letting it match one more character,
+// so a pattern with many `*`s takes O(glob × name) rather than exponential time
+fn glob_matches(glob: &[char], name: &[char]) -> bool {
+	let (mut g, mut n) = (0, 0);
+	let mut backtrack = None;
+
+	while n < name.len() {
+		match glob.get(g) {
+			Some(&'*') => {
+				backtrack = Some((g, n));
+				g += 1;
+			},
+			Some(&c) if c == '?' || c == name[n] => {
+				g += 1;
+				n += 1;
+			},
+			_ => match backtrack {
+				Some((star, matched)) => {
+					backtrack = Some((star, matched + 1));
+					g = star + 1;
+					n = matched + 1;
+				},
+				None => return false,
+			},
+		}
+	}
+
+	glob[g..].iter().all(|&c| c == '*')
+}
+
+fn remove_target(entries: &mut Vec<Moderation>, target: &Target) -> usize {
+	let before = entries.len();
+	entries.retain(|entry| entry.target != *target);
+	before - entries.len()
+}
+
+
+impl fmt::Display for AddressRange {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}/{}", self.network, self.prefix)
+	}
+}
+
+impl FromStr for AddressRange {
+	type Err = ();
+
+	/// Parse `"address/prefix"`, or a bare address as a single-address range
+	fn from_str(s: &str) -> Result<AddressRange, ()> {
+		match s.find('/') {
+			Some(idx) => {
+				let address = try!(s[..idx].parse().map_err(|_| ()));
+				let prefix = try!(s[idx + 1..].parse().map_err(|_| ()));
+				AddressRange::new(address, prefix).ok_or(())
+			},
+			None => s.parse().map(AddressRange::single).map_err(|_| ()),
+		}
+	}
+}
+
+
+impl FromJsonnable for Moderation {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
+				Ok(Moderation{
+					target: try!(TARGET.decode(&map, options)),
+					reason: try!(REASON.decode(&map, options)),
+					issuer: try!(ISSUER.decode(&map, options)),
+					expires: try!(EXPIRES.decode(&map, options)),
+				}),
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for Moderation {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		self.json_fields(&mut map, options);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for Moderation {
+	fn json_schema() -> Value {
+		json::fields_schema(&FIELDS)
+	}
+}
+
+impl FromJsonnable for Target {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				if map.len() != 1 {
+					return Err(JsonError::invalid_length(map.len()));
+				}
+				if map.contains_key("user") {
+					Ok(Target::User(try!(json::string_field(&map, "user"))))
+				} else if map.contains_key("pattern") {
+					Ok(Target::NamePattern(try!(json::string_field(&map, "pattern"))))
+				} else if map.contains_key("address") {
+					Ok(Target::Address(try!(json::field(&map, "address", &JsonOptions::default()))))
+				} else {
+					Err(JsonError::unknown_variant(map.keys().next().unwrap()))
+				}
+			},
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for Target {
+	fn to_json(&self) -> Value {
+		match *self {
+			Target::User(ref name)        => ObjectBuilder::new().insert("user", name),
+			Target::NamePattern(ref glob) => ObjectBuilder::new().insert("pattern", glob),
+			Target::Address(ref range)    => ObjectBuilder::new().insert("address", range.to_string()),
+		}.build()
+	}
+}
+
+impl JsonSchema for Target {
+	fn json_schema() -> Value {
+		json::any_of_schema(vec![json::single_field_schema("user", String::json_schema()),
+		                         json::single_field_schema("pattern", String::json_schema()),
+		                         json::single_field_schema("address", AddressRange::json_schema())])
+	}
+}
+
+impl FromJsonnable for AddressRange {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(range) => range.parse().map_err(|_| JsonError::invalid_value(&format!("\"{}\" is not an address range", range))),
+			_                    => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for AddressRange {
+	fn to_json(&self) -> Value {
+		Value::String(self.to_string())
+	}
+}
+
+impl JsonSchema for AddressRange {
+	fn json_schema() -> Value {
+		String::json_schema()
+	}
+}
+
+impl FromJsonnable for ModerationList {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
+				Ok(ModerationList{
+					bans: try!(BANS.decode(&map, options)),
+					mutes: try!(MUTES.decode(&map, options)),
+				}),
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for ModerationList {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		BANS.encode(&mut map, &self.bans, options);
+		MUTES.encode(&mut map, &self.mutes, options);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for ModerationList {
+	fn json_schema() -> Value {
+		json::fields_schema(&LIST_FIELDS)
+	}
+}
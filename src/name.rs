@@ -0,0 +1,169 @@
+use std::fmt;
+use std::ops::Deref;
+use std::error::Error;
+use unicode_segmentation::UnicodeSegmentation;
+
+
+/// A display name that passed [`validate_name()`](fn.validate_name.html)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValidatedName(String);
+
+/// Rules display names are validated against.
+///
+/// Control characters are never allowed, everything else is configurable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NamePolicy {
+	/// Minimal length, in grapheme clusters
+	pub min_length: usize,
+	/// Maximal length, in grapheme clusters
+	pub max_length: usize,
+	/// Whether the name can start or end with whitespace
+	pub allow_edge_whitespace: bool,
+	/// Whether the name can contain bidirectional formatting characters, like `U+202E RIGHT-TO-LEFT OVERRIDE`
+	pub allow_bidi_controls: bool,
+	/// Whether the name can contain zero-width characters, like `U+200B ZERO WIDTH SPACE`
+	pub allow_zero_width: bool,
+	/// Additional characters not to allow
+	pub banned_chars: Vec<char>,
+}
+
+/// Why a name didn't pass [`validate_name()`](fn.validate_name.html)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NameError {
+	TooShort{
+		min: usize,
+		length: usize,
+	},
+	TooLong{
+		max: usize,
+		length: usize,
+	},
+	EdgeWhitespace,
+	ControlCharacter(char),
+	BidiControl(char),
+	ZeroWidth(char),
+	BannedCharacter(char),
+}
+
+
+/// Validate `name` against `policy`.
+///
+/// # Examples
+///
+/// ```
+/// use chattium_oxide_lib::{validate_name, NameError, NamePolicy};
+///
+/// let policy = NamePolicy::default();
+/// assert_eq!(&*validate_name("żółw", &policy).unwrap(), "żółw");
+/// assert_eq!(validate_name("evil\nname", &policy), Err(NameError::ControlCharacter('\n')));
+/// assert_eq!(validate_name(" spacey", &policy), Err(NameError::EdgeWhitespace));
+/// ```
+pub fn validate_name(name: &str, policy: &NamePolicy) -> Result<ValidatedName, NameError> {
+	for c in name.chars() {
+		if c.is_control() {
+			return Err(NameError::ControlCharacter(c));
+		}
+		if !policy.allow_bidi_controls && is_bidi_control(c) {
+			return Err(NameError::BidiControl(c));
+		}
+		if !policy.allow_zero_width && is_zero_width(c) {
+			return Err(NameError::ZeroWidth(c));
+		}
+		if policy.banned_chars.contains(&c) {
+			return Err(NameError::BannedCharacter(c));
+		}
+	}
+
+	if !policy.allow_edge_whitespace && (name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace)) {
+		return Err(NameError::EdgeWhitespace);
+	}
+
+	let length = name.graphemes(true).count();
+	if length < policy.min_length {
+		Err(NameError::TooShort{
+			min: policy.min_length,
+			length: length,
+		})
+	} else if length > policy.max_length {
+		Err(NameError::TooLong{
+			max: policy.max_length,
+			length: length,
+		})
+	} else {
+		Ok(ValidatedName(name.to_string()))
+	}
+}
+
+
+impl ValidatedName {
+	/// Validate `name` against the default [`NamePolicy`](struct.NamePolicy.html)
+	pub fn new(name: &str) -> Result<ValidatedName, NameError> {
+		validate_name(name, &NamePolicy::default())
+	}
+
+	/// Unwrap the validated name
+	pub fn into_inner(self) -> String {
+		self.0
+	}
+}
+
+impl Deref for ValidatedName {
+	type Target = str;
+
+	fn deref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl fmt::Display for ValidatedName {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl From<ValidatedName> for String {
+	fn from(name: ValidatedName) -> String {
+		name.0
+	}
+}
+
+
+impl Default for NamePolicy {
+	/// 1 to 32 grapheme clusters, with no whitespace at the edges, bidi controls, or zero-width characters
+	fn default() -> NamePolicy {
+		NamePolicy{
+			min_length: 1,
+			max_length: 32,
+			allow_edge_whitespace: false,
+			allow_bidi_controls: false,
+			allow_zero_width: false,
+			banned_chars: vec![],
+		}
+	}
+}
+
+
+impl fmt::Display for NameError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			NameError::TooShort{min, length} => write!(f, "name is {} characters long, needs to be at least {}", length, min),
+			NameError::TooLong{max, length}  => write!(f, "name is {} characters long, can be at most {}", length, max),
+			NameError::EdgeWhitespace        => write!(f, "name can't start or end with whitespace"),
+			NameError::ControlCharacter(c)   => write!(f, "name can't contain control character U+{:04X}", c as u32),
+			NameError::BidiControl(c)        => write!(f, "name can't contain bidirectional control U+{:04X}", c as u32),
+			NameError::ZeroWidth(c)          => write!(f, "name can't contain zero-width character U+{:04X}", c as u32),
+			NameError::BannedCharacter(c)    => write!(f, "name can't contain {:?}", c),
+		}
+	}
+}
+
+impl Error for NameError {}
+
+
+fn is_bidi_control(c: char) -> bool {
+	matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+fn is_zero_width(c: char) -> bool {
+	matches!(c, '\u{180E}' | '\u{200B}'..='\u{200D}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}')
+}
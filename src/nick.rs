@@ -0,0 +1,215 @@
+use std::fmt;
+use std::error::Error;
+use std::collections::HashMap;
+use json::{self, Field, FieldSpec, FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use serde::de::{Error as SerdeError, Type};
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+
+/// A user's request to change their name from `old` to `new`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NickChange {
+	pub old: String,
+	pub new: String,
+}
+
+/// Set of names in use, guaranteeing no two of them can be mistaken for one another.
+///
+/// Names collide if they're the same after NFKC normalization and case folding (`"Admin"` and `"ａｄｍｉｎ"`),
+/// or if they have the same confusable skeleton per [UTS #39](https://www.unicode.org/reports/tr39/#Confusable_Detection)
+/// (`"Admin"` and `"Аdmin"`, with a Cyrillic `А`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameRegistry {
+	/// Folded names to the registered name
+	folded: HashMap<String, String>,
+	/// Skeletons to the registered name
+	skeletons: HashMap<String, String>,
+}
+
+/// Why a name was refused by a [`NameRegistry`](struct.NameRegistry.html)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NameRejection {
+	/// The name is empty, or becomes empty after normalization
+	Empty,
+	/// The name is already in use, up to case and compatibility normalization, by `by`
+	Taken{
+		by: String,
+	},
+	/// The name is visually confusable with `with`, which is in use
+	Confusable{
+		with: String,
+	},
+	/// The name to change from isn't registered
+	NotRegistered(String),
+}
+
+
+const OLD: Field<String> = Field::required("old");
+const NEW: Field<String> = Field::required("new");
+
+static FIELDS: [FieldSpec; 2] = [OLD.spec(), NEW.spec()];
+
+
+/// Fold a name for case- and compatibility-insensitive comparison
+pub fn fold_name(name: &str) -> String {
+	name.nfkc().collect::<String>().to_lowercase().nfkc().collect()
+}
+
+/// Get a name's confusable skeleton, such that visually similar names have the same skeleton.
+///
+/// The skeleton is taken before lowercasing, which would lose confusables like `I` and `l`.
+pub fn name_skeleton(name: &str) -> String {
+	skeleton(&name.nfkc().collect::<String>()).collect::<String>().to_lowercase()
+}
+
+
+impl NameRegistry {
+	/// Create an empty registry
+	pub fn new() -> NameRegistry {
+		NameRegistry::default()
+	}
+
+	/// Check whether `name` could be registered, without registering it
+	pub fn check(&self, name: &str) -> Result<(), NameRejection> {
+		self.check_except(name, None)
+	}
+
+	/// Register `name`, if it doesn't collide with any name already registered
+	pub fn register(&mut self, name: &str) -> Result<(), NameRejection> {
+		try!(self.check(name));
+		self.insert(name);
+		Ok(())
+	}
+
+	/// Unregister `name`, or whatever name it folds to, returning the registered form if it was present
+	pub fn unregister(&mut self, name: &str) -> Option<String> {
+		match self.folded.remove(&fold_name(name)) {
+			Some(registered) => {
+				self.skeletons.remove(&name_skeleton(&registered));
+				Some(registered)
+			},
+			None => None,
+		}
+	}
+
+	/// Get the registered form of `name`, if a name folding to the same thing is registered
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.folded.get(&fold_name(name)).map(|registered| &registered[..])
+	}
+
+	/// Check whether exactly `name` is registered
+	pub fn contains(&self, name: &str) -> bool {
+		self.get(name) == Some(name)
+	}
+
+	/// Apply a name change.
+	///
+	/// `old` must be registered exactly, and `new` must not collide with any registered name except `old`,
+	/// so users can change the case of their own names.
+	pub fn change(&mut self, change: &NickChange) -> Result<(), NameRejection> {
+		if !self.contains(&change.old) {
+			return Err(NameRejection::NotRegistered(change.old.clone()));
+		}
+		try!(self.check_except(&change.new, Some(&change.old)));
+
+		self.unregister(&change.old);
+		self.insert(&change.new);
+		Ok(())
+	}
+
+	/// Amount of registered names
+	pub fn len(&self) -> usize {
+		self.folded.len()
+	}
+
+	/// Whether no names are registered
+	pub fn is_empty(&self) -> bool {
+		self.folded.is_empty()
+	}
+
+
+	fn check_except(&self, name: &str, except: Option<&str>) -> Result<(), NameRejection> {
+		let folded = fold_name(name);
+		if folded.trim().is_empty() {
+			return Err(NameRejection::Empty);
+		}
+
+		if let Some(by) = self.folded.get(&folded) {
+			if Some(&by[..]) != except {
+				return Err(NameRejection::Taken{by: by.clone()});
+			}
+		}
+		if let Some(with) = self.skeletons.get(&name_skeleton(name)) {
+			if Some(&with[..]) != except {
+				return Err(NameRejection::Confusable{with: with.clone()});
+			}
+		}
+		Ok(())
+	}
+
+	fn insert(&mut self, name: &str) {
+		self.folded.insert(fold_name(name), name.to_string());
+		self.skeletons.insert(name_skeleton(name), name.to_string());
+	}
+}
+
+
+impl fmt::Display for NameRejection {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			NameRejection::Empty                => write!(f, "name is empty"),
+			NameRejection::Taken{ref by}        => write!(f, "name is already taken by \"{}\"", by),
+			NameRejection::Confusable{ref with} => write!(f, "name looks too much like \"{}\"", with),
+			NameRejection::NotRegistered(ref n) => write!(f, "\"{}\" is not a registered name", n),
+		}
+	}
+}
+
+impl Error for NameRejection {}
+
+
+impl NickChange {
+	pub(crate) fn json_fields(&self, map: &mut Map<String, Value>, options: &JsonOptions) {
+		OLD.encode(map, &self.old, options);
+		NEW.encode(map, &self.new, options);
+	}
+}
+
+
+impl FromJsonnable for NickChange {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) =>
+				Ok(NickChange{
+					old: try!(OLD.decode(&map, options)),
+					new: try!(NEW.decode(&map, options)),
+				}),
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for NickChange {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		self.json_fields(&mut map, options);
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for NickChange {
+	fn json_schema() -> Value {
+		json::fields_schema(&FIELDS)
+	}
+}
@@ -0,0 +1,263 @@
+use ChatUser;
+use ChatMessage;
+use NickChange;
+use Moderation;
+use {PosterMask, PosterPrivacy};
+use time::{Duration, Tm};
+use json::{self, Field, FieldSpec, FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use serde::de::{Error, Type};
+use serde_json::value::{Map, Value};
+use serde_json::error::Error as JsonError;
+use serde_json::builder::ObjectBuilder;
+
+
+/// Everything clients and the server send each other, tagged with its `"type"` on the wire.
+///
+/// Unknown keys beside the tag are ignored, but the [`ChatMessage`](struct.ChatMessage.html)s and [`ChatUser`](struct.ChatUser.html)s in it
+/// decode with their own [unknown field policies](json/enum.UnknownFields.html), so relaying keeps whatever they preserved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+	/// A regular message to the current room
+	Message(ChatMessage),
+	/// An action performed by the sender, à la IRC's `/me`
+	Action(ChatMessage),
+	/// A message visible only to `to`
+	Private{
+		to: ChatUser,
+		message: ChatMessage,
+	},
+	/// Change the sender's name
+	Nick(NickChange),
+	Join{
+		room: String,
+	},
+	Part{
+		room: String,
+		reason: Option<String>,
+	},
+	Topic{
+		room: String,
+		topic: String,
+	},
+	/// Disconnect from the server
+	Quit{
+		reason: Option<String>,
+	},
+	/// Disconnect the target from the server
+	Kick(Moderation),
+	/// Disconnect the target and keep them from reconnecting
+	Ban(Moderation),
+	/// Keep the target from posting
+	Mute(Moderation),
+	/// Sent by the server when a message of `kind` was refused by a [`RateLimiter`](struct.RateLimiter.html).
+	///
+	/// `retry_after` is sent in whole milliseconds, rounded up.
+	SlowDown{
+		kind: String,
+		retry_after: Duration,
+	},
+	/// Ask the peer for its time, to measure its [`ClockSkew`](struct.ClockSkew.html); `time` is by the sender's clock
+	Ping{
+		time: Tm,
+	},
+	/// Answer to a `Ping` sent at `ping`, with the answerer's `time`
+	Pong{
+		ping: Tm,
+		time: Tm,
+	},
+}
+
+
+const TYPE: Field<String> = Field::required("type");
+const MESSAGE: Field<ChatMessage> = Field::required("message");
+const TO: Field<ChatUser> = Field::required("to");
+const ROOM: Field<String> = Field::required("room");
+const REASON: Field<Option<String>> = Field::optional("reason");
+const TOPIC: Field<String> = Field::required("topic");
+const KIND: Field<String> = Field::required("kind");
+const RETRY_AFTER: Field<i64> = Field::required("retry_after");
+const TIME: Field<Tm> = Field::required("time");
+const PING: Field<Tm> = Field::required("ping");
+
+static MESSAGE_FIELDS: [FieldSpec; 1] = [MESSAGE.spec()];
+static PRIVATE_FIELDS: [FieldSpec; 2] = [TO.spec(), MESSAGE.spec()];
+static JOIN_FIELDS: [FieldSpec; 1] = [ROOM.spec()];
+static PART_FIELDS: [FieldSpec; 2] = [ROOM.spec(), REASON.spec()];
+static TOPIC_FIELDS: [FieldSpec; 2] = [ROOM.spec(), TOPIC.spec()];
+static QUIT_FIELDS: [FieldSpec; 1] = [REASON.spec()];
+static SLOW_DOWN_FIELDS: [FieldSpec; 2] = [KIND.spec(), RETRY_AFTER.spec()];
+static PING_FIELDS: [FieldSpec; 1] = [TIME.spec()];
+static PONG_FIELDS: [FieldSpec; 2] = [PING.spec(), TIME.spec()];
+
+
+impl Payload {
+	/// The `"type"` tag the payload is serialized with
+	pub fn kind(&self) -> &'static str {
+		match *self {
+			Payload::Message(_)  => "message",
+			Payload::Action(_)   => "action",
+			Payload::Private{..} => "private",
+			Payload::Nick(_)     => "nick",
+			Payload::Join{..}    => "join",
+			Payload::Part{..}    => "part",
+			Payload::Topic{..}   => "topic",
+			Payload::Quit{..}    => "quit",
+			Payload::Kick(_)     => "kick",
+			Payload::Ban(_)      => "ban",
+			Payload::Mute(_)     => "mute",
+			Payload::SlowDown{..} => "slow_down",
+			Payload::Ping{..}    => "ping",
+			Payload::Pong{..}    => "pong",
+		}
+	}
+
+	/// Serialize with all users' posters masked, see [`ChatUser::to_json_masked()`](struct.ChatUser.html#method.to_json_masked)
+	pub fn to_json_masked(&self, privacy: &PosterPrivacy, mask: PosterMask) -> Value {
+		self.to_json_masked_with(privacy, mask, &JsonOptions::default())
+	}
+
+	/// Serialize with all users' posters masked, with `options`
+	pub fn to_json_masked_with(&self, privacy: &PosterPrivacy, mask: PosterMask, options: &JsonOptions) -> Value {
+		let mut json = self.to_json_with(options);
+		if let Value::Object(ref mut map) = json {
+			match *self {
+				Payload::Message(ref message) |
+				Payload::Action(ref message) => {
+					map.insert(MESSAGE.name.to_string(), message.to_json_masked_with(privacy, mask, options));
+				},
+				Payload::Private{ref to, ref message} => {
+					map.insert(TO.name.to_string(), to.to_json_masked(privacy, mask));
+					map.insert(MESSAGE.name.to_string(), message.to_json_masked_with(privacy, mask, options));
+				},
+				Payload::Kick(ref moderation) |
+				Payload::Ban(ref moderation) |
+				Payload::Mute(ref moderation) => moderation.mask_issuer(map, privacy, mask),
+				_ => {},
+			}
+		}
+		json
+	}
+}
+
+impl FromJsonnable for Payload {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<Self, JsonError> {
+		match json {
+			Value::Object(map) => {
+				let kind = try!(TYPE.decode(&map, options));
+				match &kind[..] {
+					"message" => Ok(Payload::Message(try!(MESSAGE.decode(&map, options)))),
+					"action"  => Ok(Payload::Action(try!(MESSAGE.decode(&map, options)))),
+					"private" =>
+						Ok(Payload::Private{
+							to: try!(TO.decode(&map, options)),
+							message: try!(MESSAGE.decode(&map, options)),
+						}),
+					"nick" => Ok(Payload::Nick(try!(NickChange::from_json_with(Value::Object(map), options)))),
+					"join" => Ok(Payload::Join{room: try!(ROOM.decode(&map, options))}),
+					"part" =>
+						Ok(Payload::Part{
+							room: try!(ROOM.decode(&map, options)),
+							reason: try!(REASON.decode(&map, options)),
+						}),
+					"topic" =>
+						Ok(Payload::Topic{
+							room: try!(ROOM.decode(&map, options)),
+							topic: try!(TOPIC.decode(&map, options)),
+						}),
+					"quit" => Ok(Payload::Quit{reason: try!(REASON.decode(&map, options))}),
+					"kick" => Ok(Payload::Kick(try!(Moderation::from_json_with(Value::Object(map), options)))),
+					"ban"  => Ok(Payload::Ban(try!(Moderation::from_json_with(Value::Object(map), options)))),
+					"mute" => Ok(Payload::Mute(try!(Moderation::from_json_with(Value::Object(map), options)))),
+					"slow_down" =>
+						Ok(Payload::SlowDown{
+							kind: try!(KIND.decode(&map, options)),
+							retry_after: Duration::milliseconds(try!(RETRY_AFTER.decode(&map, options))),
+						}),
+					"ping" => Ok(Payload::Ping{time: try!(TIME.decode(&map, options))}),
+					"pong" =>
+						Ok(Payload::Pong{
+							ping: try!(PING.decode(&map, options)),
+							time: try!(TIME.decode(&map, options)),
+						}),
+					kind   => Err(JsonError::unknown_variant(kind)),
+				}
+			},
+			_ => Err(JsonError::invalid_type(Type::Struct)),
+		}
+	}
+}
+
+impl ToJsonnable for Payload {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		let mut map = Map::new();
+		TYPE.encode(&mut map, &self.kind().to_string(), options);
+		match *self {
+			Payload::Message(ref message) |
+			Payload::Action(ref message) => MESSAGE.encode(&mut map, message, options),
+			Payload::Private{ref to, ref message} => {
+				TO.encode(&mut map, to, options);
+				MESSAGE.encode(&mut map, message, options);
+			},
+			Payload::Nick(ref change) => change.json_fields(&mut map, options),
+			Payload::Join{ref room} => ROOM.encode(&mut map, room, options),
+			Payload::Part{ref room, ref reason} => {
+				ROOM.encode(&mut map, room, options);
+				REASON.encode(&mut map, reason, options);
+			},
+			Payload::Topic{ref room, ref topic} => {
+				ROOM.encode(&mut map, room, options);
+				TOPIC.encode(&mut map, topic, options);
+			},
+			Payload::Quit{ref reason} => REASON.encode(&mut map, reason, options),
+			Payload::Kick(ref moderation) |
+			Payload::Ban(ref moderation) |
+			Payload::Mute(ref moderation) => moderation.json_fields(&mut map, options),
+			Payload::SlowDown{ref kind, retry_after} => {
+				KIND.encode(&mut map, kind, options);
+				RETRY_AFTER.encode(&mut map, &(retry_after + Duration::nanoseconds(999999)).num_milliseconds(), options);
+			},
+			Payload::Ping{ref time} => TIME.encode(&mut map, time, options),
+			Payload::Pong{ref ping, ref time} => {
+				PING.encode(&mut map, ping, options);
+				TIME.encode(&mut map, time, options);
+			},
+		}
+		Value::Object(map)
+	}
+}
+
+impl JsonSchema for Payload {
+	fn json_schema() -> Value {
+		json::any_of_schema(vec![
+			tagged_schema("message", json::fields_schema(&MESSAGE_FIELDS)),
+			tagged_schema("action", json::fields_schema(&MESSAGE_FIELDS)),
+			tagged_schema("private", json::fields_schema(&PRIVATE_FIELDS)),
+			tagged_schema("nick", NickChange::json_schema()),
+			tagged_schema("join", json::fields_schema(&JOIN_FIELDS)),
+			tagged_schema("part", json::fields_schema(&PART_FIELDS)),
+			tagged_schema("topic", json::fields_schema(&TOPIC_FIELDS)),
+			tagged_schema("quit", json::fields_schema(&QUIT_FIELDS)),
+			tagged_schema("kick", Moderation::json_schema()),
+			tagged_schema("ban", Moderation::json_schema()),
+			tagged_schema("mute", Moderation::json_schema()),
+			tagged_schema("slow_down", json::fields_schema(&SLOW_DOWN_FIELDS)),
+			tagged_schema("ping", json::fields_schema(&PING_FIELDS)),
+			tagged_schema("pong", json::fields_schema(&PONG_FIELDS)),
+		])
+	}
+}
+
+
+/// `schema` with the `"type"` tag set to `kind`
+fn tagged_schema(kind: &str, schema: Value) -> Value {
+	let tag = json::object_schema(&[(TYPE.name, ObjectBuilder::new().insert("const", kind).build())], &[]);
+	ObjectBuilder::new().insert("allOf", vec![tag, schema]).build()
+}
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::error::Error;
+use ChatMessage;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_normalization::char::is_combining_mark;
+
+
+/// Limits and sanitization applied to messages before they're sent or accepted.
+///
+/// Meant to be applied both client-side, before sending, and server-side, before accepting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessagePolicy {
+	/// Maximal length of the sanitized contents, in bytes
+	pub max_bytes: usize,
+	/// Maximal length of the sanitized contents, in grapheme clusters
+	pub max_graphemes: usize,
+	/// Maximal amount of lines
+	pub max_lines: usize,
+	/// Maximal amount of combining marks stacked on a single character
+	pub max_combining: usize,
+	/// Whether to remove control characters other than newlines and tabs
+	pub strip_control: bool,
+	/// Whether to convert `\r\n` and lone `\r` line endings to `\n`
+	pub normalize_line_endings: bool,
+	/// Whether to reject messages that are empty or consist only of whitespace
+	pub reject_blank: bool,
+}
+
+/// A reason a message was refused by a [`MessagePolicy`](struct.MessagePolicy.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageViolation {
+	/// Empty or whitespace-only
+	Blank,
+	TooManyBytes{
+		max: usize,
+		actual: usize,
+	},
+	TooManyGraphemes{
+		max: usize,
+		actual: usize,
+	},
+	TooManyLines{
+		max: usize,
+		actual: usize,
+	},
+	/// A character has more combining marks stacked on it than allowed
+	TooManyCombiningMarks{
+		max: usize,
+		actual: usize,
+	},
+}
+
+
+impl MessagePolicy {
+	/// Sanitize the message's contents, then check them against the limits.
+	///
+	/// Returns the sanitized message, with `id` and `time_posted` untouched, or all violated limits.
+	pub fn apply(&self, mut message: ChatMessage) -> Result<ChatMessage, Vec<MessageViolation>> {
+		message.value = self.sanitize(&message.value);
+		let violations = self.violations(&message.value);
+		if violations.is_empty() {
+			Ok(message)
+		} else {
+			Err(violations)
+		}
+	}
+
+	/// Strip control characters and normalize line endings, as configured
+	pub fn sanitize(&self, text: &str) -> String {
+		let text = if self.normalize_line_endings {
+			text.replace("\r\n", "\n").replace('\r', "\n")
+		} else {
+			text.to_string()
+		};
+
+		if self.strip_control {
+			text.chars().filter(|&c| !c.is_control() || c == '\n' || c == '\t' || (c == '\r' && !self.normalize_line_endings)).collect()
+		} else {
+			text
+		}
+	}
+
+	/// Check already-sanitized text against the limits, returning all that were exceeded
+	pub fn violations(&self, text: &str) -> Vec<MessageViolation> {
+		let mut violations = Vec::new();
+
+		if self.reject_blank && text.trim().is_empty() {
+			violations.push(MessageViolation::Blank);
+		}
+		if text.len() > self.max_bytes {
+			violations.push(MessageViolation::TooManyBytes{
+				max: self.max_bytes,
+				actual: text.len(),
+			});
+		}
+
+		let mut graphemes = 0;
+		let mut max_combining = 0;
+		for grapheme in text.graphemes(true) {
+			graphemes += 1;
+			max_combining = max_combining.max(grapheme.chars().filter(|&c| is_combining_mark(c)).count());
+		}
+		if graphemes > self.max_graphemes {
+			violations.push(MessageViolation::TooManyGraphemes{
+				max: self.max_graphemes,
+				actual: graphemes,
+			});
+		}
+		if max_combining > self.max_combining {
+			violations.push(MessageViolation::TooManyCombiningMarks{
+				max: self.max_combining,
+				actual: max_combining,
+			});
+		}
+
+		let lines = text.split('\n').count();
+		if lines > self.max_lines {
+			violations.push(MessageViolation::TooManyLines{
+				max: self.max_lines,
+				actual: lines,
+			});
+		}
+
+		violations
+	}
+}
+
+impl Default for MessagePolicy {
+	/// 4KiB, 2000 characters, 50 lines, 4 combining marks per character, with all sanitization enabled
+	fn default() -> MessagePolicy {
+		MessagePolicy{
+			max_bytes: 4096,
+			max_graphemes: 2000,
+			max_lines: 50,
+			max_combining: 4,
+			strip_control: true,
+			normalize_line_endings: true,
+			reject_blank: true,
+		}
+	}
+}
+
+
+impl fmt::Display for MessageViolation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MessageViolation::Blank                              => write!(f, "message is blank"),
+			MessageViolation::TooManyBytes{max, actual}          => write!(f, "message is {} bytes long, can be at most {}", actual, max),
+			MessageViolation::TooManyGraphemes{max, actual}      => write!(f, "message is {} characters long, can be at most {}", actual, max),
+			MessageViolation::TooManyLines{max, actual}          => write!(f, "message has {} lines, can have at most {}", actual, max),
+			MessageViolation::TooManyCombiningMarks{max, actual} => write!(f, "message stacks {} combining marks, can stack at most {}", actual, max),
+		}
+	}
+}
+
+impl Error for MessageViolation {}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+
+
+/// Where a user connected from: a network address, plus the connection's port, if known.
+///
+/// Only the address identifies the user; the port is specific to one connection,
+/// so it's never serialized by [`ChatUser::to_json()`](struct.ChatUser.html) or compared when matching bans.
+///
+/// IPv4-mapped IPv6 addresses, like a dual-stack server's `::ffff:192.0.2.1`, are stored as the IPv4 address they map,
+/// so the same client is the same poster whichever socket it connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Poster {
+	pub address: IpAddr,
+	/// IPv6 zone index, like the `3` in `fe80::1%3`, or `0` for none
+	pub scope_id: u32,
+	pub port: Option<u16>,
+}
+
+
+impl Poster {
+	/// A poster at `address`, with no scope ID or port
+	pub fn new(address: IpAddr) -> Poster {
+		Poster{
+			address: address,
+			scope_id: 0,
+			port: None,
+		}.unmapped()
+	}
+
+	/// The network address, like `"192.0.2.1"` or `"fe80::1%3"`, without the port
+	pub fn address_string(&self) -> String {
+		match (self.address, self.scope_id) {
+			(IpAddr::V6(v6), scope_id) if scope_id != 0 => format!("{}%{}", v6, scope_id),
+			(address, _) => address.to_string(),
+		}
+	}
+
+	/// The same poster, without the port
+	pub fn without_port(&self) -> Poster {
+		Poster{
+			port: None,
+			..*self
+		}
+	}
+
+	/// The full socket address, if the port is known
+	pub fn socket_addr(&self) -> Option<SocketAddr> {
+		self.port.map(|port|
+			match self.address {
+				IpAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, self.scope_id)),
+				IpAddr::V4(v4) => SocketAddr::new(IpAddr::V4(v4), port),
+			})
+	}
+
+
+	// IPv4-mapped addresses as IPv4, which has no scope IDs
+	fn unmapped(self) -> Poster {
+		match self.address {
+			IpAddr::V6(v6) =>
+				match v6.to_ipv4_mapped() {
+					Some(v4) => Poster{address: IpAddr::V4(v4), scope_id: 0, ..self},
+					None     => self,
+				},
+			IpAddr::V4(_) => self,
+		}
+	}
+
+	fn parse(s: &str) -> Result<Poster, ()> {
+		if s.starts_with('[') {
+			let end = try!(s.find(']').ok_or(()));
+			let mut poster = try!(Poster::parse_address(&s[1..end]).ok_or(()));
+			if !poster.address.is_ipv6() {
+				return Err(());
+			}
+			match &s[end + 1..] {
+				"" => {},
+				port if port.starts_with(':') => poster.port = Some(try!(port[1..].parse().map_err(|_| ()))),
+				_ => return Err(()),
+			}
+			Ok(poster)
+		} else if let Some(poster) = Poster::parse_address(s) {
+			Ok(poster)
+		} else {
+			let idx = try!(s.rfind(':').ok_or(()));
+			let mut poster = try!(Poster::parse_address(&s[..idx]).ok_or(()));
+			if !poster.address.is_ipv4() {
+				return Err(());
+			}
+			poster.port = Some(try!(s[idx + 1..].parse().map_err(|_| ())));
+			Ok(poster)
+		}
+	}
+
+	fn parse_address(s: &str) -> Option<Poster> {
+		let (address, scope_id) =
+			match s.find('%') {
+				Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+				None      => (s, None),
+			};
+
+		let address: IpAddr = match address.parse() {
+			Ok(address) => address,
+			Err(_)      => return None,
+		};
+		let scope_id =
+			match (address, scope_id) {
+				(_, None) => 0,
+				(IpAddr::V6(_), Some(scope_id)) =>
+					match scope_id.parse() {
+						Ok(scope_id) => scope_id,
+						Err(_)       => return None,
+					},
+				(IpAddr::V4(_), Some(_)) => return None,
+			};
+
+		Some(Poster{
+			address: address,
+			scope_id: scope_id,
+			port: None,
+		})
+	}
+}
+
+impl From<SocketAddr> for Poster {
+	fn from(addr: SocketAddr) -> Poster {
+		Poster{
+			address: addr.ip(),
+			scope_id: match addr {
+				SocketAddr::V6(ref v6) => v6.scope_id(),
+				SocketAddr::V4(_)      => 0,
+			},
+			port: Some(addr.port()),
+		}.unmapped()
+	}
+}
+
+impl From<IpAddr> for Poster {
+	fn from(addr: IpAddr) -> Poster {
+		Poster::new(addr)
+	}
+}
+
+impl fmt::Display for Poster {
+	/// `address`, `address:port` or `[address]:port` for IPv6
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match (self.port, self.address) {
+			(None, _)                   => f.write_str(&self.address_string()),
+			(Some(port), IpAddr::V4(_)) => write!(f, "{}:{}", self.address_string(), port),
+			(Some(port), IpAddr::V6(_)) => write!(f, "[{}]:{}", self.address_string(), port),
+		}
+	}
+}
+
+impl FromStr for Poster {
+	type Err = ();
+
+	/// Parse anything [`Display`](#impl-Display) produces, as well as a bracketed IPv6 address without a port
+	fn from_str(s: &str) -> Result<Poster, ()> {
+		Poster::parse(s).map(Poster::unmapped)
+	}
+}
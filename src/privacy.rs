@@ -0,0 +1,106 @@
+use std::fmt;
+use std::net::IpAddr;
+use Role;
+use Poster;
+use ChatUser;
+use Permissions;
+use AddressRange;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+
+/// How much of a user's `poster` address a viewer gets to see
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PosterMask {
+	/// Address and port, if known, like `"192.0.2.10:51234"`
+	Full,
+	/// Just the address, like `"192.0.2.10"`, as in [`ChatUser::to_json()`](struct.ChatUser.html)
+	IpOnly,
+	/// The address's /24 network for IPv4, or /48 for IPv6, like `"192.0.2.0/24"`
+	Truncated,
+	/// A keyed hash of the address, like IRC hostmasks, so the same address can be recognized without being revealed,
+	/// like `"cloak-3f9a0c4e81d2b7a5"`
+	Cloaked,
+	/// Nothing at all, the user is serialized as if their address weren't known
+	Omitted,
+}
+
+/// Which [`PosterMask`](enum.PosterMask.html) viewers with each role get, and the key to cloak addresses with.
+///
+/// By default, owners see full addresses, admins see them without ports, moderators see cloaks,
+/// and everyone else sees nothing.
+///
+/// The cloak key is left out of the `Debug` output, so it doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PosterPrivacy {
+	cloak_key: Vec<u8>,
+	/// Indexed by `Role as usize`
+	masks: [PosterMask; 5],
+}
+
+
+impl PosterMask {
+	/// Mask `poster` for display, or `None` for [`Omitted`](#variant.Omitted).
+	///
+	/// Cloaks are keyed by `cloak_key`, which should be kept secret and stable across restarts.
+	pub fn apply(&self, poster: &Poster, cloak_key: &[u8]) -> Option<String> {
+		let ip = canonical_ip(poster.address);
+		match *self {
+			PosterMask::Full      => Some(poster.to_string()),
+			PosterMask::IpOnly    => Some(poster.address_string()),
+			PosterMask::Truncated => AddressRange::new(ip, if ip.is_ipv4() {24} else {48}).map(|range| range.to_string()),
+			PosterMask::Cloaked   => {
+				let mut mac = Hmac::<Sha256>::new_from_slice(cloak_key).expect("HMAC takes keys of any size");
+				mac.update(ip.to_string().as_bytes());
+				Some(format!("cloak-{}", mac.finalize().into_bytes()[..8].iter().map(|b| format!("{:02x}", b)).collect::<String>()))
+			},
+			PosterMask::Omitted   => None,
+		}
+	}
+}
+
+impl PosterPrivacy {
+	/// Use the default masks, cloaking with `cloak_key`
+	pub fn new(cloak_key: &[u8]) -> PosterPrivacy {
+		PosterPrivacy{
+			cloak_key: cloak_key.to_vec(),
+			masks: [PosterMask::Omitted, PosterMask::Omitted, PosterMask::Cloaked, PosterMask::IpOnly, PosterMask::Full],
+		}
+	}
+
+	/// Show posters to viewers with `role` masked by `mask`
+	pub fn set_mask(&mut self, role: Role, mask: PosterMask) {
+		self.masks[role as usize] = mask;
+	}
+
+	/// Get the mask posters are shown with to viewers with `role`
+	pub fn mask(&self, role: Role) -> PosterMask {
+		self.masks[role as usize]
+	}
+
+	/// Get the mask posters are shown with to `viewer` in `room`
+	pub fn mask_for(&self, permissions: &Permissions, viewer: &ChatUser, room: &str) -> PosterMask {
+		self.mask(permissions.role(viewer, room))
+	}
+
+	/// Mask `poster` by `mask`, with this policy's cloak key
+	pub fn apply(&self, mask: PosterMask, poster: &Poster) -> Option<String> {
+		mask.apply(poster, &self.cloak_key)
+	}
+}
+
+
+impl fmt::Debug for PosterPrivacy {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("PosterPrivacy").field("cloak_key", &"<redacted>").field("masks", &self.masks).finish()
+	}
+}
+
+
+/// Treat IPv4-mapped IPv6 addresses as IPv4, so they're truncated and cloaked the same
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+		IpAddr::V4(_)  => ip,
+	}
+}
@@ -0,0 +1,221 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use ChatUser;
+use Payload;
+use fold_name;
+use time::{now_utc, Duration, Tm};
+
+
+/// A source of the current time, so limiters can be driven by a fake clock
+pub trait Clock {
+	fn now(&self) -> Tm;
+}
+
+/// The system's UTC clock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SystemClock;
+
+/// A clock that only moves when told to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManualClock(Cell<Tm>);
+
+/// A token bucket: up to `burst` messages at once, refilling one every `interval`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+	pub burst: u32,
+	pub interval: Duration,
+}
+
+/// Whether a message fits within the rate limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateDecision {
+	Allowed,
+	/// Refused, the sender should wait at least this long before retrying
+	RetryAfter(Duration),
+}
+
+/// Flood control, keyed both by user name and by the address they post from, so neither reconnecting under
+/// a different name nor posting from a different address gets around it.
+///
+/// Every message kind (as in [`Payload::kind()`](enum.Payload.html#method.kind)) gets its own buckets,
+/// limited by the kind's [`RateLimit`](struct.RateLimit.html), or the default one.
+#[derive(Debug, Clone)]
+pub struct RateLimiter<C: Clock = SystemClock> {
+	clock: C,
+	default_limit: RateLimit,
+	limits: HashMap<String, RateLimit>,
+	/// Theoretical arrival times of the next message, in nanoseconds since the epoch, as per GCRA
+	buckets: HashMap<(BucketKey, String), i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum BucketKey {
+	User(String),
+	Address(IpAddr),
+}
+
+
+impl Clock for SystemClock {
+	fn now(&self) -> Tm {
+		now_utc()
+	}
+}
+
+impl ManualClock {
+	/// Create a clock stopped at `now`
+	pub fn new(now: Tm) -> ManualClock {
+		ManualClock(Cell::new(now))
+	}
+
+	pub fn set(&self, now: Tm) {
+		self.0.set(now);
+	}
+
+	pub fn advance(&self, by: Duration) {
+		self.0.set(self.0.get() + by);
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Tm {
+		self.0.get()
+	}
+}
+
+impl<C: Clock> Clock for &C {
+	fn now(&self) -> Tm {
+		(*self).now()
+	}
+}
+
+
+impl RateLimit {
+	/// Allow `burst` messages at once, and `burst` more every `period`
+	pub fn per(burst: u32, period: Duration) -> RateLimit {
+		RateLimit{
+			burst: burst,
+			// Divided as i64, since bursts over i32::MAX would wrap
+			interval: match period.num_nanoseconds() {
+				Some(nanos) => Duration::nanoseconds(nanos / burst.max(1) as i64),
+				None        => Duration::milliseconds(period.num_milliseconds() / burst.max(1) as i64),
+			},
+		}
+	}
+}
+
+impl Default for RateLimit {
+	/// Bursts of 5, then one a second
+	fn default() -> RateLimit {
+		RateLimit{
+			burst: 5,
+			interval: Duration::seconds(1),
+		}
+	}
+}
+
+impl RateDecision {
+	pub fn is_allowed(&self) -> bool {
+		*self == RateDecision::Allowed
+	}
+
+	/// The [`Payload::SlowDown`](enum.Payload.html) to tell the client, if the message of `kind` was refused
+	pub fn slow_down(&self, kind: &str) -> Option<Payload> {
+		match *self {
+			RateDecision::Allowed               => None,
+			RateDecision::RetryAfter(duration) =>
+				Some(Payload::SlowDown{
+					kind: kind.to_string(),
+					retry_after: duration,
+				}),
+		}
+	}
+}
+
+
+impl RateLimiter<SystemClock> {
+	/// Create a limiter using the system clock, with `default_limit` for all message kinds
+	pub fn new(default_limit: RateLimit) -> RateLimiter<SystemClock> {
+		RateLimiter::with_clock(default_limit, SystemClock)
+	}
+}
+
+impl<C: Clock> RateLimiter<C> {
+	/// Create a limiter using `clock`, with `default_limit` for all message kinds
+	pub fn with_clock(default_limit: RateLimit, clock: C) -> RateLimiter<C> {
+		RateLimiter{
+			clock: clock,
+			default_limit: default_limit,
+			limits: HashMap::new(),
+			buckets: HashMap::new(),
+		}
+	}
+
+	/// Limit messages of `kind` by `limit` instead of the default limit
+	pub fn set_limit(&mut self, kind: &str, limit: RateLimit) {
+		self.limits.insert(kind.to_string(), limit);
+	}
+
+	/// Get the limit for messages of `kind`
+	pub fn limit(&self, kind: &str) -> RateLimit {
+		self.limits.get(kind).cloned().unwrap_or(self.default_limit)
+	}
+
+	/// Check whether `user` can send a message of `kind` at `now`, using up a token from their buckets if so
+	pub fn check(&mut self, user: &ChatUser, kind: &str, now: &Tm) -> RateDecision {
+		let limit = self.limit(kind);
+		let now = nanoseconds(now);
+		let interval = limit.interval.num_nanoseconds().unwrap_or(i64::MAX);
+		let tolerance = interval.saturating_mul(limit.burst as i64);
+
+		let mut keys = vec![BucketKey::User(fold_name(user.name()))];
+		if let Some(poster) = user.poster() {
+			keys.push(BucketKey::Address(poster.address));
+		}
+		let keys: Vec<_> = keys.into_iter().map(|key| (key, kind.to_string())).collect();
+
+		let mut wait = 0;
+		let mut arrivals = Vec::with_capacity(keys.len());
+		for key in &keys {
+			let arrival = self.buckets.get(key).cloned().unwrap_or(now).max(now).saturating_add(interval);
+			wait = wait.max(arrival - now - tolerance);
+			arrivals.push(arrival);
+		}
+
+		if wait > 0 {
+			RateDecision::RetryAfter(Duration::nanoseconds(wait))
+		} else {
+			self.buckets.extend(keys.into_iter().zip(arrivals));
+			RateDecision::Allowed
+		}
+	}
+
+	/// [`check()`](#method.check) a payload sent by `user` at `now`
+	pub fn check_payload(&mut self, user: &ChatUser, payload: &Payload, now: &Tm) -> RateDecision {
+		self.check(user, payload.kind(), now)
+	}
+
+	/// [`check()`](#method.check) against the limiter's clock
+	pub fn check_now(&mut self, user: &ChatUser, kind: &str) -> RateDecision {
+		let now = self.clock.now();
+		self.check(user, kind, &now)
+	}
+
+	/// Forget buckets that have refilled completely by `now`, returning how many were forgotten
+	pub fn prune(&mut self, now: &Tm) -> usize {
+		let now = nanoseconds(now);
+		let before = self.buckets.len();
+		self.buckets.retain(|_, &mut arrival| arrival > now);
+		before - self.buckets.len()
+	}
+
+	pub fn clock(&self) -> &C {
+		&self.clock
+	}
+}
+
+
+fn nanoseconds(time: &Tm) -> i64 {
+	let spec = time.to_timespec();
+	spec.sec.saturating_mul(1_000_000_000).saturating_add(spec.nsec as i64)
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+use std::str::FromStr;
+use std::collections::HashMap;
+use ChatUser;
+use fold_name;
+use json::{self, FromJsonnable, JsonSchema, ToJsonnable};
+use serde::de::{Error, Type};
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+
+
+/// Something a user can be allowed to do in a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Permission {
+	Post,
+	EditOwn,
+	EditAny,
+	DeleteAny,
+	Kick,
+	Ban,
+	SetTopic,
+	Invite,
+}
+
+/// Built-in roles, in ascending order of power
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Role {
+	Guest,
+	Member,
+	Moderator,
+	Admin,
+	Owner,
+}
+
+/// Who a permission override applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subject {
+	/// A single user, by name
+	User(String),
+	/// Everyone with the role
+	Role(Role),
+}
+
+/// Authoritative room-scoped role assignments and permission overrides.
+///
+/// A user's role in a room is their role assigned in that room, or their global role, or the default role.
+///
+/// [`can()`](#method.can) resolves a permission by looking for explicit overrides, most specific first:
+///
+///   1. for the user in the room,
+///   2. for the user globally,
+///   3. for the user's role in the room,
+///   4. for the user's role globally,
+///
+/// and falling back to the role's built-in permissions.
+/// Owners can do everything, regardless of overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+	default_role: Role,
+	/// Keyed by folded user name and, optionally, room
+	roles: HashMap<(String, Option<String>), Role>,
+	/// `true` for allowed, `false` for denied
+	overrides: HashMap<(SubjectKey, Option<String>, Permission), bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SubjectKey {
+	User(String),
+	Role(Role),
+}
+
+
+static ALL_PERMISSIONS: &[Permission] = &[Permission::Post, Permission::EditOwn, Permission::EditAny, Permission::DeleteAny, Permission::Kick,
+                                          Permission::Ban, Permission::SetTopic, Permission::Invite];
+
+
+impl Permission {
+	/// All permissions there are
+	pub fn all() -> &'static [Permission] {
+		ALL_PERMISSIONS
+	}
+
+	/// The name the permission is serialized as
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Permission::Post      => "post",
+			Permission::EditOwn   => "edit-own",
+			Permission::EditAny   => "edit-any",
+			Permission::DeleteAny => "delete-any",
+			Permission::Kick      => "kick",
+			Permission::Ban       => "ban",
+			Permission::SetTopic  => "set-topic",
+			Permission::Invite    => "invite",
+		}
+	}
+}
+
+impl Role {
+	/// All roles there are, in ascending order
+	pub fn all() -> &'static [Role] {
+		static ALL: &[Role] = &[Role::Guest, Role::Member, Role::Moderator, Role::Admin, Role::Owner];
+		ALL
+	}
+
+	/// Permissions the role has without any overrides
+	pub fn permissions(&self) -> &'static [Permission] {
+		match *self {
+			Role::Guest     => &[Permission::Post],
+			Role::Member    => &[Permission::Post, Permission::EditOwn, Permission::Invite],
+			Role::Moderator => &[Permission::Post, Permission::EditOwn, Permission::Invite, Permission::DeleteAny, Permission::Kick, Permission::SetTopic],
+			Role::Admin |
+			Role::Owner => ALL_PERMISSIONS,
+		}
+	}
+
+	/// Check whether the role has `permission` without any overrides
+	pub fn has(&self, permission: Permission) -> bool {
+		self.permissions().contains(&permission)
+	}
+
+	/// The name the role is serialized as
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Role::Guest     => "guest",
+			Role::Member    => "member",
+			Role::Moderator => "moderator",
+			Role::Admin     => "admin",
+			Role::Owner     => "owner",
+		}
+	}
+}
+
+
+impl Permissions {
+	/// Create a table with no assignments or overrides, where everyone has `default_role`
+	pub fn new(default_role: Role) -> Permissions {
+		Permissions{
+			default_role: default_role,
+			roles: HashMap::new(),
+			overrides: HashMap::new(),
+		}
+	}
+
+	/// Assign `role` to the user named `user` in `room`, or globally for `None`
+	pub fn set_role(&mut self, user: &str, room: Option<&str>, role: Role) {
+		self.roles.insert((fold_name(user), room.map(str::to_string)), role);
+	}
+
+	/// Remove the role assignment of the user named `user` in `room`, or globally for `None`, returning the removed role
+	pub fn remove_role(&mut self, user: &str, room: Option<&str>) -> Option<Role> {
+		self.roles.remove(&(fold_name(user), room.map(str::to_string)))
+	}
+
+	/// Get `user`'s effective role in `room`
+	pub fn role(&self, user: &ChatUser, room: &str) -> Role {
+		let name = fold_name(user.name());
+		self.roles
+			.get(&(name.clone(), Some(room.to_string())))
+			.or_else(|| self.roles.get(&(name, None)))
+			.cloned()
+			.unwrap_or(self.default_role)
+	}
+
+	/// Explicitly allow `subject` to do `permission` in `room`, or globally for `None`
+	pub fn allow(&mut self, subject: &Subject, room: Option<&str>, permission: Permission) {
+		self.overrides.insert((subject.key(), room.map(str::to_string), permission), true);
+	}
+
+	/// Explicitly deny `subject` doing `permission` in `room`, or globally for `None`
+	pub fn deny(&mut self, subject: &Subject, room: Option<&str>, permission: Permission) {
+		self.overrides.insert((subject.key(), room.map(str::to_string), permission), false);
+	}
+
+	/// Remove an explicit allow or deny, returning whether there was one
+	pub fn clear_override(&mut self, subject: &Subject, room: Option<&str>, permission: Permission) -> bool {
+		self.overrides.remove(&(subject.key(), room.map(str::to_string), permission)).is_some()
+	}
+
+	/// Check whether `user` can do `permission` in `room`
+	pub fn can(&self, user: &ChatUser, permission: Permission, room: &str) -> bool {
+		let role = self.role(user, room);
+		if role == Role::Owner {
+			return true;
+		}
+
+		let user = SubjectKey::User(fold_name(user.name()));
+		let room = Some(room.to_string());
+		let levels = [(&user, &room), (&user, &None), (&SubjectKey::Role(role), &room), (&SubjectKey::Role(role), &None)];
+		for &(subject, room) in &levels {
+			if let Some(&allowed) = self.overrides.get(&(subject.clone(), room.clone(), permission)) {
+				return allowed;
+			}
+		}
+
+		role.has(permission)
+	}
+
+	/// Get everything `user` can do in `room`, e.g. to grey out unavailable actions
+	pub fn permissions(&self, user: &ChatUser, room: &str) -> Vec<Permission> {
+		ALL_PERMISSIONS.iter().cloned().filter(|&permission| self.can(user, permission, room)).collect()
+	}
+}
+
+impl Default for Permissions {
+	/// Everyone's a [`Member`](enum.Role.html#variant.Member)
+	fn default() -> Permissions {
+		Permissions::new(Role::Member)
+	}
+}
+
+impl Subject {
+	fn key(&self) -> SubjectKey {
+		match *self {
+			Subject::User(ref name) => SubjectKey::User(fold_name(name)),
+			Subject::Role(role)     => SubjectKey::Role(role),
+		}
+	}
+}
+
+
+impl fmt::Display for Permission {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for Permission {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Permission, ()> {
+		ALL_PERMISSIONS.iter().find(|permission| permission.name() == s).cloned().ok_or(())
+	}
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for Role {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Role, ()> {
+		Role::all().iter().find(|role| role.name() == s).cloned().ok_or(())
+	}
+}
+
+impl FromJsonnable for Permission {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(name) => name.parse().map_err(|_| JsonError::unknown_variant(&name)),
+			_                   => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for Permission {
+	fn to_json(&self) -> Value {
+		Value::String(self.name().to_string())
+	}
+}
+
+impl JsonSchema for Permission {
+	fn json_schema() -> Value {
+		json::enum_schema(&Permission::all().iter().map(Permission::name).collect::<Vec<_>>())
+	}
+}
+
+impl FromJsonnable for Role {
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		match json {
+			Value::String(name) => name.parse().map_err(|_| JsonError::unknown_variant(&name)),
+			_                   => Err(JsonError::invalid_type(Type::String)),
+		}
+	}
+}
+
+impl ToJsonnable for Role {
+	fn to_json(&self) -> Value {
+		Value::String(self.name().to_string())
+	}
+}
+
+impl JsonSchema for Role {
+	fn json_schema() -> Value {
+		json::enum_schema(&Role::all().iter().map(Role::name).collect::<Vec<_>>())
+	}
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use ChatMessage;
+use Payload;
+use fold_name;
+use filter::{FilterVerdict, MessageFilter};
+use time::{Duration, Tm};
+
+
+/// Estimated offset of a peer's clock from ours, measured with a [`Payload::Ping`/`Payload::Pong`](enum.Payload.html) exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClockSkew {
+	/// How far ahead of ours the peer's clock is
+	pub offset: Duration,
+	/// How long the exchange took, bounding the estimate's error to half of it
+	pub round_trip: Duration,
+}
+
+/// Flags messages whose claimed time differs from when they were received by more than `threshold`,
+/// after correcting for each sender's measured [`ClockSkew`](struct.ClockSkew.html), if any.
+///
+/// Messages that weren't [`receive()`](struct.ChatMessage.html#method.receive)d are accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkewFilter {
+	pub threshold: Duration,
+	/// Keyed by folded user name
+	skews: HashMap<String, ClockSkew>,
+}
+
+
+impl ClockSkew {
+	/// Estimate the skew from a ping we `sent` and the `remote` time in the pong we `received`, assuming symmetric latency.
+	///
+	/// `sent` and `received` are by our clock, `remote` by the peer's.
+	pub fn estimate(sent: &Tm, remote: &Tm, received: &Tm) -> ClockSkew {
+		let round_trip = *received - *sent;
+		ClockSkew{
+			offset: *remote - (*sent + round_trip / 2),
+			round_trip: round_trip,
+		}
+	}
+
+	/// [`estimate()`](#method.estimate) the skew from a [`Payload::Pong`](enum.Payload.html) `received` by our clock
+	pub fn from_pong(pong: &Payload, received: &Tm) -> Option<ClockSkew> {
+		match *pong {
+			Payload::Pong{ref ping, ref time} => Some(ClockSkew::estimate(ping, time, received)),
+			_ => None,
+		}
+	}
+
+	/// Convert a time by the peer's clock to ours
+	pub fn to_local(&self, remote: &Tm) -> Tm {
+		*remote - self.offset
+	}
+}
+
+impl SkewFilter {
+	/// Flag messages more than `threshold` off, with no skews measured
+	pub fn new(threshold: Duration) -> SkewFilter {
+		SkewFilter{
+			threshold: threshold,
+			skews: HashMap::new(),
+		}
+	}
+
+	/// Remember the clock skew measured for the user named `user`
+	pub fn record(&mut self, user: &str, skew: ClockSkew) {
+		self.skews.insert(fold_name(user), skew);
+	}
+
+	/// Forget the clock skew measured for the user named `user`, e.g. when they disconnect
+	pub fn forget(&mut self, user: &str) -> Option<ClockSkew> {
+		self.skews.remove(&fold_name(user))
+	}
+
+	pub fn skew(&self, user: &str) -> Option<&ClockSkew> {
+		self.skews.get(&fold_name(user))
+	}
+}
+
+impl MessageFilter for SkewFilter {
+	fn filter(&mut self, message: &ChatMessage) -> FilterVerdict {
+		match message.corrected_skew(self.skew(message.sender.name())) {
+			Some(difference) if difference > self.threshold || difference < -self.threshold =>
+				FilterVerdict::Flag(format!("claimed time is {}s off from receipt", difference.num_seconds())),
+			_ => FilterVerdict::Accept,
+		}
+	}
+}
@@ -0,0 +1,174 @@
+use std::fmt::Debug;
+use std::time::{Duration as StdDuration, SystemTime, UNIX_EPOCH};
+use json::{FromJsonnable, JsonOptions, JsonSchema, ToJsonnable};
+use time::{at_utc, now_utc, Duration, Timespec, Tm};
+use serde_json::value::Value;
+use serde_json::error::Error as JsonError;
+#[cfg(feature = "time03")]
+use time03::OffsetDateTime;
+
+
+/// What [`ChatMessage`](struct.ChatMessage.html)s are timestamped with.
+///
+/// `time::Tm` by default, `std::time::SystemTime` with the `systemtime` feature,
+/// or `time::OffsetDateTime` from `time` 0.3 with the `time03` feature, which takes precedence if both are enabled.
+///
+/// All backends serialize exactly like `Tm` does, in the current [`TimeFormat`](json/enum.TimeFormat.html),
+/// so peers built with different backends understand each other.
+#[cfg(not(any(feature = "systemtime", feature = "time03")))]
+pub type Timestamp = Tm;
+
+/// What [`ChatMessage`](struct.ChatMessage.html)s are timestamped with: `std::time::SystemTime`,
+/// selected by the `systemtime` feature unless `time03` is enabled too.
+///
+/// Serializes exactly like `time::Tm`, in the current [`TimeFormat`](json/enum.TimeFormat.html).
+#[cfg(all(feature = "systemtime", not(feature = "time03")))]
+pub type Timestamp = SystemTime;
+
+/// What [`ChatMessage`](struct.ChatMessage.html)s are timestamped with: `time::OffsetDateTime` from `time` 0.3,
+/// selected by the `time03` feature, even if `systemtime` is enabled too.
+///
+/// Serializes exactly like `time::Tm`, in the current [`TimeFormat`](json/enum.TimeFormat.html).
+#[cfg(feature = "time03")]
+pub type Timestamp = OffsetDateTime;
+
+
+/// A point in time a [`Timestamp`](type.Timestamp.html) can be backed by.
+///
+/// Everything goes through `Timespec`s, so the backends agree to the nanosecond.
+pub trait TimestampBackend: Copy + Ord + Debug + FromJsonnable + ToJsonnable + JsonSchema {
+	/// The current time
+	fn now() -> Self;
+
+	/// Convert from seconds and nanoseconds since the epoch.
+	///
+	/// Panics if `spec` can't be represented, which can't happen for times decoded from JSON.
+	fn from_timespec(spec: Timespec) -> Self;
+
+	/// Convert to seconds and nanoseconds since the epoch
+	fn as_timespec(&self) -> Timespec;
+
+	fn from_tm(tm: &Tm) -> Self {
+		Self::from_timespec(tm.to_timespec())
+	}
+
+	/// Convert to a UTC `Tm`
+	fn to_tm(&self) -> Tm {
+		at_utc(self.as_timespec())
+	}
+
+	/// How much later than `earlier` this is, negative if it's earlier
+	fn since(&self, earlier: &Self) -> Duration {
+		self.as_timespec() - earlier.as_timespec()
+	}
+}
+
+
+impl TimestampBackend for Tm {
+	fn now() -> Tm {
+		now_utc()
+	}
+
+	fn from_timespec(spec: Timespec) -> Tm {
+		at_utc(spec)
+	}
+
+	fn as_timespec(&self) -> Timespec {
+		self.to_timespec()
+	}
+}
+
+impl TimestampBackend for SystemTime {
+	fn now() -> SystemTime {
+		SystemTime::now()
+	}
+
+	fn from_timespec(spec: Timespec) -> SystemTime {
+		if spec.sec >= 0 {
+			UNIX_EPOCH + StdDuration::new(spec.sec as u64, spec.nsec as u32)
+		} else {
+			UNIX_EPOCH - StdDuration::new(spec.sec.unsigned_abs(), 0) + StdDuration::new(0, spec.nsec as u32)
+		}
+	}
+
+	fn as_timespec(&self) -> Timespec {
+		match self.duration_since(UNIX_EPOCH) {
+			Ok(after) => Timespec::new(after.as_secs() as i64, after.subsec_nanos() as i32),
+			Err(before) => {
+				let before = before.duration();
+				match before.subsec_nanos() {
+					0    => Timespec::new(-(before.as_secs() as i64), 0),
+					nsec => Timespec::new(-(before.as_secs() as i64) - 1, (1_000_000_000 - nsec) as i32),
+				}
+			},
+		}
+	}
+}
+
+#[cfg(feature = "time03")]
+impl TimestampBackend for OffsetDateTime {
+	fn now() -> OffsetDateTime {
+		OffsetDateTime::now_utc()
+	}
+
+	fn from_timespec(spec: Timespec) -> OffsetDateTime {
+		OffsetDateTime::from_unix_timestamp_nanos(spec.sec as i128 * 1_000_000_000 + spec.nsec as i128)
+			.expect("Timespec out of time::OffsetDateTime's range")
+	}
+
+	fn as_timespec(&self) -> Timespec {
+		Timespec::new(self.unix_timestamp(), self.nanosecond() as i32)
+	}
+}
+
+
+impl FromJsonnable for SystemTime {
+	// Deserialize via Tm
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Tm::from_json(json).map(|tm| SystemTime::from_tm(&tm))
+	}
+}
+
+impl ToJsonnable for SystemTime {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	// Serialize via Tm
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		self.to_tm().to_json_with(options)
+	}
+}
+
+impl JsonSchema for SystemTime {
+	fn json_schema() -> Value {
+		Tm::json_schema()
+	}
+}
+
+#[cfg(feature = "time03")]
+impl FromJsonnable for OffsetDateTime {
+	// Deserialize via Tm
+	fn from_json(json: Value) -> Result<Self, JsonError> {
+		Tm::from_json(json).map(|tm| OffsetDateTime::from_tm(&tm))
+	}
+}
+
+#[cfg(feature = "time03")]
+impl ToJsonnable for OffsetDateTime {
+	fn to_json(&self) -> Value {
+		self.to_json_with(&JsonOptions::default())
+	}
+
+	// Serialize via Tm
+	fn to_json_with(&self, options: &JsonOptions) -> Value {
+		self.to_tm().to_json_with(options)
+	}
+}
+
+#[cfg(feature = "time03")]
+impl JsonSchema for OffsetDateTime {
+	fn json_schema() -> Value {
+		Tm::json_schema()
+	}
+}
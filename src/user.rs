@@ -1,52 +1,115 @@
-use std::net::{SocketAddr, ToSocketAddrs};
-use json::{ToJsonnable, FromJsonnable};
+use std::net::ToSocketAddrs;
+use Poster;
+use ValidatedName;
+use {PosterMask, PosterPrivacy};
+use json::{self, Extensible, Extensions, Field, FieldSpec, ToJsonnable, FromJsonnable, JsonOptions, JsonSchema};
 use serde::de::{Error, Type};
-use serde_json::value::Value;
+use serde_json::value::{Map, Value};
 use serde_json::error::Error as JsonError;
 use serde_json::builder::ObjectBuilder;
 
 
-#[derive(Debug, Clone, Eq)]
+#[derive(Debug, Clone)]
 pub struct ChatUser {
-	/// User's desired name
-	pub name: String,
-	poster: Option<SocketAddr>,
+	name: String,
+	poster: Option<Poster>,
+	/// Fields this version doesn't know about, kept if decoded under [`UnknownFields::Preserve`](json/enum.UnknownFields.html)
+	pub extensions: Extensions,
 }
 
 
+const NAME: Field<String> = Field::required("name");
+const POSTER: Field<Option<String>> = Field::optional("poster");
+
+// For the object form, the plain string form is just the name
+static FIELDS: [FieldSpec; 2] = [NAME.spec_with(name_schema), POSTER.spec()];
+
+
 impl ChatUser {
-	/// Creates a user defined by the supplied arguments
-	pub fn get<Addr: ToSocketAddrs>(name: String, poster: Addr) -> ChatUser {
+	/// Creates a user defined by the supplied arguments, with the name validated by [`validate_name()`](fn.validate_name.html)
+	pub fn get<Addr: ToSocketAddrs>(name: ValidatedName, poster: Addr) -> ChatUser {
 		ChatUser{
-			name: name,
+			name: name.into_inner(),
 			poster: Self::socket_addr_to_option(poster),
+			extensions: Extensions::default(),
 		}
 	}
 
 	/// Creates a named, IP-less user.
 	/// Used by the client, as it doesn't know its IP, which is filled in server-side using [`fill_ip()`](#method.fill_ip).
-	pub fn me(name: String) -> ChatUser {
+	pub fn me(name: ValidatedName) -> ChatUser {
 		ChatUser{
-			name: name,
+			name: name.into_inner(),
 			poster: None,
+			extensions: Extensions::default(),
+		}
+	}
+
+	/// Creates a user posting from `poster`, with the name validated by [`validate_name()`](fn.validate_name.html)
+	pub fn at(name: ValidatedName, poster: Poster) -> ChatUser {
+		ChatUser{
+			name: name.into_inner(),
+			poster: Some(poster),
+			extensions: Extensions::default(),
 		}
 	}
 
+	/// User's desired name
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Rename the user, with the name validated by [`validate_name()`](fn.validate_name.html)
+	pub fn set_name(&mut self, name: ValidatedName) {
+		self.name = name.into_inner();
+	}
+
 	/// Server-side function to fill in user's IP, see [`me()`](#method.me).
 	pub fn fill_ip<Addr: ToSocketAddrs>(&mut self, poster: Addr) {
 		self.poster = Self::socket_addr_to_option(poster);
 	}
 
+	/// The address the user posted from, if known
+	pub fn poster(&self) -> Option<Poster> {
+		self.poster
+	}
+
+	/// Set or clear the address the user posted from
+	pub fn set_poster(&mut self, poster: Option<Poster>) {
+		self.poster = poster;
+	}
+
+	/// Serialize like [`to_json()`](json/trait.ToJsonnable.html#tymethod.to_json), but with the poster masked by `mask`,
+	/// see [`PosterPrivacy::mask_for()`](struct.PosterPrivacy.html#method.mask_for).
+	///
+	/// Masked posters other than [`Full`](enum.PosterMask.html#variant.Full) deserialize to unknown ones.
+	pub fn to_json_masked(&self, privacy: &PosterPrivacy, mask: PosterMask) -> Value {
+		self.json_with_poster(self.poster.and_then(|poster| privacy.apply(mask, &poster)))
+	}
+
 
-	fn socket_addr_to_option<Addr: ToSocketAddrs>(poster: Addr) -> Option<SocketAddr> {
-		match poster.to_socket_addrs() {
-			Ok(mut itr) =>
-				match itr.next() {
-					Some(addr) => Some(addr),
-					None => None,
-				},
-			Err(_) => None,
+	fn validate(name: &str) -> Result<ValidatedName, JsonError> {
+		ValidatedName::new(name).map_err(|e| JsonError::invalid_value(&e.to_string()))
+	}
+
+	fn socket_addr_to_option<Addr: ToSocketAddrs>(poster: Addr) -> Option<Poster> {
+		poster.to_socket_addrs().ok().and_then(|mut it| it.next()).map(Poster::from)
+	}
+
+	// Just the name if there's nothing else to send, for older peers
+	fn json_with_poster(&self, poster: Option<String>) -> Value {
+		if poster.is_none() && self.extensions.is_empty() {
+			return Value::String(self.name.clone());
+		}
+
+		let options = JsonOptions::default();
+		let mut map = Map::new();
+		NAME.encode(&mut map, &self.name, &options);
+		if poster.is_some() {
+			POSTER.encode(&mut map, &poster, &options);
 		}
+		self.extensions.fill(&mut map);
+		Value::Object(map)
 	}
 }
 
@@ -57,45 +120,51 @@ impl PartialEq for ChatUser {
 	}
 }
 
+impl Eq for ChatUser {}
+
+impl Extensible for ChatUser {
+	fn extensions(&self) -> &Extensions {
+		&self.extensions
+	}
+}
+
 impl FromJsonnable for ChatUser {
 	fn from_json(json: Value) -> Result<ChatUser, JsonError> {
+		Self::from_json_with(json, &JsonOptions::default())
+	}
+
+	fn from_json_with(json: Value, options: &JsonOptions) -> Result<ChatUser, JsonError> {
 		match json {
-			Value::Object(map) => {
-				let name =
-					match map.get("name") {
-						Some(name) =>
-							match name {
-								&Value::String(ref name) => name,
-								_                        => return Err(JsonError::invalid_type(Type::String)),
-							},
-						None => return Err(JsonError::missing_field("Missing \"name\"")),
-					};
-				let poster =
-					match map.get("poster") {
-						Some(poster) =>
-							match poster {
-								&Value::String(ref poster) => poster,
-								_                          => return Err(JsonError::invalid_type(Type::String)),
-							},
-						None => return Err(JsonError::missing_field("Missing \"poster\"")),
-					};
-
-				Ok(ChatUser::get(name.clone(), &poster[..]))
-			},
-			Value::String(name) => Ok(ChatUser::me(name)),
+			Value::Object(map) =>
+				Ok(ChatUser{
+					name: try!(Self::validate(&try!(NAME.decode(&map, options)))).into_inner(),
+					poster: try!(POSTER.decode(&map, options)).and_then(|poster| poster.parse().ok()),
+					extensions: try!(json::extensions::<ChatUser>(map, &FIELDS, options)),
+				}),
+			Value::String(name) => Ok(ChatUser::me(try!(Self::validate(&name)))),
 			_                   => Err(JsonError::invalid_type(Type::Struct)),
 		}
 	}
 }
 
 impl ToJsonnable for ChatUser {
+	// The port is specific to the connection, so it's left out
 	fn to_json(&self) -> Value {
-		match self.poster {
-			Some(ref ip) =>
-				ObjectBuilder::new().insert("name", &self.name)
-			                      .insert("poster", ip.to_string())
-			                      .build(),
-			None => Value::String(self.name.clone()),
-		}
+		self.json_with_poster(self.poster.map(|poster| poster.address_string()))
 	}
 }
+
+impl JsonSchema for ChatUser {
+	// Posters can have IPv6 scope IDs, so they're any string
+	fn json_schema() -> Value {
+		json::any_of_schema(vec![name_schema(), json::fields_schema(&FIELDS)])
+	}
+}
+
+
+// Names are validated in grapheme clusters, which JSON Schema can't count
+fn name_schema() -> Value {
+	ObjectBuilder::new().insert("type", "string")
+	                    .insert("minLength", 1)
+	                    .build()
+}
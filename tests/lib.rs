@@ -1,21 +1,50 @@
 extern crate chattium_oxide_lib as cho;  // Chang
 extern crate rand;
+extern crate serde_json;
+extern crate tempdir;
 extern crate time;
+extern crate jsonschema;
+extern crate serde_json1;
+#[cfg(feature = "modern-serde")]
+extern crate serde1;
+#[cfg(feature = "modern-serde")]
+#[macro_use]
+extern crate serde_derive1;
 
 
 use time::Tm;
 use rand::Rng;
-use std::net::Ipv4Addr;
-
-
-fn random_ip<Rand: Rng>(rng: &mut Rand) -> (Ipv4Addr, u16) {
-	(Ipv4Addr::new(rng.gen(), rng.gen(), rng.gen(), rng.gen()), rng.gen())
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+
+fn random_ip<Rand: Rng>(rng: &mut Rand) -> SocketAddr {
+	if rng.gen() {
+		SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(rng.gen(), rng.gen(), rng.gen(), rng.gen()), rng.gen()))
+	} else {
+		let scope_id = if rng.gen_weighted_bool(4) {rng.gen()} else {0};
+		SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::new(rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen(), rng.gen()),
+		                                 rng.gen(),
+		                                 0,
+		                                 scope_id))
+	}
 }
 
 fn random_name<Rand: Rng>(rng: &mut Rand) -> String {
 	rng.gen_ascii_chars().take(10).collect()
 }
 
+fn random_user_name<Rand: Rng>(rng: &mut Rand) -> cho::ValidatedName {
+	cho::ValidatedName::new(&random_name(rng)).unwrap()
+}
+
+fn user(name: &str, poster: Option<&str>) -> cho::ChatUser {
+	let name = cho::ValidatedName::new(name).unwrap();
+	match poster {
+		Some(poster) => cho::ChatUser::get(name, poster),
+		None         => cho::ChatUser::me(name),
+	}
+}
+
 fn random_text<Rand: Rng>(rng: &mut Rand) -> String {
 	rng.gen_ascii_chars().take(100).collect()
 }
@@ -30,8 +59,9 @@ fn random_time<Rand: Rng>(rng: &mut Rand) -> Tm {
 #[cfg(test)]
 mod user {
 	use random_ip;
-	use random_name;
+	use random_user_name;
 	use rand;
+	use std::net::{SocketAddr, SocketAddrV6};
 	use cho::*;
 
 
@@ -41,7 +71,7 @@ mod user {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let user = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+			let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 			assert_eq!(user, user);
 		}
 	}
@@ -52,8 +82,8 @@ mod user {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let user_1 = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
-			let user_2 = ChatUser::get(user_1.name.clone(), random_ip(&mut rng));
+			let user_1 = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
+			let user_2 = ChatUser::get(ValidatedName::new(user_1.name()).unwrap(), random_ip(&mut rng));
 			assert_eq!(user_1, user_2);
 		}
 	}
@@ -65,8 +95,8 @@ mod user {
 
 		for _ in 1..times {
 			let ip = random_ip(&mut rng);
-			let user_1 = ChatUser::get(random_name(&mut rng), ip);
-			let user_2 = ChatUser::get(random_name(&mut rng), ip);
+			let user_1 = ChatUser::get(random_user_name(&mut rng), ip);
+			let user_2 = ChatUser::get(random_user_name(&mut rng), ip);
 			assert!(user_1 != user_2);
 		}
 	}
@@ -77,25 +107,81 @@ mod user {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let name = random_name(&mut rng);
+			let name = random_user_name(&mut rng);
 			let user = ChatUser::get(name.clone(), "0.0.0.0:0");
-			assert_eq!(user.name, name);
+			assert_eq!(user.name(), &*name);
+		}
+	}
+
+	#[test]
+	fn poster_splits_address_and_port() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {100000} else {1000};
+
+		for _ in 1..times {
+			let addr = random_ip(&mut rng);
+			let poster = ChatUser::get(random_user_name(&mut rng), addr).poster().unwrap();
+			assert_eq!(poster.address, addr.ip());
+			assert_eq!(poster.port, Some(addr.port()));
+			assert_eq!(poster.socket_addr(), Some(addr));
+			assert_eq!(poster.to_string().parse(), Ok(poster));
+			assert_eq!(poster.address_string().parse(), Ok(poster.without_port()));
+		}
+	}
+
+	#[test]
+	fn poster_strings() {
+		let table = vec![
+			("192.0.2.1", "192.0.2.1", None, 0),
+			("192.0.2.1:6667", "192.0.2.1", Some(6667), 0),
+			("::1", "::1", None, 0),
+			("[::1]", "::1", None, 0),
+			("[2001:db8::1]:443", "2001:db8::1", Some(443), 0),
+			("fe80::1%3", "fe80::1%3", None, 3),
+			("[fe80::1%3]:6667", "fe80::1%3", Some(6667), 3),
+			("::ffff:192.0.2.1", "192.0.2.1", None, 0),
+			("[::ffff:192.0.2.1]:5", "192.0.2.1", Some(5), 0),
+		];
+		for (input, address, port, scope_id) in table {
+			let poster: Poster = input.parse().unwrap();
+			assert_eq!((&poster.address_string()[..], poster.port, poster.scope_id), (address, port, scope_id), "Parsing {:?}", input);
+		}
+
+		for bad in &["", "example.com", "example.com:80", "192.0.2.1%3", "[192.0.2.1]:80", "[::1]80", "fe80::1%eth0", "192.0.2.1:65536"] {
+			assert_eq!(bad.parse::<Poster>(), Err(()), "Parsing {:?}", bad);
 		}
 	}
 
+	#[test]
+	fn mapped_posters_are_ipv4() {
+		let plain: Poster = "192.0.2.1:5".parse().unwrap();
+		let mapped = ChatUser::get(ValidatedName::new("dual").unwrap(), "[::ffff:192.0.2.1]:5").poster().unwrap();
+		assert_eq!(mapped, plain);
+		assert_eq!(Poster::new("::ffff:192.0.2.1".parse().unwrap()), plain.without_port());
+		assert_eq!(Poster::from(SocketAddr::V6(SocketAddrV6::new("::ffff:192.0.2.1".parse().unwrap(), 5, 0, 3))), plain);
+	}
+
 	#[test]
 	fn cloner_eq_clonee() {
 		let mut rng = rand::thread_rng();
 
-		let user = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+		let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 		let clone = user.clone();
 		assert_eq!(user, clone);
-		assert_eq!(user.name, clone.name);
+		assert_eq!(user.name(), clone.name());
+	}
+
+	#[test]
+	fn renamed_users_keep_posters() {
+		let mut user = ChatUser::get(ValidatedName::new("alice").unwrap(), "192.0.2.1:6667");
+		user.set_name(ValidatedName::new("bob").unwrap());
+		assert_eq!(user.name(), "bob");
+		assert_eq!(user.poster(), Some("192.0.2.1:6667".parse().unwrap()));
 	}
 
 	mod j_son {
 		use random_ip;
-		use random_name;
+		use random_user_name;
 		use rand;
 		use cho::*;
 		use cho::json::*;
@@ -107,10 +193,11 @@ mod user {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let user = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+				let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 				let trans = ChatUser::from_json(user.to_json()).expect("Full transserialization");
 				assert_eq!(user, trans);
-				assert_eq!(user.name, trans.name);
+				assert_eq!(user.name(), trans.name());
+				assert_eq!(user.poster().map(|poster| poster.without_port()), trans.poster());
 			}
 		}
 
@@ -120,10 +207,10 @@ mod user {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let user = ChatUser::me(random_name(&mut rng));
+				let user = ChatUser::me(random_user_name(&mut rng));
 				let trans = ChatUser::from_json(user.to_json()).expect("IP-less transserialization");
 				assert_eq!(user, trans);
-				assert_eq!(user.name, trans.name);
+				assert_eq!(user.name(), trans.name());
 			}
 		}
 		#[test]
@@ -132,11 +219,12 @@ mod user {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let user = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+				let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 				let user_s = user.to_json_string().expect("Full serialization to string");
 				let trans = ChatUser::from_json_string(&user_s).expect("Full deserialization from string");
 				assert_eq!(user, trans);
-				assert_eq!(user.name, trans.name);
+				assert_eq!(user.name(), trans.name());
+				assert_eq!(user.poster().map(|poster| poster.without_port()), trans.poster());
 			}
 		}
 
@@ -146,14 +234,21 @@ mod user {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let user = ChatUser::me(random_name(&mut rng));
+				let user = ChatUser::me(random_user_name(&mut rng));
 				let user_s = user.to_json_string().expect("IP-less serialization to string");
 				let trans = ChatUser::from_json_string(&user_s).expect("IP-less deserialization from string");
 				assert_eq!(user, trans);
-				assert_eq!(user.name, trans.name);
+				assert_eq!(user.name(), trans.name());
 			}
 		}
 
+		#[test]
+		fn legacy_poster_with_port_deserializes() {
+			let user = ChatUser::from_json_string(&"{\"name\": \"you\", \"poster\": \"[fe80::1%2]:6667\"}".to_string()).unwrap();
+			assert_eq!(user.poster(), Some("[fe80::1%2]:6667".parse().unwrap()));
+			assert_eq!(user.to_json_string().unwrap(), "{\"name\":\"you\",\"poster\":\"fe80::1%2\"}");
+		}
+
 		#[test]
 		//#[should_fail]  // The attribute `should_fail` is currently unknown to the compiler and may have meaning added to it in the future
 		fn deserialization_from_malformed_fails() {
@@ -165,7 +260,7 @@ mod user {
 #[cfg(test)]
 mod message {
 	use random_ip;
-	use random_name;
+	use random_user_name;
 	use random_text;
 	use rand;
 	use cho::*;
@@ -180,7 +275,7 @@ mod message {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
 			assert_eq!(message, message);
 		}
 	}
@@ -191,7 +286,7 @@ mod message {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let user = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+			let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 			let text = random_text(&mut rng);
 			let message_1 = ChatMessage::new(user.clone(), text.clone());
 			sleep(Duration::new(0, 1));
@@ -206,7 +301,7 @@ mod message {
 		let times = if cfg!(feature = "ci") {100000} else {1000};
 
 		for _ in 1..times {
-			let sender = ChatUser::get(random_name(&mut rng), random_ip(&mut rng));
+			let sender = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
 			let message = ChatMessage::new(sender.clone(), random_text(&mut rng));
 			assert_eq!(message.sender, sender);
 		}
@@ -219,7 +314,7 @@ mod message {
 
 		for _ in 1..times {
 			let value = random_text(&mut rng);
-			let message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), value.clone());
+			let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), value.clone());
 			assert_eq!(message.value, value);
 		}
 	}
@@ -228,7 +323,7 @@ mod message {
 	fn cloner_eq_clonee() {
 		let mut rng = rand::thread_rng();
 
-		let message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+		let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
 		let clone = message.clone();
 		assert_eq!(message, clone);
 	}
@@ -240,7 +335,7 @@ mod message {
 
 		let id_lock = RwLock::new(1u64);
 		for time in 1..times {
-			let mut message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			let mut message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
 			message.fill_id(id_lock.write().unwrap());
 			assert_eq!(message.id, time);
 			assert_eq!(*id_lock.read().unwrap(), time + 1);
@@ -249,7 +344,7 @@ mod message {
 
 	mod j_son {
 		use random_ip;
-		use random_name;
+		use random_user_name;
 		use random_text;
 		use rand::{self, Rng};
 		use cho::*;
@@ -262,7 +357,7 @@ mod message {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let mut message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+				let mut message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
 				message.fill_id(&mut rng.next_u64());
 				let trans = ChatMessage::from_json(message.to_json()).expect("Full transserialization via ChatMessage");
 				assert_eq!(message, trans);
@@ -275,7 +370,7 @@ mod message {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let mut message = ChatMessage::new(ChatUser::me(random_name(&mut rng)), random_text(&mut rng));
+				let mut message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng));
 				message.fill_id(&mut rng.next_u64());
 				let trans = ChatMessage::from_json(message.to_json()).expect("IP-less transserialization via ChatMessage");
 				assert_eq!(message, trans);
@@ -288,7 +383,7 @@ mod message {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let mut message = ChatMessage::new(ChatUser::get(random_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+				let mut message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
 				message.fill_id(&mut rng.next_u64());
 				let message_s = message.to_json_string().expect("Full serialization to string via ChatMessage");
 				let trans = ChatMessage::from_json_string(&message_s).expect("Full deserialization from string via ChatMessage");
@@ -302,7 +397,7 @@ mod message {
 			let times = if cfg!(feature = "ci") {100000} else {1000};
 
 			for _ in 1..times {
-				let mut message = ChatMessage::new(ChatUser::me(random_name(&mut rng)), random_text(&mut rng));
+				let mut message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng));
 				message.fill_id(&mut rng.next_u64());
 				let message_s = message.to_json_string().expect("IP-less serialization to string via ChatMessage");
 				let trans = ChatMessage::from_json_string(&message_s).expect("IP-less deserialization from string via ChatMessage");
@@ -315,16 +410,135 @@ mod message {
 		fn deserialization_from_malformed_fails() {
 			ChatMessage::from_json_string(&"{\"user\": \"you\"}".to_string()).unwrap_err();
 		}
+
+		#[test]
+		fn unknown_fields_follow_policy() {
+			use serde_json::value::Value;
+			use serde_json::error::{Error as JsonError, ErrorCode};
+
+			let mut rng = rand::thread_rng();
+			let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			let mut newer = match message.to_json() {
+				Value::Object(map) => map,
+				_ => unreachable!(),
+			};
+			newer.insert("edited".to_string(), Value::Bool(true));
+			newer.insert("reactions".to_string(), Value::Array(vec![Value::String("+1".to_string())]));
+			let newer_s = serde_json::to_string(&Value::Object(newer.clone())).unwrap();
+
+			assert_eq!(JsonOptions::default().unknown_fields::<ChatMessage>(), json::UnknownFields::Ignore);
+			let ignored = ChatMessage::from_json_string(&newer_s).unwrap();
+			assert_eq!(ignored, message);
+			assert!(ignored.extensions.is_empty());
+
+			let reject = JsonOptions::default().with_unknown_fields::<ChatMessage>(json::UnknownFields::Reject);
+			match ChatMessage::from_json_string_with(&newer_s, &reject) {
+				Err(JsonError::Syntax(ErrorCode::UnknownField(field), _, _)) => assert_eq!(field, "edited"),
+				other => panic!("{:?}", other),
+			}
+			assert!(ChatMessage::from_json_string_with(&message.to_json_string().unwrap(), &reject).is_ok());
+
+			let preserve = JsonOptions::default().with_unknown_fields::<ChatMessage>(json::UnknownFields::Preserve);
+			let preserved = ChatMessage::from_json_string_with(&newer_s, &preserve).unwrap();
+			assert_eq!(preserved.extensions.len(), 2);
+			assert_eq!(preserved.extensions["edited"], Value::Bool(true));
+			assert_eq!(preserved.to_json(), Value::Object(newer.clone()));
+
+			let relayed = Payload::from_json_string_with(&Payload::Message(preserved.clone()).to_json_string().unwrap(), &preserve).unwrap();
+			assert_eq!(relayed, Payload::Message(preserved));
+
+			assert_eq!(preserve.unknown_fields::<ChatUser>(), json::UnknownFields::Ignore);
+		}
+
+		#[test]
+		fn extensions_dont_override_known_fields() {
+			use serde_json::value::Value;
+
+			let mut rng = rand::thread_rng();
+			let mut message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng));
+			message.extensions.insert("value".to_string(), Value::String("spoofed".to_string()));
+			message.extensions.insert("mood".to_string(), Value::String("happy".to_string()));
+
+			let preserve = JsonOptions::default().with_unknown_fields::<ChatMessage>(json::UnknownFields::Preserve);
+			let trans = ChatMessage::from_json_with(message.to_json(), &preserve).unwrap();
+			assert_eq!(trans.value, message.value);
+			assert_eq!(trans.extensions.keys().collect::<Vec<_>>(), vec!["mood"]);
+		}
+
+		#[test]
+		fn extensions_equal_themselves() {
+			use std::f64;
+			use serde_json::value::Value;
+
+			fn assert_reflexive<T: Eq + ::std::fmt::Debug>(value: &T) {
+				assert_eq!(value, value);
+			}
+
+			let mut rng = rand::thread_rng();
+			let mut message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng));
+			message.extensions.insert("score".to_string(), Value::F64(f64::NAN));
+			assert_reflexive(&message);
+			assert_reflexive(&Payload::Message(message.clone()));
+
+			let mut other = message.clone();
+			other.extensions.insert("score".to_string(), Value::F64(1.5));
+			assert_ne!(message, other);
+		}
+
+		#[test]
+		fn relayed_users_keep_unknown_fields() {
+			use serde_json::value::Value;
+
+			let mut rng = rand::thread_rng();
+			let mut to = ChatUser::me(random_user_name(&mut rng));
+			to.extensions.insert("avatar".to_string(), Value::String("cat.png".to_string()));
+			let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			let json = Payload::Private{to, message}.to_json_string().unwrap();
+			assert!(json.contains(r#""avatar":"cat.png""#), "{}", json);
+
+			match Payload::from_json_string(&json).unwrap() {
+				Payload::Private{to, ..} => assert!(to.extensions.is_empty()),
+				other => panic!("{:?}", other),
+			}
+			let reject = JsonOptions::default().with_unknown_fields::<ChatUser>(json::UnknownFields::Reject);
+			assert!(Payload::from_json_string_with(&json, &reject).is_err());
+
+			let preserve = JsonOptions::default().with_unknown_fields::<ChatUser>(json::UnknownFields::Preserve);
+			let relayed = Payload::from_json_string_with(&json, &preserve).unwrap();
+			assert_eq!(relayed.to_json_string().unwrap(), json);
+		}
+
+		#[test]
+		fn id_is_range_checked() {
+			let mut rng = rand::thread_rng();
+			let message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng)).to_json_string().unwrap();
+			let with_id = |id: &str| message.replacen("{", &format!("{{\"id\":{},", id), 1);
+
+			assert_eq!(ChatMessage::from_json_string(&with_id("18446744073709551615")).unwrap().id, u64::MAX);
+			ChatMessage::from_json_string(&with_id("-1")).unwrap_err();
+			ChatMessage::from_json_string(&with_id("3.0")).unwrap_err();
+			let lenient = JsonOptions::default().with_lenient_integers(true);
+			assert_eq!(ChatMessage::from_json_string_with(&with_id("3.0"), &lenient).unwrap().id, 3);
+			ChatMessage::from_json_string_with(&with_id("3.5"), &lenient).unwrap_err();
+		}
 	}
 }
 
 #[cfg(test)]
 mod json_impl {
 	use rand::{self, Rng};
-	use time::Tm;
+	use time::{at_utc, Duration, Timespec, Tm};
+	use random_ip;
+	use random_name;
 	use random_time;
 	use cho::json::*;
 	use std::{f32, f64};
+	use std::rc::Rc;
+	use std::sync::Arc;
+	use std::fmt::Debug;
+	use std::net::{IpAddr, SocketAddr};
+	use std::time::Duration as StdDuration;
+	use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 
 	macro_rules! primitive_test {
@@ -385,6 +599,129 @@ mod json_impl {
 		}
 	}
 
+	#[test]
+	fn time_transserializes_properly_in_all_formats() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {100000} else {1000};
+
+		for _ in 1..times {
+			let time = random_time(&mut rng);
+			for &format in &[TimeFormat::Legacy, TimeFormat::Rfc3339] {
+				let time_s = time.to_json_string_with(&JsonOptions::default().with_time_format(format)).expect("Serialization to string via time::Tm");
+				assert_eq!(Tm::from_json_string(&time_s).expect("Deserialization from string via time::Tm"), time);
+			}
+
+			let millis_s = time.to_json_string_with(&JsonOptions::default().with_time_format(TimeFormat::EpochMillis)).unwrap();
+			let trans = Tm::from_json_string(&millis_s).unwrap().to_timespec();
+			let spec = time.to_timespec();
+			assert_eq!((trans.sec, trans.nsec), (spec.sec, spec.nsec / 1_000_000 * 1_000_000));
+		}
+	}
+
+	#[test]
+	fn time_format_defaults_to_legacy() {
+		let time = at_utc(Timespec::new(1500000000, 123456789));
+		assert_eq!(JsonOptions::default().time_format(), TimeFormat::Legacy);
+		assert_eq!(time.to_json_string().unwrap(), r#"{"nsec":123456789,"sec":1500000000}"#);
+
+		assert_eq!(time.to_json_string_with(&JsonOptions::default().with_time_format(TimeFormat::Rfc3339)).unwrap(), r#""2017-07-14T02:40:00.123456789Z""#);
+		assert_eq!(time.to_json_string_with(&JsonOptions::default().with_time_format(TimeFormat::EpochMillis)).unwrap(), "1500000000123");
+	}
+
+	#[test]
+	fn time_parses_rfc3339() {
+		for &(s, sec, nsec) in &[("1970-01-01T00:00:00Z", 0, 0),
+		                         ("2017-07-14T02:40:00.5Z", 1500000000, 500000000),
+		                         ("2017-07-14t04:40:00.000000001+02:00", 1500000000, 1),
+		                         ("2017-07-13 21:10:00-05:30", 1500000000, 0),
+		                         ("2000-02-29T00:00:00Z", 951782400, 0),
+		                         ("1969-12-31T23:59:59.999Z", -1, 999000000)] {
+			assert_eq!(parse_rfc3339(s), Some(Timespec::new(sec, nsec)), "{}", s);
+		}
+
+		for s in &["2017-07-14", "2017-07-14T02:40:00", "2017-13-01T00:00:00Z", "2001-02-29T00:00:00Z", "2017-07-14T02:40:00.Z",
+		           "2017-07-14T02:40:00.1234567890Z", "2017-07-14T24:00:00Z", "2017-07-14T02:40:00+2:00", "+017-07-14T02:40:00Z",
+		           "2017-07-14T02:40:0éZ", "2017-07-14T02:40:00+0é00"] {
+			assert_eq!(parse_rfc3339(s), None, "{}", s);
+		}
+	}
+
+	#[test]
+	fn time_parses_epoch_millis() {
+		assert_eq!(Tm::from_json_string(&"1500000000123".to_string()).unwrap().to_timespec(), Timespec::new(1500000000, 123000000));
+		assert_eq!(Tm::from_json_string(&"-1".to_string()).unwrap().to_timespec(), Timespec::new(-1, 999000000));
+		assert!(Tm::from_json_string(&r#""yesterday""#.to_string()).is_err());
+	}
+
+	#[test]
+	fn time_rejects_out_of_range_values() {
+		use serde_json::value::Value;
+		use serde_json::builder::ObjectBuilder;
+
+		fn legacy(sec: Value, nsec: Value) -> Result<Tm, JsonError> {
+			Tm::from_json(ObjectBuilder::new().insert("sec", sec).insert("nsec", nsec).build())
+		}
+
+		let nsecs = [Value::I64(-1), Value::I64(i64::MIN), Value::U64(1_000_000_000), Value::U64(5_000_000_000), Value::U64(u64::MAX)];
+		let secs = [Value::I64(MIN_TIME_SEC - 1), Value::I64(i64::MIN), Value::U64(MAX_TIME_SEC as u64 + 1), Value::U64(u64::MAX)];
+		for nsec in &nsecs {
+			assert!(legacy(Value::I64(0), nsec.clone()).is_err(), "nsec {:?}", nsec);
+		}
+		for sec in &secs {
+			assert!(legacy(sec.clone(), Value::I64(0)).is_err(), "sec {:?}", sec);
+		}
+		for millis in &[Value::I64(i64::MIN), Value::I64(i64::MAX), Value::U64(u64::MAX)] {
+			assert!(Tm::from_json(millis.clone()).is_err(), "millis {:?}", millis);
+		}
+		assert!(Tm::from_json(Value::String("9999-12-31T23:59:60Z".to_string())).is_err());
+
+		assert_eq!(legacy(Value::I64(MIN_TIME_SEC), Value::I64(0)).unwrap().to_timespec(), Timespec::new(MIN_TIME_SEC, 0));
+		assert_eq!(legacy(Value::U64(MAX_TIME_SEC as u64), Value::U64(999_999_999)).unwrap().to_timespec(), Timespec::new(MAX_TIME_SEC, 999_999_999));
+		assert_eq!(checked_timespec(0, 1_000_000_000), Err(TimeRangeError::Nanoseconds(1_000_000_000)));
+		assert_eq!(checked_timespec(MAX_TIME_SEC + 1, 0), Err(TimeRangeError::Seconds(MAX_TIME_SEC + 1)));
+	}
+
+	#[test]
+	fn time_fuzzes_boundaries() {
+		use serde_json::value::Value;
+		use serde_json::builder::ObjectBuilder;
+
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {100000} else {1000};
+
+		let secs = [i64::MIN, i64::MIN + 1, MIN_TIME_SEC - 1, MIN_TIME_SEC, -1, 0, 1, MAX_TIME_SEC, MAX_TIME_SEC + 1, i64::MAX - 1, i64::MAX];
+		let nsecs = [i64::MIN, i32::MIN as i64, -1, 0, 1, 999_999_999, 1_000_000_000, i32::MAX as i64, i32::MAX as i64 + 1, i64::MAX];
+		for i in 0..times {
+			let (sec, nsec) =
+				if i < secs.len() * nsecs.len() {
+					(secs[i % secs.len()], nsecs[i / secs.len()])
+				} else if rng.gen() {
+					(rng.gen_range(MIN_TIME_SEC - 1000, MAX_TIME_SEC + 1000), rng.gen_range(-2_000_000_000, 2_000_000_000))
+				} else {
+					(rng.gen(), rng.gen())
+				};
+			let valid = (MIN_TIME_SEC..=MAX_TIME_SEC).contains(&sec) && (0..1_000_000_000).contains(&nsec);
+
+			let time = Tm::from_json(ObjectBuilder::new().insert("sec", sec).insert("nsec", nsec).build());
+			assert_eq!(time.is_ok(), valid, "{{sec: {}, nsec: {}}}", sec, nsec);
+			if let Ok(time) = time {
+				assert_eq!(time.to_timespec(), Timespec::new(sec, nsec as i32));
+				for &format in &[TimeFormat::Rfc3339, TimeFormat::EpochMillis] {
+					let json = time.to_json_with(&JsonOptions::default().with_time_format(format));
+					assert!(Tm::from_json(json).is_ok());
+				}
+			}
+
+			let millis = Tm::from_json(Value::I64(sec));
+			assert_eq!(millis.is_ok(), (MIN_TIME_SEC..=MAX_TIME_SEC).contains(&sec.div_euclid(1000)), "{} ms", sec);
+		}
+
+		// Multi-byte characters where the parser slices
+		for s in &["2017-07-14T02:40:0éZ", "2017-07-14T02:40:00+0é00", "2017-07-1éT02:40:00Z", "2017-07-14T02:40:00.é5Z"] {
+			assert!(Tm::from_json(Value::String(s.to_string())).is_err(), "{}", s);
+		}
+	}
+
 	primitive_test!(i8,  "i8",  i8_transserializes_properly,  i8_transserializes_properly_through_string,  |n, t| assert_eq!(n, t));
 	primitive_test!(i16, "i16", i16_transserializes_properly, i16_transserializes_properly_through_string, |n, t| assert_eq!(n, t));
 	primitive_test!(i32, "i32", i32_transserializes_properly, i32_transserializes_properly_through_string, |n, t| assert_eq!(n, t));
@@ -424,4 +761,2476 @@ mod json_impl {
 			assert_eq!(vec, trans);
 		}
 	}
+
+	fn round_trip<T: FromJsonnable + ToJsonnable + PartialEq + Debug>(value: T) {
+		let trans = T::from_json(value.to_json()).expect("Full transserialization");
+		assert_eq!(trans, value);
+		let trans = T::from_json_string(&value.to_json_string().expect("Serialization to string")).expect("Deserialization from string");
+		assert_eq!(trans, value);
+	}
+
+	macro_rules! random_round_trip_test {
+		($name:ident, $t:ty) => {
+			#[test]
+			fn $name() {
+				let mut rng = rand::thread_rng();
+				let times = if cfg!(feature = "ci") {10000} else {1000};
+
+				for _ in 1..times {
+					let value: $t = rng.gen();
+					round_trip(value);
+				}
+			}
+		};
+	}
+
+	type Tuple12 = (u8, i8, u16, i16, u32, i32, u64, i64, bool, char, Option<u8>, (u8, bool));
+
+	random_round_trip_test!(bool_transserializes_properly, bool);
+	random_round_trip_test!(char_transserializes_properly, char);
+	random_round_trip_test!(option_transserializes_properly, Option<u32>);
+	random_round_trip_test!(nested_option_transserializes_properly, Option<(Option<bool>, i8)>);
+	random_round_trip_test!(tuple_1_transserializes_properly, (u8,));
+	random_round_trip_test!(tuple_2_transserializes_properly, (i8, bool));
+	random_round_trip_test!(tuple_6_transserializes_properly, (i8, bool, char, u64, i64, Option<i16>));
+	random_round_trip_test!(tuple_12_transserializes_properly, Tuple12);
+	random_round_trip_test!(array_transserializes_properly, [i32; 16]);
+	random_round_trip_test!(empty_array_transserializes_properly, [bool; 0]);
+
+	#[test]
+	fn string_transserializes_properly() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for len in 0..times {
+			round_trip(rng.gen_iter::<char>().take(len % 50).collect::<String>());
+		}
+		round_trip("\"quoted\" \\ \n\u{0}".to_string());
+	}
+
+	#[test]
+	fn pointers_transserialize_properly() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for _ in 1..times {
+			let value: (u32, bool) = rng.gen();
+			assert_eq!(Box::new(value).to_json(), value.to_json());
+			round_trip(Box::new(value));
+			round_trip(Rc::new(value));
+			round_trip(Arc::new(value));
+		}
+	}
+
+	#[test]
+	fn collections_transserialize_properly() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {1000} else {100};
+
+		for size in 0..times {
+			let hash_map: HashMap<String, i32> = (0..size).map(|_| (random_name(&mut rng), rng.gen())).collect();
+			let btree_map: BTreeMap<String, Vec<bool>> = (0..size).map(|_| (random_name(&mut rng), vec![rng.gen(); size % 5])).collect();
+			let hash_set: HashSet<char> = (0..size).map(|_| rng.gen()).collect();
+			let btree_set: BTreeSet<u16> = (0..size).map(|_| rng.gen()).collect();
+
+			round_trip(hash_map);
+			round_trip(btree_map);
+			round_trip(hash_set);
+			round_trip(btree_set);
+		}
+	}
+
+	#[test]
+	fn addresses_transserialize_properly() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for _ in 1..times {
+			let addr = random_ip(&mut rng);
+			round_trip(addr);
+			round_trip(addr.ip());
+		}
+		assert_eq!("192.0.2.1:80".parse::<SocketAddr>().unwrap().to_json_string().unwrap(), r#""192.0.2.1:80""#);
+		assert!(IpAddr::from_json_string(&r#""192.0.2.1:80""#.to_string()).is_err());
+	}
+
+	#[test]
+	fn durations_transserialize_properly() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for _ in 1..times {
+			round_trip(Duration::nanoseconds(rng.gen()));
+			round_trip(Duration::milliseconds(rng.gen()));
+			round_trip(StdDuration::new(rng.gen(), rng.gen_range(0, 1_000_000_000)));
+		}
+		round_trip(Duration::min_value());
+		round_trip(Duration::max_value());
+		round_trip(StdDuration::new(u64::MAX, 999_999_999));
+
+		assert_eq!(Duration::milliseconds(-1500).to_json_string().unwrap(), r#"{"nsec":500000000,"sec":-2}"#);
+		assert!(Duration::from_json_string(&r#"{"nsec":1000000000,"sec":0}"#.to_string()).is_err());
+		assert!(Duration::from_json_string(&r#"{"nsec":0,"sec":-9223372036854775807}"#.to_string()).is_err());
+		assert!(StdDuration::from_json_string(&r#"{"nsec":0,"sec":-1}"#.to_string()).is_err());
+	}
+
+	#[test]
+	fn out_of_range_integers_fail() {
+		fn decode<T: FromJsonnable>(json: &str) -> Result<T, JsonError> {
+			T::from_json_string(&json.to_string())
+		}
+
+		assert!(decode::<u8>("300").is_err());
+		assert!(decode::<u8>("-1").is_err());
+		assert!(decode::<i8>("128").is_err());
+		assert!(decode::<i8>("-129").is_err());
+		assert!(decode::<i8>("18446744073709551615").is_err());
+		assert!(decode::<u16>("65536").is_err());
+		assert!(decode::<i16>("-32769").is_err());
+		assert!(decode::<u32>("4294967296").is_err());
+		assert!(decode::<i32>("2147483648").is_err());
+		assert!(decode::<i64>("9223372036854775808").is_err());
+		assert!(decode::<u64>("-1").is_err());
+
+		assert_eq!(decode::<u8>("255").unwrap(), 255);
+		assert_eq!(decode::<i8>("-128").unwrap(), -128);
+		assert_eq!(decode::<i64>("9223372036854775807").unwrap(), i64::MAX);
+		assert_eq!(decode::<i64>("-9223372036854775808").unwrap(), i64::MIN);
+		assert_eq!(decode::<u64>("18446744073709551615").unwrap(), u64::MAX);
+	}
+
+	#[test]
+	fn integral_floats_need_leniency() {
+		fn decode<T: FromJsonnable>(json: &str) -> Result<T, JsonError> {
+			T::from_json_string_with(json, &JsonOptions::default().with_lenient_integers(true))
+		}
+
+		assert!(!JsonOptions::default().lenient_integers());
+		assert!(u8::from_json_string(&"3.0".to_string()).is_err());
+		assert!(i64::from_json_string(&"-1e3".to_string()).is_err());
+
+		assert_eq!(decode::<u8>("3.0").unwrap(), 3);
+		assert_eq!(decode::<i64>("-1e3").unwrap(), -1000);
+		assert_eq!(decode::<u64>("1e19").unwrap(), 10_000_000_000_000_000_000);
+		assert_eq!(decode::<Vec<(u8, i8)>>("[[1.0, -1.0]]").unwrap(), vec![(1, -1)]);
+		assert!(decode::<u8>("3.5").is_err());
+		assert!(decode::<u8>("256.0").is_err());
+		assert!(decode::<i64>("1e19").is_err());
+		assert!(decode::<u64>("1e20").is_err());
+		assert!(decode::<u64>("-1.0").is_err());
+		assert!(decode::<f64>("3").is_err());
+	}
+
+	#[test]
+	fn mismatched_lengths_fail() {
+		assert!(<(u8, u8)>::from_json_string(&"[1]".to_string()).is_err());
+		assert!(<(u8, u8)>::from_json_string(&"[1, 2, 3]".to_string()).is_err());
+		assert!(<[u8; 2]>::from_json_string(&"[1, 2, 3]".to_string()).is_err());
+		assert!(char::from_json_string(&r#""ab""#.to_string()).is_err());
+		assert!(char::from_json_string(&r#""""#.to_string()).is_err());
+		assert_eq!(Option::<u8>::from_json_string(&"null".to_string()).unwrap(), None);
+	}
+}
+
+#[cfg(test)]
+mod blob {
+	use random_text;
+	use rand;
+	use cho::*;
+	use cho::json::*;
+	use tempdir::TempDir;
+	use std::io::{self, ErrorKind, Read, Write};
+	use std::fs::{self, OpenOptions};
+
+
+	#[test]
+	fn put_get_roundtrips() {
+		let mut rng = rand::thread_rng();
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap();
+
+		for _ in 1..100 {
+			let data = random_text(&mut rng).into_bytes();
+			let hash = store.put_bytes(&data).expect("Storing blob");
+			assert_eq!(hash, BlobHash::of(&data));
+			assert_eq!(store.get_bytes(&hash).expect("Reading blob"), data);
+
+			let mut streamed = Vec::new();
+			store.get(&hash).expect("Opening blob").read_to_end(&mut streamed).expect("Streaming blob");
+			assert_eq!(streamed, data);
+		}
+	}
+
+	#[test]
+	fn known_hash() {
+		assert_eq!(BlobHash::of(b"abc").to_hex(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+	}
+
+	#[test]
+	fn hash_transserializes_properly() {
+		let hash = BlobHash::of(b"chattium");
+		assert_eq!(BlobHash::from_json(hash.to_json()).unwrap(), hash);
+		assert_eq!(BlobHash::from_hex(&hash.to_hex().to_uppercase()), Some(hash));
+		BlobHash::from_json_string(&"\"abc\"".to_string()).unwrap_err();
+		assert_eq!(BlobHash::from_hex(&format!("+{}", &hash.to_hex()[1..])), None);
+		assert_eq!(BlobHash::from_hex(&format!("0+{}", &hash.to_hex()[2..])), None);
+	}
+
+	#[test]
+	fn corrupted_blob_fails_verification() {
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap();
+		let hash = store.put_bytes(b"uncorrupted").unwrap();
+
+		let hex = hash.to_hex();
+		OpenOptions::new().append(true).open(dir.path().join(&hex[..2]).join(&hex[2..])).unwrap().write_all(b"!").unwrap();
+
+		match store.get_bytes(&hash) {
+			Err(BlobError::HashMismatch{expected, ..}) => assert_eq!(expected, hash),
+			other => panic!("Expected hash mismatch, got {:?}", other),
+		}
+		store.get(&hash).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+	}
+
+	#[test]
+	fn gc_removes_only_unreferenced() {
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap();
+
+		let kept = store.put_bytes(b"kept").unwrap();
+		let dropped = store.put_bytes(b"dropped").unwrap();
+		assert_eq!(store.put_bytes(b"dropped").unwrap(), dropped);
+		assert_eq!(store.refcount(&dropped).unwrap(), 2);
+
+		assert_eq!(store.release(&dropped).unwrap(), 1);
+		assert_eq!(store.gc().unwrap(), 0);
+		assert_eq!(store.release(&dropped).unwrap(), 0);
+		assert_eq!(store.gc().unwrap(), 7);
+
+		assert!(store.contains(&kept));
+		assert!(!store.contains(&dropped));
+		match store.get_bytes(&dropped) {
+			Err(BlobError::NotFound(hash)) => assert_eq!(hash, dropped),
+			other => panic!("Expected not found, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn corrupt_refcount_isnt_collected() {
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap();
+		let hash = store.put_bytes(b"referenced").unwrap();
+
+		let hex = hash.to_hex();
+		fs::write(dir.path().join(&hex[..2]).join(format!("{}.refs", &hex[2..])), "1\u{0}\u{0}").unwrap();
+
+		match store.refcount(&hash) {
+			Err(BlobError::Io(e)) => assert_eq!(e.kind(), ErrorKind::InvalidData),
+			other => panic!("Expected invalid data, got {:?}", other),
+		}
+		store.gc().unwrap_err();
+		store.release(&hash).unwrap_err();
+		assert!(store.contains(&hash));
+		assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+	}
+
+	#[test]
+	fn quota_is_enforced() {
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap().with_quota(10);
+
+		store.put_bytes(b"123456").unwrap();
+		store.put_bytes(b"123456").unwrap();
+		match store.put_bytes(b"7890!") {
+			Err(BlobError::QuotaExceeded{quota, required}) => {
+				assert_eq!(quota, 10);
+				assert_eq!(required, 11);
+			},
+			other => panic!("Expected quota exceeded, got {:?}", other),
+		}
+		store.put_bytes(b"7890").unwrap();
+		assert_eq!(store.usage().unwrap(), 10);
+		assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+	}
+
+	#[test]
+	fn failed_puts_leave_no_temporaries() {
+		struct Failing;
+		impl Read for Failing {
+			fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+				Err(io::Error::new(ErrorKind::BrokenPipe, "unreadable"))
+			}
+		}
+
+		let dir = TempDir::new("chattium-blob").unwrap();
+		let store = BlobStore::open(dir.path()).unwrap();
+		store.put(Failing).unwrap_err();
+
+		// A file where the blob's directory should be
+		fs::write(dir.path().join(&BlobHash::of(b"misplaced").to_hex()[..2]), "").unwrap();
+		store.put_bytes(b"misplaced").unwrap_err();
+		assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+	}
+}
+
+#[cfg(test)]
+mod mention {
+	use cho::*;
+
+
+	static SPANS: &[(&str, &[(SpanKind, &str)])] = &[
+		("", &[]),
+		("no spans here", &[]),
+		("@alice", &[(SpanKind::Mention, "@alice")]),
+		("hi @alice!", &[(SpanKind::Mention, "@alice")]),
+		("@alice, @bob: hi", &[(SpanKind::Mention, "@alice"), (SpanKind::Mention, "@bob")]),
+		("(@alice)", &[(SpanKind::Mention, "@alice")]),
+		("\"@alice\"", &[(SpanKind::Mention, "@alice")]),
+		("@alice.", &[(SpanKind::Mention, "@alice")]),
+		("@alice...", &[(SpanKind::Mention, "@alice")]),
+		("@alice's cat", &[(SpanKind::Mention, "@alice")]),
+		("@jean-luc.picard- hi", &[(SpanKind::Mention, "@jean-luc.picard")]),
+		("@snake_case_", &[(SpanKind::Mention, "@snake_case_")]),
+		("@user42", &[(SpanKind::Mention, "@user42")]),
+		("@", &[]),
+		("@ alice", &[]),
+		("@@alice", &[]),
+		("@-alice", &[]),
+		("mail me@example.com", &[]),
+		("@żółć", &[(SpanKind::Mention, "@żółć")]),
+		("@Владимир: привет", &[(SpanKind::Mention, "@Владимир")]),
+		("@東京さん。", &[(SpanKind::Mention, "@東京さん")]),
+		("@José", &[(SpanKind::Mention, "@José")]),
+		("@Jose\u{301}!", &[(SpanKind::Mention, "@Jose\u{301}")]),
+		("@alice\u{200B}bob", &[(SpanKind::Mention, "@alice")]),
+		("#general", &[(SpanKind::Room, "#general")]),
+		("join #rust-beginners.", &[(SpanKind::Room, "#rust-beginners")]),
+		("C# is #1", &[(SpanKind::Room, "#1")]),
+		("##", &[]),
+		("#", &[]),
+		("@alice in #general", &[(SpanKind::Mention, "@alice"), (SpanKind::Room, "#general")]),
+		("http://example.com", &[(SpanKind::Url, "http://example.com")]),
+		("see https://example.com/a?b=c#d.", &[(SpanKind::Url, "https://example.com/a?b=c#d")]),
+		("(https://example.com/wiki/Rust_(language))", &[(SpanKind::Url, "https://example.com/wiki/Rust_(language)")]),
+		("(see https://example.com)", &[(SpanKind::Url, "https://example.com")]),
+		("[https://example.com]", &[(SpanKind::Url, "https://example.com")]),
+		("<https://example.com>", &[(SpanKind::Url, "https://example.com")]),
+		("HTTPS://EXAMPLE.COM", &[(SpanKind::Url, "HTTPS://EXAMPLE.COM")]),
+		("www.example.com, ok", &[(SpanKind::Url, "www.example.com")]),
+		("ftp://files.example.com/x.tar.gz", &[(SpanKind::Url, "ftp://files.example.com/x.tar.gz")]),
+		("http://", &[]),
+		("http://.", &[]),
+		("xhttp://example.com", &[]),
+		("https://example.com/@alice/#room", &[(SpanKind::Url, "https://example.com/@alice/#room")]),
+		("https://example.com/żółć „quoted”", &[(SpanKind::Url, "https://example.com/żółć")]),
+		("@alice https://x.io #room", &[(SpanKind::Mention, "@alice"), (SpanKind::Url, "https://x.io"), (SpanKind::Room, "#room")]),
+		("a\n@alice\n#b", &[(SpanKind::Mention, "@alice"), (SpanKind::Room, "#b")]),
+		("żółć@alice", &[]),
+		("日本@alice", &[]),
+		("。@alice", &[(SpanKind::Mention, "@alice")]),
+	];
+
+
+	#[test]
+	fn spans_table() {
+		for &(text, expected) in SPANS {
+			let spans = parse_spans(text);
+			let found: Vec<_> = spans.iter().map(|span| (span.kind, span.text(text))).collect();
+			assert_eq!(&found[..], expected, "Spans of {:?}", text);
+		}
+	}
+
+	#[test]
+	fn target_strips_sigil() {
+		let text = "@alice #room http://x.io";
+		let targets: Vec<_> = parse_spans(text).iter().map(|span| span.target(text)).collect();
+		assert_eq!(targets, vec!["alice", "room", "http://x.io"]);
+	}
+
+	#[test]
+	fn resolves_case_insensitively() {
+		let users = vec![ChatUser::me(ValidatedName::new("Alice").unwrap()), ChatUser::me(ValidatedName::new("Żaneta").unwrap()), ChatUser::me(ValidatedName::new("bob").unwrap())];
+		let text = "@alice @ŻANETA @carol @Bob";
+
+		let resolved: Vec<_> = resolve_mentions(text, &users).into_iter().map(|(span, user)| (span.text(text), user.name())).collect();
+		assert_eq!(resolved, vec![("@alice", "Alice"), ("@ŻANETA", "Żaneta"), ("@Bob", "bob")]);
+	}
+
+	#[test]
+	fn message_mentions() {
+		let alice = ChatUser::me(ValidatedName::new("alice").unwrap());
+		let bob = ChatUser::get(ValidatedName::new("bob").unwrap(), "127.0.0.1:0");
+
+		assert!(ChatMessage::new(bob.clone(), "hey @Alice!".to_string()).mentions(&alice));
+		assert!(!ChatMessage::new(bob.clone(), "hey alice".to_string()).mentions(&alice));
+		assert!(!ChatMessage::new(bob.clone(), "mail alice@alice.com".to_string()).mentions(&alice));
+		assert!(!ChatMessage::new(bob.clone(), "#alice".to_string()).mentions(&alice));
+		assert!(!ChatMessage::new(alice, "@alicebob".to_string()).mentions(&bob));
+	}
+}
+
+#[cfg(test)]
+mod markup {
+	use rand::{self, Rng};
+	use cho::markup::*;
+	use cho::markup;
+
+
+	static RENDERS: &[(&str, &str, &str)] = &[
+		// (markup, plain, html)
+		("", "", ""),
+		("plain", "plain", "plain"),
+		("**bold**", "bold", "<strong>bold</strong>"),
+		("*italic*", "italic", "<em>italic</em>"),
+		("~~strike~~", "strike", "<del>strike</del>"),
+		("`co*de*`", "co*de*", "<code>co*de*</code>"),
+		("`` a`b ``", "a`b", "<code>a`b</code>"),
+		("**bold *italic***", "bold italic", "<strong>bold <em>italic</em></strong>"),
+		("*it **bold** it*", "it bold it", "<em>it <strong>bold</strong> it</em>"),
+		("~~**both**~~", "both", "<del><strong>both</strong></del>"),
+		("**unterminated", "**unterminated", "**unterminated"),
+		("a * b * c", "a * b * c", "a * b * c"),
+		("2*3*4", "234", "2<em>3</em>4"),
+		("** not bold**", "** not bold**", "** not bold**"),
+		("****", "****", "****"),
+		("\\*not italic\\*", "*not italic*", "*not italic*"),
+		("\\\\*italic*", "\\italic", "\\<em>italic</em>"),
+		("\\n stays", "\\n stays", "\\n stays"),
+		("[link](http://example.com)", "link (http://example.com)", "<a href=\"http://example.com\" rel=\"nofollow noopener noreferrer\">link</a>"),
+		("[http://x.io](http://x.io)", "http://x.io", "<a href=\"http://x.io\" rel=\"nofollow noopener noreferrer\">http://x.io</a>"),
+		("[**b**](https://x.io/a_(b))", "b (https://x.io/a_(b))", "<a href=\"https://x.io/a_(b)\" rel=\"nofollow noopener noreferrer\"><strong>b</strong></a>"),
+		("[evil](javascript:alert(1))", "evil (javascript:alert(1))", "evil"),
+		("[x](\"onclick=\")", "x (\"onclick=\")", "x"),
+		("[](http://x.io)", "[](http://x.io)", "[](http://x.io)"),
+		("[x]()", "[x]()", "[x]()"),
+		("<script>&\"'", "<script>&\"'", "&lt;script&gt;&amp;&quot;&#39;"),
+		("a\nb", "a\nb", "a<br>\nb"),
+		("a\r\nb", "a\nb", "a<br>\nb"),
+		("> quote", "> quote", "<blockquote>quote</blockquote>"),
+		(">quote\n>> nested\nafter", "> quote\n> > nested\nafter", "<blockquote>quote\n<blockquote>nested</blockquote></blockquote>\nafter"),
+		("\\> not a quote", "> not a quote", "&gt; not a quote"),
+		("```rust\nfn main() {}\n```", "fn main() {}", "<pre><code class=\"language-rust\">fn main() {}</code></pre>"),
+		("```\n<b>**raw**</b>\n\n```\nafter", "<b>**raw**</b>\n\nafter", "<pre><code>&lt;b&gt;**raw**&lt;/b&gt;\n</code></pre>\nafter"),
+		("```\nunterminated", "unterminated", "<pre><code>unterminated</code></pre>"),
+		("> ```\n> quoted code\n> ```", "> quoted code", "<blockquote><pre><code>quoted code</code></pre></blockquote>"),
+		("żółć **gęślą** 日本", "żółć gęślą 日本", "żółć <strong>gęślą</strong> 日本"),
+	];
+
+	static CANONICAL: &[&str] = &[
+		"",
+		"**bold** *italic* ~~strike~~ `code` [link](http://x.io)",
+		"**bold *italic***",
+		"\\*escaped\\* \\[not\\](a link)",
+		"> quote\n> > nested\n> \\> not nested\nline",
+		"```rust\nfn main() {\n    println!(\"**\");\n}\n```",
+		"`` ` ``",
+	];
+
+
+	#[test]
+	fn renders_table() {
+		for &(source, plain, html) in RENDERS {
+			let doc = markup::parse(source);
+			assert_eq!(doc.to_plain(), plain, "Plain rendering of {:?}", source);
+			assert_eq!(doc.to_html(), html, "HTML rendering of {:?}", source);
+		}
+	}
+
+	#[test]
+	fn canonical_markup_roundtrips_exactly() {
+		for &source in CANONICAL {
+			assert_eq!(markup::parse(source).to_markup(), source);
+		}
+	}
+
+	#[test]
+	fn markup_roundtrips_through_ast() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {100000} else {10000};
+		let alphabet = ['*', '*', '~', '`', '[', ']', '(', ')', '\\', '>', ' ', '\n', 'a', 'b', 'ż', 'h', ':', '/'];
+
+		for &(source, _, _) in RENDERS {
+			let doc = markup::parse(source);
+			assert_eq!(markup::parse(&doc.to_markup()), doc, "Round-trip of {:?}", source);
+		}
+		for _ in 1..times {
+			let len = rng.gen_range(0, 20);
+			let source: String = (0..len).map(|_| *rng.choose(&alphabet).unwrap()).collect();
+			let doc = markup::parse(&source);
+			assert_eq!(markup::parse(&doc.to_markup()), doc, "Round-trip of {:?} via {:?}", source, doc.to_markup());
+		}
+	}
+
+	#[test]
+	fn ast_roundtrips_through_markup() {
+		let docs = vec![
+			Document(vec![Block::Line(vec![Inline::Code("``".to_string())])]),
+			Document(vec![Block::Line(vec![Inline::Text(">*~`[]\\".to_string())])]),
+			Document(vec![Block::Quote(vec![Block::CodeBlock{lang: None, code: "a\n\nb".to_string()}, Block::Line(vec![])])]),
+			Document(vec![Block::Line(vec![Inline::Link{text: vec![Inline::Italic(vec![Inline::Text("x".to_string())])], url: "http://x.io/(y)".to_string()}])]),
+		];
+
+		for doc in docs {
+			assert_eq!(markup::parse(&doc.to_markup()), doc);
+		}
+	}
+
+	#[test]
+	fn unclosed_delimiters_stay_text() {
+		for unclosed in &["*a ", "~~a ", "**a ", "*a ~~a ", "[a](", "[a "] {
+			let source = unclosed.repeat(2000);
+			assert_eq!(markup::parse(&source).to_plain(), source, "{:?} repeated has spans", unclosed);
+		}
+	}
+
+	#[test]
+	fn nesting_is_capped() {
+		fn innermost(inlines: &[Inline], depth: usize) -> (usize, &[Inline]) {
+			for inline in inlines {
+				match *inline {
+					Inline::Italic(ref inner) | Inline::Link{text: ref inner, ..} => return innermost(inner, depth + 1),
+					_ => {},
+				}
+			}
+			(depth, inlines)
+		}
+
+		let n = 100000;
+		let mut blocks = markup::parse(&(">".repeat(n) + "x")).0;
+		let mut depth = 0;
+		while let [Block::Quote(inner)] = &blocks[..] {
+			blocks = inner.clone();
+			depth += 1;
+		}
+		assert_eq!(depth, MAX_NESTING);
+		assert_eq!(blocks, [Block::Line(vec![Inline::Text(">".repeat(n - MAX_NESTING) + "x")])]);
+
+		for &(open, close) in &[("*a ", " a*"), ("[", "](u)")] {
+			let source = open.repeat(5000) + "b" + &close.repeat(5000);
+			let doc = markup::parse(&source);
+			match &doc.0[..] {
+				[Block::Line(inlines)] => {
+					let (depth, inner) = innermost(inlines, 0);
+					assert_eq!(depth, MAX_NESTING, "Nesting of {:?}", open);
+					assert!(inner.iter().all(|inline| matches!(*inline, Inline::Text(_))), "Spans deeper than the cap in {:?}", open);
+				},
+				blocks => panic!("{:?} parsed into {} blocks", open, blocks.len()),
+			}
+			assert_eq!(markup::parse(&doc.to_markup()), doc);
+		}
+	}
+
+	#[test]
+	fn ansi_strips_escapes() {
+		let ansi = markup::parse("**bold** \x1b[31mred").to_ansi();
+		assert_eq!(ansi, "\x1b[1mbold\x1b[22m \u{FFFD}[31mred");
+	}
+}
+
+#[cfg(test)]
+mod command {
+	use cho::*;
+
+
+	fn s(text: &str) -> String {
+		text.to_string()
+	}
+
+	fn n(name: &str) -> ValidatedName {
+		ValidatedName::new(name).unwrap()
+	}
+
+
+	#[test]
+	fn parses_valid() {
+		let table = vec![
+			("hello", Command::Say(s("hello"))),
+			("  hello /there ", Command::Say(s("  hello /there "))),
+			("//me is not a command", Command::Say(s("/me is not a command"))),
+			("/nick newname", Command::Nick(n("newname"))),
+			("/NICK newname\n", Command::Nick(n("newname"))),
+			("/nick \"new name\"", Command::Nick(n("new name"))),
+			("/nick 'it\\'s me'", Command::Nick(n("it's me"))),
+			("/nick new\\ name", Command::Nick(n("new name"))),
+			("/me waves", Command::Me(s("waves"))),
+			("/me   says \"hi\"  ", Command::Me(s("says \"hi\""))),
+			("/join #rust", Command::Join(s("rust"))),
+			("/join rust", Command::Join(s("rust"))),
+			("/join \"#two words\"", Command::Join(s("two words"))),
+			("/part", Command::Part{room: None, reason: None}),
+			("/part bye all", Command::Part{room: None, reason: Some(s("bye all"))}),
+			("/part #rust", Command::Part{room: Some(s("rust")), reason: None}),
+			("/part #rust bye all", Command::Part{room: Some(s("rust")), reason: Some(s("bye all"))}),
+			("/topic Rust 1.0 is out", Command::Topic{room: None, topic: s("Rust 1.0 is out")}),
+			("/topic #rust Rust 1.0 is out", Command::Topic{room: Some(s("rust")), topic: s("Rust 1.0 is out")}),
+			("/msg bob hi there", Command::Msg{to: n("bob"), text: s("hi there")}),
+			("/msg \"Żaneta K\" cześć", Command::Msg{to: n("Żaneta K"), text: s("cześć")}),
+			("/quit", Command::Quit(None)),
+			("/quit   gone fishing", Command::Quit(Some(s("gone fishing")))),
+		];
+
+		for (input, expected) in table {
+			assert_eq!(parse_command(input), Ok(expected), "Parsing {:?}", input);
+		}
+	}
+
+	#[test]
+	fn rejects_invalid() {
+		let table = vec![
+			("", CommandError::Empty),
+			("  \n", CommandError::Empty),
+			("/frobnicate", CommandError::Unknown(s("frobnicate"))),
+			("/", CommandError::Unknown(s(""))),
+			("/nick", CommandError::MissingArgument{command: "nick", argument: "name"}),
+			("/nick \"\"", CommandError::MissingArgument{command: "nick", argument: "name"}),
+			("/nick new name", CommandError::TooManyArguments{command: "nick"}),
+			("/nick \"new name", CommandError::UnterminatedQuote{command: "nick"}),
+			("/me  ", CommandError::MissingArgument{command: "me", argument: "action"}),
+			("/join", CommandError::MissingArgument{command: "join", argument: "#room"}),
+			("/join #", CommandError::MissingArgument{command: "join", argument: "#room"}),
+			("/join #a #b", CommandError::TooManyArguments{command: "join"}),
+			("/topic #rust", CommandError::MissingArgument{command: "topic", argument: "topic"}),
+			("/msg", CommandError::MissingArgument{command: "msg", argument: "user"}),
+			("/msg bob", CommandError::MissingArgument{command: "msg", argument: "text"}),
+			("/msg 'bob hi", CommandError::UnterminatedQuote{command: "msg"}),
+			("/nick \" padded \"", CommandError::InvalidName{command: "nick", error: NameError::EdgeWhitespace}),
+			("/msg \"a\u{202E}b\" hi", CommandError::InvalidName{command: "msg", error: NameError::BidiControl('\u{202E}')}),
+		];
+
+		for (input, expected) in table {
+			assert_eq!(parse_command(input), Err(expected), "Parsing {:?}", input);
+		}
+	}
+
+	#[test]
+	fn errors_have_usage_hints() {
+		let err = parse_command("/msg bob").unwrap_err();
+		assert_eq!(err.usage(), Some("/msg <user> <text>"));
+		assert_eq!(err.to_string(), "/msg is missing text; usage: /msg <user> <text>");
+		assert_eq!(parse_command("/what").unwrap_err().usage(), None);
+		assert_eq!(command_usage("TOPIC"), Some("/topic [#room] <topic>"));
+	}
+
+	#[test]
+	fn converts_to_payloads() {
+		let me = ChatUser::me(n("me"));
+
+		match parse_command("hi").unwrap().into_payload(&me, "lobby") {
+			Payload::Message(msg) => {
+				assert_eq!(msg.sender, me);
+				assert_eq!(msg.value, "hi");
+			},
+			other => panic!("Expected message, got {:?}", other),
+		}
+		match parse_command("/me waves").unwrap().into_payload(&me, "lobby") {
+			Payload::Action(msg) => assert_eq!(msg.value, "waves"),
+			other => panic!("Expected action, got {:?}", other),
+		}
+		match parse_command("/msg bob psst").unwrap().into_payload(&me, "lobby") {
+			Payload::Private{to, message} => {
+				assert_eq!(to.name(), "bob");
+				assert_eq!(message.value, "psst");
+			},
+			other => panic!("Expected private message, got {:?}", other),
+		}
+		assert_eq!(parse_command("/part").unwrap().into_payload(&me, "lobby"), Payload::Part{room: s("lobby"), reason: None});
+		assert_eq!(parse_command("/topic #rust hi").unwrap().into_payload(&me, "lobby"), Payload::Topic{room: s("rust"), topic: s("hi")});
+		assert_eq!(parse_command("/nick you").unwrap().into_payload(&me, "lobby"), Payload::Nick(NickChange{old: s("me"), new: s("you")}));
+		assert_eq!(parse_command("/join #a").unwrap().into_payload(&me, "lobby"), Payload::Join{room: s("a")});
+		assert_eq!(parse_command("/quit").unwrap().into_payload(&me, "lobby"), Payload::Quit{reason: None});
+	}
+
+	mod j_son {
+		use random_ip;
+		use random_name;
+		use random_user_name;
+		use random_text;
+		use random_time;
+		use rand::{self, Rng};
+		use time::Duration;
+		use cho::*;
+		use cho::json::*;
+
+
+		#[test]
+		fn payloads_transserialize_properly() {
+			let mut rng = rand::thread_rng();
+			let times = if cfg!(feature = "ci") {10000} else {100};
+
+			for _ in 1..times {
+				let user = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
+				let payloads = vec![
+					Payload::Message(ChatMessage::new(user.clone(), random_text(&mut rng))),
+					Payload::Action(ChatMessage::new(user.clone(), random_text(&mut rng))),
+					Payload::Private{to: ChatUser::me(random_user_name(&mut rng)), message: ChatMessage::new(user.clone(), random_text(&mut rng))},
+					Payload::Nick(NickChange{old: random_name(&mut rng), new: random_name(&mut rng)}),
+					Payload::Join{room: random_name(&mut rng)},
+					Payload::Part{room: random_name(&mut rng), reason: None},
+					Payload::Part{room: random_name(&mut rng), reason: Some(random_text(&mut rng))},
+					Payload::Topic{room: random_name(&mut rng), topic: random_text(&mut rng)},
+					Payload::Quit{reason: Some(random_text(&mut rng))},
+					Payload::Kick(Moderation{target: Target::User(random_name(&mut rng)), reason: None, issuer: user.clone(), expires: None}),
+					Payload::Ban(Moderation{
+						target: Target::Address(AddressRange::new(random_ip(&mut rng).ip(), 16).unwrap()),
+						reason: Some(random_text(&mut rng)),
+						issuer: user.clone(),
+						expires: Some(random_time(&mut rng)),
+					}),
+					Payload::Mute(Moderation{target: Target::NamePattern(random_name(&mut rng) + "*"), reason: None, issuer: user.clone(), expires: Some(random_time(&mut rng))}),
+					Payload::SlowDown{kind: "message".to_string(), retry_after: Duration::milliseconds(rng.gen_range(0, 100000))},
+					Payload::Ping{time: random_time(&mut rng)},
+					Payload::Pong{ping: random_time(&mut rng), time: random_time(&mut rng)},
+				];
+
+				for payload in payloads {
+					let payload_s = payload.to_json_string().expect("Serialization to string via Payload");
+					let trans = Payload::from_json_string(&payload_s).expect("Deserialization from string via Payload");
+					assert_eq!(payload, trans);
+				}
+			}
+		}
+
+		#[test]
+		fn slow_down_rounds_up() {
+			let payload = Payload::SlowDown{kind: "message".to_string(), retry_after: Duration::microseconds(1500)};
+			assert_eq!(payload.to_json_string().unwrap(), "{\"kind\":\"message\",\"retry_after\":2,\"type\":\"slow_down\"}");
+		}
+
+		#[test]
+		fn deserialization_from_malformed_fails() {
+			Payload::from_json_string(&"{\"type\": \"frobnicate\"}".to_string()).unwrap_err();
+			Payload::from_json_string(&"{\"type\": \"join\"}".to_string()).unwrap_err();
+			Payload::from_json_string(&"{\"room\": \"lobby\"}".to_string()).unwrap_err();
+		}
+	}
+}
+
+#[cfg(test)]
+mod nick {
+	use random_name;
+	use rand;
+	use cho::*;
+
+
+	#[test]
+	fn registers_distinct_names() {
+		let mut rng = rand::thread_rng();
+		let mut registry = NameRegistry::new();
+
+		let names: Vec<_> = (0..100).map(|_| random_name(&mut rng)).collect();
+		for name in &names {
+			let _ = registry.register(name);
+		}
+		for name in &names {
+			assert!(registry.check(name).is_err());
+		}
+		assert!(registry.len() <= names.len());
+	}
+
+	#[test]
+	fn rejects_collisions() {
+		let mut registry = NameRegistry::new();
+		registry.register("Admin").unwrap();
+		registry.register("bob").unwrap();
+		registry.register("kIm").unwrap();
+
+		let table = vec![
+			("admin", NameRejection::Taken{by: "Admin".to_string()}),
+			("ADMIN", NameRejection::Taken{by: "Admin".to_string()}),
+			("ａｄｍｉｎ", NameRejection::Taken{by: "Admin".to_string()}),
+			("\u{410}dmin", NameRejection::Confusable{with: "Admin".to_string()}),
+			("\u{430}dmin", NameRejection::Confusable{with: "Admin".to_string()}),
+			("Adrnin", NameRejection::Confusable{with: "Admin".to_string()}),
+			("b\u{43e}b", NameRejection::Confusable{with: "bob".to_string()}),
+			("klm", NameRejection::Confusable{with: "kIm".to_string()}),
+			("", NameRejection::Empty),
+			("   ", NameRejection::Empty),
+		];
+		for (name, rejection) in table {
+			assert_eq!(registry.register(name), Err(rejection), "Registering {:?}", name);
+		}
+
+		registry.register("Administrator").unwrap();
+		assert_eq!(registry.len(), 4);
+	}
+
+	#[test]
+	fn changes_names() {
+		let mut registry = NameRegistry::new();
+		registry.register("alice").unwrap();
+		registry.register("bob").unwrap();
+
+		registry.change(&NickChange{old: "alice".to_string(), new: "Alice".to_string()}).unwrap();
+		assert!(registry.contains("Alice"));
+		assert!(!registry.contains("alice"));
+
+		assert_eq!(registry.change(&NickChange{old: "Alice".to_string(), new: "B\u{41e}B".to_string()}),
+		           Err(NameRejection::Confusable{with: "bob".to_string()}));
+		assert_eq!(registry.change(&NickChange{old: "carol".to_string(), new: "dave".to_string()}),
+		           Err(NameRejection::NotRegistered("carol".to_string())));
+
+		registry.change(&NickChange{old: "Alice".to_string(), new: "carol".to_string()}).unwrap();
+		registry.register("alice").unwrap();
+		assert_eq!(registry.unregister("BOB"), Some("bob".to_string()));
+		registry.register("b\u{43e}b").unwrap();
+	}
+
+	#[test]
+	fn rejections_say_why() {
+		assert_eq!(NameRejection::Confusable{with: "Admin".to_string()}.to_string(), "name looks too much like \"Admin\"");
+		assert_eq!(name_skeleton("\u{410}dmin"), name_skeleton("admin"));
+		assert_eq!(fold_name("ＡＤＭＩＮ"), "admin");
+	}
+
+	mod j_son {
+		use random_name;
+		use rand;
+		use cho::*;
+		use cho::json::*;
+
+
+		#[test]
+		fn transserializes_properly() {
+			let mut rng = rand::thread_rng();
+			let times = if cfg!(feature = "ci") {100000} else {1000};
+
+			for _ in 1..times {
+				let change = NickChange{old: random_name(&mut rng), new: random_name(&mut rng)};
+				let trans = NickChange::from_json(change.to_json()).expect("Full transserialization via NickChange");
+				assert_eq!(change, trans);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod name {
+	use cho::*;
+
+
+	#[test]
+	fn validates_table() {
+		let policy = NamePolicy::default();
+		let table: Vec<(&str, Result<(), NameError>)> = vec![
+			("alice", Ok(())),
+			("Żaneta Kowalska", Ok(())),
+			("東京", Ok(())),
+			("x", Ok(())),
+			("José", Ok(())),
+			("Jose\u{301}", Ok(())),
+			("👍🏽", Ok(())),
+			("abcdefghijklmnopqrstuvwxyz012345", Ok(())),
+			("e\u{301}\u{301}\u{301}bcdefghijklmnopqrstuvwxyz012345", Ok(())),
+			("", Err(NameError::TooShort{min: 1, length: 0})),
+			("abcdefghijklmnopqrstuvwxyz0123456", Err(NameError::TooLong{max: 32, length: 33})),
+			(" alice", Err(NameError::EdgeWhitespace)),
+			("alice\u{3000}", Err(NameError::EdgeWhitespace)),
+			(" ", Err(NameError::EdgeWhitespace)),
+			("ali\nce", Err(NameError::ControlCharacter('\n'))),
+			("alice\r", Err(NameError::ControlCharacter('\r'))),
+			("\u{1b}[31malice", Err(NameError::ControlCharacter('\u{1b}'))),
+			("\u{85}alice", Err(NameError::ControlCharacter('\u{85}'))),
+			("alice\u{202E}gnp.exe", Err(NameError::BidiControl('\u{202E}'))),
+			("\u{2067}alice", Err(NameError::BidiControl('\u{2067}'))),
+			("ali\u{200B}ce", Err(NameError::ZeroWidth('\u{200B}'))),
+			("\u{FEFF}alice", Err(NameError::ZeroWidth('\u{FEFF}'))),
+			("alice\u{2060}", Err(NameError::ZeroWidth('\u{2060}'))),
+		];
+
+		for (name, expected) in table {
+			assert_eq!(validate_name(name, &policy).map(|validated| assert_eq!(&*validated, name)), expected, "Validating {:?}", name);
+		}
+	}
+
+	#[test]
+	fn policy_is_configurable() {
+		let policy = NamePolicy{
+			min_length: 3,
+			max_length: 5,
+			allow_edge_whitespace: true,
+			allow_bidi_controls: true,
+			allow_zero_width: true,
+			banned_chars: vec!['@', '#'],
+		};
+
+		assert!(validate_name(" a\u{200D}\u{202E}b", &policy).is_ok());
+		assert_eq!(validate_name("ab", &policy), Err(NameError::TooShort{min: 3, length: 2}));
+		assert_eq!(validate_name("abcdef", &policy), Err(NameError::TooLong{max: 5, length: 6}));
+		assert_eq!(validate_name("@bob", &policy), Err(NameError::BannedCharacter('@')));
+		assert_eq!(validate_name("b\tob", &policy), Err(NameError::ControlCharacter('\t')));
+	}
+
+	#[test]
+	fn invalid_names_dont_deserialize() {
+		use cho::json::*;
+
+		ChatUser::from_json_string(&"\"multi\\nline\"".to_string()).unwrap_err();
+		ChatUser::from_json_string(&"{\"name\": \"\", \"poster\": \"127.0.0.1:0\"}".to_string()).unwrap_err();
+		assert_eq!(ChatUser::from_json_string(&"\"valid\"".to_string()).unwrap().name(), "valid");
+	}
+}
+
+#[cfg(test)]
+mod policy {
+	use random_user_name;
+	use random_text;
+	use rand::{self, Rng};
+	use cho::*;
+
+
+	#[test]
+	fn sanitizes() {
+		let policy = MessagePolicy::default();
+		let table = vec![
+			("plain", "plain"),
+			("a\r\nb\rc\nd", "a\nb\nc\nd"),
+			("tab\tbed", "tab\tbed"),
+			("\u{1b}[2Jcleared", "[2Jcleared"),
+			("nul\u{0}l\u{7f}\u{85}", "null"),
+			("żółć 日本 👍🏽", "żółć 日本 👍🏽"),
+		];
+
+		for (input, expected) in table {
+			assert_eq!(policy.sanitize(input), expected, "Sanitizing {:?}", input);
+		}
+
+		let raw = MessagePolicy{strip_control: false, normalize_line_endings: false, ..policy};
+		assert_eq!(raw.sanitize("a\r\n\u{7}"), "a\r\n\u{7}");
+	}
+
+	#[test]
+	fn applies_limits() {
+		let policy = MessagePolicy{
+			max_bytes: 16,
+			max_graphemes: 8,
+			max_lines: 2,
+			max_combining: 2,
+			..MessagePolicy::default()
+		};
+		let table = vec![
+			("ok", vec![]),
+			("a\u{301}\u{302}", vec![]),
+			("", vec![MessageViolation::Blank]),
+			(" \n\t\u{7}", vec![MessageViolation::Blank]),
+			("123456789", vec![MessageViolation::TooManyGraphemes{max: 8, actual: 9}]),
+			("żółćżółćż", vec![MessageViolation::TooManyBytes{max: 16, actual: 18}, MessageViolation::TooManyGraphemes{max: 8, actual: 9}]),
+			("a\u{301}\u{302}\u{303}", vec![MessageViolation::TooManyCombiningMarks{max: 2, actual: 3}]),
+			("a\nb\r\nc", vec![MessageViolation::TooManyLines{max: 2, actual: 3}]),
+			("👨\u{200D}👩", vec![]),
+		];
+
+		for (input, expected) in table {
+			assert_eq!(policy.violations(&policy.sanitize(input)), expected, "Checking {:?}", input);
+		}
+	}
+
+	#[test]
+	fn apply_keeps_metadata() {
+		let mut rng = rand::thread_rng();
+		let policy = MessagePolicy::default();
+
+		for _ in 1..100 {
+			let mut message = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), random_text(&mut rng) + "\r\n\u{7}");
+			message.fill_id(&mut rng.next_u64());
+
+			let applied = policy.apply(message.clone()).expect("Applying default policy");
+			assert_eq!(applied.value, message.value[..message.value.len() - 3].to_string() + "\n");
+			assert_eq!(applied.id, message.id);
+			assert_eq!(applied.time_posted, message.time_posted);
+			assert_eq!(applied.sender, message.sender);
+		}
+
+		let blank = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), "\u{7}".to_string());
+		assert_eq!(policy.apply(blank), Err(vec![MessageViolation::Blank]));
+	}
+}
+
+#[cfg(test)]
+mod role {
+	use user;
+	use cho::*;
+	use cho::json::{FromJsonnable, ToJsonnable};
+
+
+	#[test]
+	fn built_in_roles() {
+		let permissions = Permissions::new(Role::Guest);
+		let table = vec![
+			(Role::Guest, vec![Permission::Post]),
+			(Role::Member, vec![Permission::Post, Permission::EditOwn, Permission::Invite]),
+			(Role::Moderator, vec![Permission::Post, Permission::EditOwn, Permission::DeleteAny, Permission::Kick, Permission::SetTopic, Permission::Invite]),
+			(Role::Admin, Permission::all().to_vec()),
+			(Role::Owner, Permission::all().to_vec()),
+		];
+
+		for (role, expected) in table {
+			let mut permissions = permissions.clone();
+			permissions.set_role("Alice", None, role);
+			assert_eq!(permissions.role(&user("alice", None), "#lobby"), role);
+			assert_eq!(permissions.permissions(&user("alice", None), "#lobby"), expected, "Permissions of {}", role);
+		}
+		assert_eq!(permissions.permissions(&user("bob", None), "#lobby"), vec![Permission::Post]);
+	}
+
+	#[test]
+	fn room_scoped_roles() {
+		let mut permissions = Permissions::default();
+		permissions.set_role("alice", Some("#ops"), Role::Moderator);
+		permissions.set_role("bob", None, Role::Admin);
+		permissions.set_role("bob", Some("#jail"), Role::Guest);
+
+		assert!(permissions.can(&user("alice", None), Permission::Kick, "#ops"));
+		assert!(!permissions.can(&user("alice", None), Permission::Kick, "#lobby"));
+		assert!(permissions.can(&user("bob", None), Permission::Ban, "#lobby"));
+		assert!(!permissions.can(&user("bob", None), Permission::Ban, "#jail"));
+
+		assert_eq!(permissions.remove_role("bob", Some("#jail")), Some(Role::Guest));
+		assert!(permissions.can(&user("bob", None), Permission::Ban, "#jail"));
+		assert_eq!(permissions.remove_role("bob", Some("#jail")), None);
+	}
+
+	#[test]
+	fn overrides() {
+		let mut permissions = Permissions::default();
+		permissions.set_role("carol", None, Role::Moderator);
+		permissions.set_role("dave", None, Role::Owner);
+
+		permissions.deny(&Subject::Role(Role::Member), Some("#announcements"), Permission::Post);
+		permissions.allow(&Subject::User("Alice".to_string()), Some("#announcements"), Permission::Post);
+		permissions.deny(&Subject::User("bob".to_string()), None, Permission::Invite);
+		permissions.allow(&Subject::User("bob".to_string()), Some("#open"), Permission::Invite);
+		permissions.deny(&Subject::Role(Role::Moderator), None, Permission::Kick);
+		permissions.deny(&Subject::User("dave".to_string()), None, Permission::Post);
+
+		let table = vec![
+			("alice", Permission::Post, "#announcements", true),
+			("bob", Permission::Post, "#announcements", false),
+			("bob", Permission::Post, "#lobby", true),
+			("bob", Permission::Invite, "#lobby", false),
+			("bob", Permission::Invite, "#open", true),
+			("carol", Permission::Post, "#announcements", true),
+			("carol", Permission::Kick, "#lobby", false),
+			("carol", Permission::DeleteAny, "#lobby", true),
+			("dave", Permission::Post, "#lobby", true),
+		];
+		for (name, permission, room, expected) in table {
+			assert_eq!(permissions.can(&user(name, None), permission, room), expected, "Can {} {} in {}", name, permission, room);
+		}
+
+		assert!(permissions.clear_override(&Subject::User("ALICE".to_string()), Some("#announcements"), Permission::Post));
+		assert!(!permissions.can(&user("alice", None), Permission::Post, "#announcements"));
+		assert!(!permissions.clear_override(&Subject::User("alice".to_string()), Some("#announcements"), Permission::Post));
+	}
+
+	#[test]
+	fn j_son() {
+		for &role in Role::all() {
+			assert_eq!(Role::from_json_string(&role.to_json_string().unwrap()).unwrap(), role);
+			assert_eq!(role.to_string().parse(), Ok(role));
+		}
+		for &permission in Permission::all() {
+			assert_eq!(Permission::from_json_string(&permission.to_json_string().unwrap()).unwrap(), permission);
+		}
+		assert_eq!(Permission::SetTopic.to_json_string().unwrap(), "\"set-topic\"");
+		assert!(Role::from_json_string(&"\"root\"".to_string()).is_err());
+		assert!(Permission::from_json_string(&"1".to_string()).is_err());
+	}
+}
+
+#[cfg(test)]
+mod moderation {
+	use user;
+	use std::net::IpAddr;
+	use std::time::Instant;
+	use time::{at_utc, Duration, Timespec};
+	use cho::*;
+
+
+	fn moderation(target: Target, expires: Option<i64>) -> Moderation {
+		Moderation{
+			target: target,
+			reason: Some("spam".to_string()),
+			issuer: user("mod", None),
+			expires: expires.map(|sec| at_utc(Timespec::new(sec, 0))),
+		}
+	}
+
+	fn range(s: &str) -> AddressRange {
+		s.parse().unwrap()
+	}
+
+
+	#[test]
+	fn address_ranges() {
+		let table = vec![
+			("192.0.2.0/24", "192.0.2.77", true),
+			("192.0.2.99/24", "192.0.2.1", true),
+			("192.0.2.0/24", "192.0.3.1", false),
+			("10.0.0.0/8", "10.255.255.255", true),
+			("0.0.0.0/0", "203.0.113.9", true),
+			("198.51.100.7", "198.51.100.7", true),
+			("198.51.100.7", "198.51.100.8", false),
+			("192.0.2.0/24", "::ffff:192.0.2.5", true),
+			("::ffff:192.0.2.1", "::ffff:192.0.2.1", true),
+			("::ffff:192.0.2.1", "192.0.2.1", true),
+			("::ffff:192.0.2.1", "192.0.2.2", false),
+			("::ffff:192.0.2.0/120", "::ffff:192.0.2.200", true),
+			("::ffff:192.0.2.0/120", "192.0.2.200", true),
+			("::ffff:192.0.2.0/120", "192.0.3.1", false),
+			("2001:db8::/32", "2001:db8:dead::beef", true),
+			("2001:db8::/32", "2001:db9::1", false),
+			("2001:db8::/32", "192.0.2.1", false),
+			("::/0", "::1", true),
+		];
+
+		for (range_s, address, expected) in table {
+			let address: IpAddr = address.parse().unwrap();
+			assert_eq!(range(range_s).contains(address), expected, "{} contains {}", range_s, address);
+		}
+
+		assert_eq!(range("192.0.2.99/24").to_string(), "192.0.2.0/24");
+		assert_eq!(range("2001:db8::1").to_string(), "2001:db8::1/128");
+		assert_eq!(range("::ffff:192.0.2.0/120"), range("192.0.2.0/24"));
+		assert_eq!(AddressRange::single("::ffff:192.0.2.1".parse().unwrap()).to_string(), "192.0.2.1/32");
+		for bad in &["192.0.2.0/33", "2001:db8::/129", "::ffff:192.0.2.0/95", "192.0.2/24", "192.0.2.0/", "nope"] {
+			assert_eq!(bad.parse::<AddressRange>(), Err(()), "Parsing {:?}", bad);
+		}
+	}
+
+	#[test]
+	fn targets() {
+		let alice = user("Alice", Some("192.0.2.10:6667"));
+		let table = vec![
+			(Target::User("alice".to_string()), true),
+			(Target::User("alicia".to_string()), false),
+			(Target::NamePattern("al*".to_string()), true),
+			(Target::NamePattern("A?ICE".to_string()), true),
+			(Target::NamePattern("*ce*".to_string()), true),
+			(Target::NamePattern("a?ce".to_string()), false),
+			(Target::NamePattern("*".to_string()), true),
+			(Target::NamePattern("a**c*e".to_string()), true),
+			(Target::NamePattern("*i*i*".to_string()), false),
+			(Target::NamePattern("alice?".to_string()), false),
+			(Target::Address(range("192.0.2.0/24")), true),
+			(Target::Address(range("198.51.100.0/24")), false),
+		];
+
+		for (target, expected) in table {
+			assert_eq!(target.matches(&alice), expected, "{:?} matches Alice", target);
+		}
+		assert!(!Target::Address(range("0.0.0.0/0")).matches(&user("alice", None)));
+	}
+
+	#[test]
+	fn pathological_patterns_match_quickly() {
+		let name = user(&"a".repeat(32), None);
+		let start = Instant::now();
+		assert!(!Target::NamePattern("*a".repeat(12) + "b").matches(&name));
+		assert!(Target::NamePattern("*a".repeat(12) + "*").matches(&name));
+		assert!(!Target::NamePattern("*a".repeat(33)).matches(&name));
+		assert!(start.elapsed().as_secs() < 1, "Matching took {:?}", start.elapsed());
+	}
+
+	#[test]
+	fn list_expiry() {
+		let now = at_utc(Timespec::new(1500000000, 0));
+		let alice = user("alice", Some("192.0.2.10:6667"));
+		let bob = user("bob", Some("198.51.100.1:6667"));
+		let mut list = ModerationList::new();
+
+		assert!(list.record(&Payload::Ban(moderation(Target::Address(range("192.0.2.0/24")), Some(1500000060)))));
+		assert!(list.record(&Payload::Mute(moderation(Target::User("bob".to_string()), None))));
+		assert!(!list.record(&Payload::Kick(moderation(Target::User("alice".to_string()), None))));
+		list.ban(moderation(Target::NamePattern("spam*".to_string()), Some(1400000000)));
+
+		assert!(list.is_banned(&alice, &now));
+		assert!(list.is_address_banned("192.0.2.200".parse().unwrap(), &now));
+		assert!(!list.is_muted(&alice, &now));
+		assert!(!list.is_banned(&bob, &now));
+		assert!(list.is_muted(&bob, &now));
+		assert!(!list.is_banned(&user("spammer", Some("203.0.113.1:1")), &now));
+
+		let later = now + Duration::minutes(1);
+		assert!(!list.is_banned(&alice, &later));
+		assert!(list.is_muted(&bob, &later));
+
+		assert_eq!(list.prune(&later), 2);
+		assert_eq!(list.bans().len(), 0);
+		assert_eq!(list.unmute(&Target::User("bob".to_string())), 1);
+		assert!(!list.is_muted(&bob, &later));
+	}
+
+	mod j_son {
+		use time::{at_utc, Timespec};
+		use cho::*;
+		use cho::json::*;
+		use super::{moderation, range};
+
+
+		#[test]
+		fn list_transserializes_properly() {
+			let mut list = ModerationList::new();
+			list.ban(moderation(Target::Address(range("2001:db8::/48")), Some(1500000000)));
+			list.ban(moderation(Target::NamePattern("troll*".to_string()), None));
+			list.mute(moderation(Target::User("bob".to_string()), Some(1600000000)));
+
+			let list_s = list.to_json_string().expect("Serialization to string via ModerationList");
+			let trans = ModerationList::from_json_string(&list_s).expect("Deserialization from string via ModerationList");
+			assert_eq!(list, trans);
+			assert_eq!(trans.bans()[0].expires, Some(at_utc(Timespec::new(1500000000, 0))));
+		}
+
+		#[test]
+		fn deserialization_from_malformed_fails() {
+			Target::from_json_string(&"{\"user\": \"a\", \"pattern\": \"b\"}".to_string()).unwrap_err();
+			Target::from_json_string(&"{\"group\": \"a\"}".to_string()).unwrap_err();
+			Target::from_json_string(&"{\"address\": \"10.0.0.0/40\"}".to_string()).unwrap_err();
+			ModerationList::from_json_string(&"{\"bans\": []}".to_string()).unwrap_err();
+		}
+	}
+}
+
+#[cfg(test)]
+mod ratelimit {
+	use user;
+	use time::{at_utc, Duration, Timespec};
+	use cho::*;
+
+
+
+	#[test]
+	fn token_bucket() {
+		let clock = ManualClock::new(at_utc(Timespec::new(1500000000, 0)));
+		let mut limiter = RateLimiter::with_clock(RateLimit{burst: 3, interval: Duration::seconds(2)}, &clock);
+		let alice = user("alice", Some("192.0.2.1:1"));
+
+		for _ in 0..3 {
+			assert_eq!(limiter.check_now(&alice, "message"), RateDecision::Allowed);
+		}
+		assert_eq!(limiter.check_now(&alice, "message"), RateDecision::RetryAfter(Duration::seconds(2)));
+
+		clock.advance(Duration::milliseconds(500));
+		assert_eq!(limiter.check_now(&alice, "message"), RateDecision::RetryAfter(Duration::milliseconds(1500)));
+		clock.advance(Duration::milliseconds(1500));
+		assert_eq!(limiter.check_now(&alice, "message"), RateDecision::Allowed);
+		assert!(!limiter.check_now(&alice, "message").is_allowed());
+
+		clock.advance(Duration::seconds(60));
+		for _ in 0..3 {
+			assert!(limiter.check_now(&alice, "message").is_allowed());
+		}
+		assert!(!limiter.check_now(&alice, "message").is_allowed());
+	}
+
+	#[test]
+	fn keyed_by_name_and_address() {
+		let now = at_utc(Timespec::new(1500000000, 0));
+		let mut limiter = RateLimiter::new(RateLimit{burst: 1, interval: Duration::seconds(10)});
+
+		assert!(limiter.check(&user("alice", Some("192.0.2.1:1")), "message", &now).is_allowed());
+		assert!(!limiter.check(&user("ALICE", Some("198.51.100.1:1")), "message", &now).is_allowed());
+		assert!(!limiter.check(&user("bob", Some("192.0.2.1:2")), "message", &now).is_allowed());
+		assert!(limiter.check(&user("bob", Some("198.51.100.1:1")), "message", &now).is_allowed());
+		assert!(limiter.check(&user("carol", None), "message", &now).is_allowed());
+
+		assert_eq!(limiter.prune(&(now + Duration::seconds(9))), 0);
+		assert_eq!(limiter.prune(&(now + Duration::seconds(10))), 5);
+		assert!(limiter.check(&user("alice", Some("192.0.2.1:1")), "message", &(now + Duration::seconds(10))).is_allowed());
+	}
+
+	#[test]
+	fn per_kind_limits() {
+		let now = at_utc(Timespec::new(1500000000, 0));
+		let mut limiter = RateLimiter::new(RateLimit::default());
+		limiter.set_limit("nick", RateLimit::per(2, Duration::minutes(1)));
+		assert_eq!(limiter.limit("nick"), RateLimit{burst: 2, interval: Duration::seconds(30)});
+		assert_eq!(limiter.limit("message"), RateLimit::default());
+		assert_eq!(RateLimit::per(u32::MAX, Duration::seconds(u32::MAX as i64)).interval, Duration::seconds(1));
+		assert_eq!(RateLimit::per(0, Duration::days(365 * 1000)).interval, Duration::days(365 * 1000));
+
+		let alice = user("alice", Some("192.0.2.1:1"));
+		let nick = Payload::Nick(NickChange{old: "alice".to_string(), new: "alicia".to_string()});
+		assert!(limiter.check_payload(&alice, &nick, &now).is_allowed());
+		assert!(limiter.check_payload(&alice, &nick, &now).is_allowed());
+
+		let refused = limiter.check_payload(&alice, &nick, &now);
+		assert_eq!(refused, RateDecision::RetryAfter(Duration::seconds(30)));
+		assert_eq!(refused.slow_down("nick"), Some(Payload::SlowDown{kind: "nick".to_string(), retry_after: Duration::seconds(30)}));
+		assert_eq!(RateDecision::Allowed.slow_down("nick"), None);
+
+		assert!(limiter.check(&alice, "message", &now).is_allowed());
+	}
+}
+
+#[cfg(test)]
+mod filter {
+	use user;
+	use time::{at_utc, Duration, Timespec};
+	use cho::*;
+
+
+	fn message(sender: &str, text: &str) -> ChatMessage {
+		let mut message = ChatMessage::new(user(sender, None), text.to_string());
+		message.time_posted = Timestamp::from_tm(&at_utc(Timespec::new(1500000000, 0)));
+		message.id = 42;
+		message
+	}
+
+	fn verdict<F: MessageFilter>(filter: &mut F, text: &str) -> FilterVerdict {
+		filter.filter(&message("alice", text))
+	}
+
+
+	#[test]
+	fn word_list() {
+		let mut censor = WordListFilter::new(&["spam", "h4x0r", "pas"], WordAction::Censor);
+		let table = vec![
+			("fine words", FilterVerdict::Accept),
+			("spam", FilterVerdict::Rewrite("****".to_string())),
+			("buy  SPAAAM!\tnow", FilterVerdict::Rewrite("buy  *******\tnow".to_string())),
+			("$p4m and sp@m", FilterVerdict::Rewrite("**** and ****".to_string())),
+			("l33t haxor", FilterVerdict::Rewrite("l33t *****".to_string())),
+			("spammer", FilterVerdict::Accept),
+			("pass", FilterVerdict::Accept),
+			("passs pa$$$$", FilterVerdict::Rewrite("***** ******".to_string())),
+		];
+		for (text, expected) in table {
+			assert_eq!(verdict(&mut censor, text), expected, "Filtering {:?}", text);
+		}
+
+		assert_eq!(verdict(&mut WordListFilter::new(&["spam"], WordAction::Reject), "spam spam"), FilterVerdict::Reject("contains 2 listed words".to_string()));
+		assert_eq!(verdict(&mut WordListFilter::new(&["spam"], WordAction::Flag), "5pam"), FilterVerdict::Flag("contains 1 listed word".to_string()));
+
+		let doubled = WordListFilter::new(&["pass", "bookkeeper"], WordAction::Reject);
+		for word in &["pass", "passss", "PAAASSSS", "bookkeeper", "boooookkkkeeeeper"] {
+			assert!(doubled.is_listed(word), "{:?} isn't listed", word);
+		}
+		for word in &["pas", "paass", "bokeper"] {
+			assert!(!doubled.is_listed(word), "{:?} is listed", word);
+		}
+	}
+
+	#[test]
+	fn repeats() {
+		let mut filter = RepeatFilter::new(2, Duration::minutes(1));
+		let start = at_utc(Timespec::new(1500000000, 0));
+		let table = vec![
+			("alice", 0, "hello", true),
+			("alice", 1, "Hello ", true),
+			("alice", 2, "HELLO", false),
+			("bob", 3, "hello", true),
+			("alice", 4, "bye", true),
+			("alice", 5, "hello", true),
+			("alice", 6, "hello", true),
+			("alice", 7, "hello", false),
+			("alice", 70, "hello", true),
+		];
+
+		for (sender, seconds, text, accepted) in table {
+			let mut message = message(sender, text);
+			message.time_posted = Timestamp::from_tm(&(start + Duration::seconds(seconds)));
+			assert_eq!(filter.filter(&message) == FilterVerdict::Accept, accepted, "{} at {}s: {:?}", sender, seconds, text);
+		}
+		assert!(!format!("{:?}", filter).contains("\"bob\""), "Stale repeats kept: {:?}", filter);
+	}
+
+	#[test]
+	fn caps_and_links() {
+		let mut caps = CapsFilter::default();
+		assert_eq!(verdict(&mut caps, "LOL OK"), FilterVerdict::Accept);
+		assert_eq!(verdict(&mut caps, "Hello There Everyone"), FilterVerdict::Accept);
+		assert_eq!(verdict(&mut caps, "STOP SHOUTING at me"), FilterVerdict::Rewrite("stop shouting at me".to_string()));
+		assert_eq!(verdict(&mut CapsFilter{lowercase: false, ..caps}, "WHY ARE WE YELLING"), FilterVerdict::Reject("100% of the message is in capital letters".to_string()));
+
+		let mut links = LinkFilter{max_links: 1};
+		assert_eq!(verdict(&mut links, "see https://example.com"), FilterVerdict::Accept);
+		assert_eq!(verdict(&mut links, "https://a.example and www.b.example"), FilterVerdict::Reject("message has 2 links, can have at most 1".to_string()));
+	}
+
+	#[test]
+	fn chain() {
+		let mut chain = FilterChain::new()
+			.with(WordListFilter::new(&["darn"], WordAction::Censor))
+			.with(CapsFilter::default())
+			.with(|message: &ChatMessage| if message.value.contains("review") {FilterVerdict::Flag("asked for review".to_string())} else {FilterVerdict::Accept})
+			.with(WordListFilter::new(&["forbidden"], WordAction::Reject))
+			.with(|message: &ChatMessage| FilterVerdict::Flag(format!("reached the end with {:?}", message.value)));
+		assert_eq!(chain.len(), 5);
+
+		let original = message("alice", "DARN IT PLEASE REVIEW");
+		match chain.run(original.clone()) {
+			FilterOutcome::Flagged(filtered, flags) => {
+				assert_eq!(filtered.value, "**** it please review");
+				assert_eq!(filtered.id, original.id);
+				assert_eq!(filtered.time_posted, original.time_posted);
+				assert_eq!(filtered.sender, original.sender);
+				assert_eq!(flags, vec!["asked for review".to_string(), "reached the end with \"**** it please review\"".to_string()]);
+			},
+			outcome => panic!("Unexpected outcome {:?}", outcome),
+		}
+
+		assert_eq!(chain.run(message("alice", "this is forbidden")), FilterOutcome::Rejected("contains 1 listed word".to_string()));
+
+		let mut quiet = FilterChain::new().with(WordListFilter::new(&["darn"], WordAction::Censor));
+		assert_eq!(quiet.run(message("alice", "hi")), FilterOutcome::Accepted(message("alice", "hi")));
+		assert_eq!(quiet.filter(&message("alice", "darn")), FilterVerdict::Rewrite("****".to_string()));
+		assert_eq!(FilterChain::new().filter(&message("alice", "darn")), FilterVerdict::Accept);
+	}
+}
+
+#[cfg(test)]
+mod audit {
+	use user;
+	use std::fs::{self, OpenOptions};
+	use std::io::{Cursor, Write};
+	use serde_json::value::Value;
+	use tempdir::TempDir;
+	use time::{at_utc, Timespec};
+	use cho::*;
+	use cho::json::*;
+
+
+	fn events() -> Vec<AuditEvent> {
+		let admin = ChatUser::get(ValidatedName::new("admin").unwrap(), "192.0.2.1:6667");
+		let time = |sec| at_utc(Timespec::new(sec, 0));
+		let ban = Moderation{
+			target: Target::User("troll".to_string()),
+			reason: Some("trolling".to_string()),
+			issuer: admin.clone(),
+			expires: Some(time(1500003600)),
+		};
+		let mut deleted = ChatMessage::new(user("troll", None), "spam".to_string());
+		deleted.time_posted = Timestamp::from_tm(&time(1499999999));
+		vec![
+			AuditEvent::new(AuditKind::Ban, admin.clone(), AuditTarget::User("troll".to_string()), time(1500000000)).after(&ban),
+			AuditEvent::new(AuditKind::RoleChange, admin.clone(), AuditTarget::User("alice".to_string()), time(1500000001))
+				.in_room("#ops")
+				.before(&Role::Member)
+				.after(&Role::Moderator),
+			AuditEvent::new(AuditKind::Delete, admin.clone(), AuditTarget::Message(42), time(1500000002))
+				.before(&deleted),
+			AuditEvent::new(AuditKind::RoomConfig, admin.clone(), AuditTarget::Room("#lobby".to_string()), time(1500000003))
+				.before(&Payload::Topic{room: "#lobby".to_string(), topic: "old topic".to_string()})
+				.after(&Payload::Topic{room: "#lobby".to_string(), topic: "new topic".to_string()}),
+		]
+	}
+
+	fn written_log() -> (String, AuditHead) {
+		written_log_with(JsonOptions::default())
+	}
+
+	fn written_log_with(options: JsonOptions) -> (String, AuditHead) {
+		let mut sink = AuditSink::new(Vec::new()).with_options(options);
+		for event in events() {
+			sink.append(event).unwrap();
+		}
+		let head = sink.head();
+		(String::from_utf8(sink.into_inner()).unwrap(), head)
+	}
+
+
+	#[test]
+	fn chain_verifies() {
+		let (log, head) = written_log();
+		let records = verify_audit_log(Cursor::new(log.as_bytes()), &head).unwrap();
+
+		assert_eq!(records.len(), 4);
+		assert_eq!(records.iter().map(|record| record.event.clone()).collect::<Vec<_>>(), events());
+		assert_eq!(records[0].previous, BlobHash([0; 32]));
+		for (previous, record) in records.iter().zip(records.iter().skip(1)) {
+			assert_eq!(record.previous, previous.hash);
+			assert_eq!(record.sequence, previous.sequence + 1);
+		}
+		assert_eq!(records[3].head(), head);
+		assert_eq!(verify_audit_log(Cursor::new(&b""[..]), &AuditHead::default()).unwrap(), vec![]);
+	}
+
+	#[test]
+	fn chain_covers_time_format() {
+		for &format in &[TimeFormat::Rfc3339, TimeFormat::EpochMillis] {
+			let (log, head) = written_log_with(JsonOptions::default().with_time_format(format));
+			assert_ne!(log, written_log().0);
+			let records = verify_audit_log(Cursor::new(log.as_bytes()), &head).unwrap();
+			assert_eq!(records.into_iter().map(|record| record.event).collect::<Vec<_>>(), events());
+			assert_ne!(head, written_log().1);
+		}
+	}
+
+	#[test]
+	fn actor_extensions_verify() {
+		let mut admin = ChatUser::get(ValidatedName::new("admin").unwrap(), "192.0.2.1:6667");
+		admin.extensions.insert("avatar".to_string(), Value::String("cat.png".to_string()));
+		let mut sink = AuditSink::new(Vec::new());
+		sink.append(AuditEvent::new(AuditKind::Kick, admin, AuditTarget::User("troll".to_string()), at_utc(Timespec::new(1500000000, 0))))
+			.unwrap();
+		let head = sink.head();
+
+		let records = verify_audit_log(Cursor::new(sink.into_inner()), &head).unwrap();
+		assert_eq!(records[0].event.actor.extensions["avatar"], Value::String("cat.png".to_string()));
+	}
+
+	#[test]
+	fn unreadable_events_refused() {
+		let policy = NamePolicy{max_length: 64, ..NamePolicy::default()};
+		let admin = ChatUser::get(validate_name(&"a".repeat(40), &policy).unwrap(), "192.0.2.1:6667");
+		let mut sink = AuditSink::new(Vec::new());
+		match sink.append(AuditEvent::new(AuditKind::Kick, admin, AuditTarget::User("troll".to_string()), at_utc(Timespec::new(1500000000, 0)))) {
+			Err(AuditError::Malformed{line: 1, ..}) => {},
+			result => panic!("Appending unreadable event gave {:?}", result),
+		}
+		assert_eq!(sink.next_sequence(), 0);
+		assert!(sink.into_inner().is_empty());
+	}
+
+	#[test]
+	fn tampering_detected() {
+		let (log, head) = written_log();
+		let lines: Vec<&str> = log.lines().collect();
+
+		let edited = log.replace("trolling", "being nice");
+		match verify_audit_log(Cursor::new(edited.as_bytes()), &head) {
+			Err(AuditError::HashMismatch{sequence: 0}) => {},
+			result => panic!("Edited log verified as {:?}", result),
+		}
+
+		let removed = [lines[0], lines[2], lines[3]].join("\n");
+		match verify_audit_log(Cursor::new(removed.as_bytes()), &head) {
+			Err(AuditError::OutOfSequence{expected: 1, actual: 2}) => {},
+			result => panic!("Log with removed record verified as {:?}", result),
+		}
+
+		let mut rehashed = AuditRecord::from_json_string(&lines[1].to_string()).unwrap();
+		rehashed.event.after = rehashed.event.before.clone();
+		let rehashed = AuditRecord::new(rehashed.sequence, rehashed.previous, rehashed.event);
+		let forged = [lines[0].to_string(), rehashed.to_json_string().unwrap(), lines[2].to_string()].join("\n");
+		match verify_audit_log(Cursor::new(forged.as_bytes()), &head) {
+			Err(AuditError::BrokenChain{sequence: 2}) => {},
+			result => panic!("Forged log verified as {:?}", result),
+		}
+
+		match verify_audit_log(Cursor::new(&b"{\"sequence\": 0}\n"[..]), &head) {
+			Err(AuditError::Malformed{line: 1, ..}) => {},
+			result => panic!("Malformed log verified as {:?}", result),
+		}
+	}
+
+	#[test]
+	fn truncation_and_rewriting_detected() {
+		let (log, head) = written_log();
+		let lines: Vec<&str> = log.lines().collect();
+
+		let truncated = lines[..2].join("\n");
+		let records = verify_audit_log(Cursor::new(log.as_bytes()), &head).unwrap();
+		verify_audit_log(Cursor::new(truncated.as_bytes()), &records[1].head()).unwrap();
+		match verify_audit_log(Cursor::new(truncated.as_bytes()), &head) {
+			Err(AuditError::HeadMismatch{expected, actual}) => {
+				assert_eq!(expected, head);
+				assert_eq!(actual, records[1].head());
+			},
+			result => panic!("Truncated log verified as {:?}", result),
+		}
+		match verify_audit_log(Cursor::new(&b""[..]), &head) {
+			Err(AuditError::HeadMismatch{actual, ..}) => assert_eq!(actual, AuditHead::default()),
+			result => panic!("Emptied log verified as {:?}", result),
+		}
+
+		let mut sink = AuditSink::new(Vec::new());
+		for mut event in events() {
+			event.before = None;
+			sink.append(event).unwrap();
+		}
+		let rewritten = String::from_utf8(sink.into_inner()).unwrap();
+		match verify_audit_log(Cursor::new(rewritten.as_bytes()), &head) {
+			Err(AuditError::HeadMismatch{expected, actual}) => {
+				assert_eq!(expected, head);
+				assert_eq!(actual.records, head.records);
+			},
+			result => panic!("Rewritten log verified as {:?}", result),
+		}
+	}
+
+	#[test]
+	fn file_sink_resumes() {
+		let dir = TempDir::new("chattium-oxide-audit").unwrap();
+		let path = dir.path().join("audit.log");
+		let events = events();
+
+		let head = {
+			let mut sink = AuditSink::open(&path, &AuditHead::default()).unwrap();
+			sink.append(events[0].clone()).unwrap();
+			sink.append(events[1].clone()).unwrap();
+			sink.head()
+		};
+		let head = {
+			let mut sink = AuditSink::open(&path, &head).unwrap();
+			assert_eq!(sink.next_sequence(), 2);
+			sink.append(events[2].clone()).unwrap();
+			sink.head()
+		};
+		let records = verify_audit_log(Cursor::new(fs::read(&path).unwrap()), &head).unwrap();
+		assert_eq!(records.len(), 3);
+		assert_eq!(records[2].event, events[2]);
+
+		match AuditSink::open(&path, &AuditHead::default()) {
+			Err(AuditError::HeadMismatch{..}) => {},
+			result => panic!("Opening log at the wrong head gave {:?}", result.map(|_| ())),
+		}
+		let record = fs::read(&path).unwrap();
+		let cut_off = &record[..record.iter().position(|&b| b == b'\n').unwrap() / 2];
+		OpenOptions::new().append(true).open(&path).unwrap().write_all(cut_off).unwrap();
+		let head = {
+			let mut sink = AuditSink::open(&path, &head).unwrap();
+			assert_eq!(sink.next_sequence(), 3);
+			sink.append(events[3].clone()).unwrap();
+			sink.head()
+		};
+		let records = verify_audit_log(Cursor::new(fs::read(&path).unwrap()), &head).unwrap();
+		assert_eq!(records.len(), 4);
+
+		OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{}\n").unwrap();
+		match AuditSink::open(&path, &head) {
+			Err(AuditError::Malformed{line: 5, ..}) => {},
+			result => panic!("Opening tampered-with log gave {:?}", result.map(|_| ())),
+		}
+	}
+}
+
+#[cfg(test)]
+mod privacy {
+	use user;
+	use serde_json::value::Value;
+	use cho::*;
+	use cho::json::*;
+
+
+	fn poster(s: &str) -> Poster {
+		s.parse().unwrap()
+	}
+
+
+	#[test]
+	fn masks() {
+		let table = vec![
+			("192.0.2.10:51234", PosterMask::Full, Some("192.0.2.10:51234")),
+			("192.0.2.10:51234", PosterMask::IpOnly, Some("192.0.2.10")),
+			("192.0.2.10:51234", PosterMask::Truncated, Some("192.0.2.0/24")),
+			("[::ffff:192.0.2.10]:51234", PosterMask::Truncated, Some("192.0.2.0/24")),
+			("[2001:db8:1234:5678::1]:51234", PosterMask::IpOnly, Some("2001:db8:1234:5678::1")),
+			("[2001:db8:1234:5678::1]:51234", PosterMask::Truncated, Some("2001:db8:1234::/48")),
+			("192.0.2.10:51234", PosterMask::Omitted, None),
+		];
+
+		for (address, mask, expected) in table {
+			assert_eq!(mask.apply(&poster(address), b"key").as_ref().map(|s| &s[..]), expected, "Masking {} with {:?}", address, mask);
+		}
+	}
+
+	#[test]
+	fn cloaks() {
+		let cloak = |address, key: &[u8]| PosterMask::Cloaked.apply(&poster(address), key).unwrap();
+
+		let base = cloak("192.0.2.10:1", b"key");
+		assert!(base.starts_with("cloak-"));
+		assert_eq!(base.len(), "cloak-".len() + 16);
+		assert!(!base.contains("192"));
+		assert_eq!(cloak("192.0.2.10:2", b"key"), base);
+		assert_eq!(cloak("[::ffff:192.0.2.10]:3", b"key"), base);
+		assert!(cloak("192.0.2.11:1", b"key") != base);
+		assert!(cloak("192.0.2.10:1", b"other key") != base);
+	}
+
+	#[test]
+	fn cloak_key_not_debugged() {
+		let debugged = format!("{:?}", PosterPrivacy::new(b"hunter2"));
+		assert!(!debugged.contains("hunter2"), "{}", debugged);
+		assert!(!debugged.contains(&format!("{:?}", b"hunter2")[1..8]), "{}", debugged);
+		assert!(debugged.contains("Cloaked"));
+	}
+
+	#[test]
+	fn by_viewer_role() {
+		let mut permissions = Permissions::default();
+		permissions.set_role("root", None, Role::Owner);
+		permissions.set_role("mod", Some("#lobby"), Role::Moderator);
+		let mut privacy = PosterPrivacy::new(b"secret");
+		privacy.set_mask(Role::Member, PosterMask::Truncated);
+
+		let viewer = |name| user(name, None);
+		assert_eq!(privacy.mask_for(&permissions, &viewer("root"), "#lobby"), PosterMask::Full);
+		assert_eq!(privacy.mask_for(&permissions, &viewer("mod"), "#lobby"), PosterMask::Cloaked);
+		assert_eq!(privacy.mask_for(&permissions, &viewer("mod"), "#other"), PosterMask::Truncated);
+		assert_eq!(PosterPrivacy::new(b"secret").mask(Role::Member), PosterMask::Omitted);
+		assert_eq!(privacy.mask(Role::Admin), PosterMask::IpOnly);
+	}
+
+	#[test]
+	fn masked_serialization() {
+		let privacy = PosterPrivacy::new(b"secret");
+		let alice = user("alice", Some("192.0.2.10:51234"));
+
+		assert_eq!(alice.to_json_masked(&privacy, PosterMask::IpOnly), alice.to_json());
+		assert_eq!(ChatUser::from_json(alice.to_json_masked(&privacy, PosterMask::Full)).unwrap().poster(), alice.poster());
+		assert_eq!(alice.to_json_masked(&privacy, PosterMask::Omitted), Value::String("alice".to_string()));
+		assert_eq!(user("bob", None).to_json_masked(&privacy, PosterMask::Full), Value::String("bob".to_string()));
+
+		let message = ChatMessage::new(alice.clone(), "hi".to_string());
+		let payloads = vec![
+			Payload::Message(message.clone()),
+			Payload::Private{to: user("alice", Some("198.51.100.7:1")), message: message.clone()},
+			Payload::Ban(Moderation{target: Target::User("troll".to_string()), reason: None, issuer: alice.clone(), expires: None}),
+		];
+		for payload in payloads {
+			let masked = serde_json::to_string(&payload.to_json_masked(&privacy, PosterMask::Omitted)).unwrap();
+			assert!(!masked.contains("192.0.2") && !masked.contains("198.51.100"), "{} leaks an address", masked);
+			assert!(masked.contains("alice"));
+
+			let cloaked = serde_json::to_string(&payload.to_json_masked(&privacy, PosterMask::Cloaked)).unwrap();
+			assert!(!cloaked.contains("192.0.2") && cloaked.contains("cloak-"), "{} leaks an address", cloaked);
+
+			let ip_only = payload.to_json_masked(&privacy, PosterMask::IpOnly);
+			assert_eq!(ip_only, payload.to_json());
+			assert_eq!(Payload::from_json(ip_only).unwrap(), payload);
+		}
+	}
+}
+
+#[cfg(test)]
+mod skew {
+	use random_time;
+	use rand;
+	use time::{at_utc, Duration, Timespec, Tm};
+	use cho::*;
+	use cho::json::*;
+
+
+	fn at(ms: i64) -> Tm {
+		at_utc(Timespec::new(1500000000, 0)) + Duration::milliseconds(ms)
+	}
+
+	fn message(posted: i64, received: Option<i64>) -> ChatMessage {
+		let mut message = ChatMessage::new(ChatUser::me(ValidatedName::new("alice").unwrap()), "hi".to_string());
+		message.time_posted = Timestamp::from_tm(&at(posted));
+		if let Some(received) = received {
+			message.receive(Timestamp::from_tm(&at(received)));
+		}
+		message
+	}
+
+
+	#[test]
+	fn received_time_transserializes() {
+		let mut rng = rand::thread_rng();
+		for _ in 1..100 {
+			let mut message = message(0, None);
+			message.time_posted = Timestamp::from_tm(&random_time(&mut rng));
+			assert_eq!(ChatMessage::from_json(message.to_json()).unwrap(), message);
+			assert_eq!(message.order_time(), message.time_posted);
+
+			message.receive(Timestamp::from_tm(&random_time(&mut rng)));
+			let trans = ChatMessage::from_json_string(&message.to_json_string().unwrap()).unwrap();
+			assert_eq!(trans.time_received, message.time_received);
+			assert_eq!(trans.order_time(), message.time_received.unwrap());
+		}
+	}
+
+	#[test]
+	fn estimates_from_ping_pong() {
+		// Peer's clock is 5s ahead, 100ms each way
+		let skew = ClockSkew::estimate(&at(0), &at(5100), &at(200));
+		assert_eq!(skew, ClockSkew{offset: Duration::seconds(5), round_trip: Duration::milliseconds(200)});
+		assert_eq!(skew.to_local(&at(5300)), at(300));
+
+		let pong = Payload::Pong{ping: at(0), time: at(-3000)};
+		assert_eq!(ClockSkew::from_pong(&pong, &at(50)).map(|skew| skew.offset), Some(Duration::milliseconds(-3025)));
+		assert_eq!(ClockSkew::from_pong(&Payload::Ping{time: at(0)}, &at(50)), None);
+	}
+
+	#[test]
+	fn flags_skewed() {
+		let threshold = Duration::seconds(30);
+		let ahead = ClockSkew{offset: Duration::minutes(10), round_trip: Duration::zero()};
+		let table = vec![
+			(message(0, None), None, false),
+			(message(0, Some(10000)), None, false),
+			(message(-30000, Some(0)), None, false),
+			(message(-30001, Some(0)), None, true),
+			(message(600000, Some(0)), None, true),
+			(message(600000, Some(0)), Some(ahead), false),
+			(message(0, Some(0)), Some(ahead), true),
+		];
+		for (message, skew, expected) in table {
+			assert_eq!(message.is_skewed(threshold, skew.as_ref()), expected, "{:?} with {:?}", message.claimed_skew(), skew);
+		}
+
+		let mut filter = SkewFilter::new(threshold);
+		assert_eq!(filter.filter(&message(600000, Some(0))), FilterVerdict::Flag("claimed time is 600s off from receipt".to_string()));
+		filter.record("ALICE", ahead);
+		assert_eq!(filter.filter(&message(600000, Some(0))), FilterVerdict::Accept);
+		assert_eq!(filter.filter(&message(660000, Some(0))), FilterVerdict::Flag("claimed time is 60s off from receipt".to_string()));
+		assert_eq!(message(660000, Some(0)).corrected_skew(Some(&ahead)), Some(Duration::minutes(1)));
+		assert_eq!(message(660000, None).corrected_skew(Some(&ahead)), None);
+		assert_eq!(filter.forget("alice"), Some(ahead));
+		assert!(filter.skew("alice").is_none());
+	}
+}
+
+
+#[cfg(test)]
+mod timestamp {
+	use std::time::SystemTime;
+	use random_time;
+	use rand::{self, Rng};
+	use time::{at_utc, Timespec, Tm};
+	use cho::*;
+	use cho::json::*;
+
+
+	fn wire_format_matches<T: TimestampBackend>() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for i in 0..times {
+			let time =
+				if i % 2 == 0 {
+					random_time(&mut rng)
+				} else {
+					at_utc(Timespec::new(rng.gen_range(MIN_TIME_SEC, MAX_TIME_SEC + 1), rng.gen_range(0, 1_000_000_000)))
+				};
+			let backend = T::from_tm(&time);
+			assert_eq!(backend.as_timespec(), time.to_timespec());
+			assert_eq!(backend.to_tm(), time);
+
+			for &format in &[TimeFormat::Legacy, TimeFormat::Rfc3339, TimeFormat::EpochMillis] {
+				let options = JsonOptions::default().with_time_format(format);
+				let expected = time.to_json_string_with(&options).unwrap();
+				assert_eq!(backend.to_json_string_with(&options).unwrap(), expected);
+				assert_eq!(T::from_json_string(&expected).unwrap(), T::from_json(time.to_json_with(&options)).unwrap());
+			}
+			assert_eq!(T::from_json(time.to_json()).unwrap(), backend);
+		}
+	}
+
+	#[test]
+	fn tm_wire_format() {
+		wire_format_matches::<Tm>();
+	}
+
+	#[test]
+	fn system_time_wire_format() {
+		wire_format_matches::<SystemTime>();
+	}
+
+	#[test]
+	fn configured_backend_wire_format() {
+		wire_format_matches::<Timestamp>();
+	}
+
+	#[test]
+	fn system_time_before_epoch() {
+		for &(sec, nsec) in &[(-1, 0), (-1, 1), (-1, 999_999_999), (MIN_TIME_SEC, 500)] {
+			let spec = Timespec::new(sec, nsec);
+			assert_eq!(SystemTime::from_timespec(spec).as_timespec(), spec);
+		}
+	}
+
+	#[test]
+	fn message_time_is_backend_independent() {
+		let mut message = ChatMessage::new(ChatUser::me(ValidatedName::new("Timestamper").unwrap()), "hi".to_string());
+		message.time_posted = Timestamp::from_tm(&at_utc(Timespec::new(1500000000, 123456789)));
+		message.receive(Timestamp::from_tm(&at_utc(Timespec::new(1500000001, 0))));
+
+		let json = message.to_json_string().unwrap();
+		assert!(json.contains(r#""time_posted":{"nsec":123456789,"sec":1500000000}"#), "{}", json);
+		assert!(json.contains(r#""time_received":{"nsec":0,"sec":1500000001}"#), "{}", json);
+		assert_eq!(ChatMessage::from_json_string(&json).unwrap(), message);
+	}
+}
+
+
+#[cfg(all(test, feature = "modern-serde"))]
+mod bridge {
+	use serde_json1;
+	use random_ip;
+	use random_text;
+	use random_time;
+	use random_user_name;
+	use rand::{self, Rng};
+	use time::Tm;
+	use cho::*;
+	use cho::json::*;
+	use cho::json::bridge::{Legacy, Modern};
+
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	#[serde(crate = "serde1")]
+	struct Envelope {
+		room: String,
+		message: ChatMessage,
+		payload: Payload,
+		#[serde(with = "cho::json::bridge")]
+		relayed: Tm,
+	}
+
+	#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+	#[serde(crate = "serde1")]
+	struct Modernity {
+		level: u32,
+		tags: Vec<String>,
+	}
+
+	fn random_message<R: Rng>(rng: &mut R) -> ChatMessage {
+		let mut message = ChatMessage::new(ChatUser::get(random_user_name(rng), random_ip(rng)), random_text(rng));
+		message.time_posted = Timestamp::from_tm(&random_time(rng));
+		if rng.gen() {
+			message.receive(Timestamp::from_tm(&random_time(rng)));
+		}
+		message.id = rng.gen();
+		message
+	}
+
+
+	#[test]
+	fn protocol_types_serialize_identically() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {10000} else {1000};
+
+		for _ in 0..times {
+			let message = random_message(&mut rng);
+			let legacy = message.to_json_string().unwrap();
+			assert_eq!(serde_json1::to_string(&message).unwrap(), legacy);
+			assert_eq!(serde_json1::from_str::<ChatMessage>(&legacy).unwrap(), message);
+
+			let user = message.sender.clone();
+			assert_eq!(serde_json1::to_string(&user).unwrap(), user.to_json_string().unwrap());
+
+			let payloads = [Payload::Message(message.clone()),
+			                Payload::Private{
+			                    to: user.clone(),
+			                    message: message.clone(),
+			                },
+			                Payload::Topic{
+			                    room: "#lobby".to_string(),
+			                    topic: random_text(&mut rng),
+			                },
+			                Payload::Ping{time: random_time(&mut rng)}];
+			for payload in &payloads {
+				let legacy = payload.to_json_string().unwrap();
+				assert_eq!(serde_json1::to_string(payload).unwrap(), legacy);
+				assert_eq!(&serde_json1::from_str::<Payload>(&legacy).unwrap(), payload);
+			}
+		}
+	}
+
+	#[test]
+	fn embeds_in_derived_structs() {
+		let mut rng = rand::thread_rng();
+		let message = random_message(&mut rng);
+		let envelope = Envelope{
+			room: "#lobby".to_string(),
+			message: message.clone(),
+			payload: Payload::Message(message.clone()),
+			relayed: random_time(&mut rng),
+		};
+
+		let json = serde_json1::to_string(&envelope).unwrap();
+		assert!(json.contains(&message.to_json_string().unwrap()));
+		assert!(json.contains(&format!(r#""relayed":{}"#, envelope.relayed.to_json_string().unwrap())));
+		assert_eq!(serde_json1::from_str::<Envelope>(&json).unwrap(), envelope);
+
+		assert!(serde_json1::from_str::<Envelope>(&json.replace("\"sender\"", "\"sent_by\"")).is_err());
+	}
+
+	#[test]
+	fn adapters_round_trip() {
+		let mut rng = rand::thread_rng();
+		let time = random_time(&mut rng);
+		let modern = Modern(time);
+		let json = serde_json1::to_string(&modern).unwrap();
+		assert_eq!(json, time.to_json_string().unwrap());
+		assert_eq!(serde_json1::from_str::<Modern<Tm>>(&json).unwrap(), modern);
+
+		let legacy = Legacy(Modernity{
+			level: 3,
+			tags: vec!["new".to_string(), "shiny".to_string()],
+		});
+		let json = legacy.to_json_string().unwrap();
+		assert_eq!(json, serde_json1::to_string(&legacy.0).unwrap());
+		assert_eq!(Legacy::<Modernity>::from_json_string(&json).unwrap(), legacy);
+		assert!(Legacy::<Modernity>::from_json_string(&r#"{"level":-1,"tags":[]}"#.to_string()).is_err());
+	}
+}
+
+
+#[cfg(test)]
+mod stream {
+	use std::io::{self, Cursor, Read};
+	use random_ip;
+	use random_text;
+	use random_user_name;
+	use rand;
+	use cho::*;
+	use cho::json::*;
+	use serde_json::error::{Error as JsonError, ErrorCode};
+
+
+	fn random_history(count: usize) -> Vec<ChatMessage> {
+		let mut rng = rand::thread_rng();
+		(0..count).map(|id| {
+			let mut message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			message.id = id as u64 + 1;
+			message
+		}).collect()
+	}
+
+	/// Hands out one byte at a time, like a slow socket
+	struct Trickle<R: Read>(R);
+
+	impl<R: Read> Read for Trickle<R> {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let len = buf.len().min(1);
+			self.0.read(&mut buf[..len])
+		}
+	}
+
+
+	#[test]
+	fn reader_and_writer_round_trip() {
+		for message in random_history(100) {
+			let mut written = Vec::new();
+			message.to_json_writer(&mut written).unwrap();
+			assert_eq!(written, message.to_json_string().unwrap().into_bytes());
+			assert_eq!(ChatMessage::from_json_reader(Cursor::new(written)).unwrap(), message);
+		}
+
+		assert!(ChatMessage::from_json_reader(Cursor::new(b"{\"value\": ".to_vec())).is_err());
+	}
+
+	#[test]
+	fn json_lines() {
+		let history = random_history(500);
+		let mut written = Vec::new();
+		for message in &history {
+			message.to_json_writer(&mut written).unwrap();
+			written.extend_from_slice(b"\r\n");
+		}
+
+		let read: Vec<ChatMessage> = JsonStream::new(Trickle(Cursor::new(written))).map(Result::unwrap).collect();
+		assert_eq!(read, history);
+	}
+
+	#[test]
+	fn array() {
+		let history = random_history(500);
+		let mut written = Vec::new();
+		history.to_json_writer(&mut written).unwrap();
+
+		let read: Vec<ChatMessage> = JsonStream::array(Cursor::new(written)).map(Result::unwrap).collect();
+		assert_eq!(read, history);
+
+		assert_eq!(JsonStream::<_, ChatMessage>::array(Cursor::new(b" [ ] ".to_vec())).count(), 0);
+		assert_eq!(JsonStream::<_, ChatMessage>::new(Cursor::new(b" \n ".to_vec())).count(), 0);
+	}
+
+	#[test]
+	fn tricky_values() {
+		let json = br#"1 -2.5e3 "a \"quoted\" ] string" {"k": ["}", "{", "\\"]} [[], {}] true null"#;
+		let read: Vec<_> = JsonStream::<_, Raw>::new(Cursor::new(json.to_vec())).map(|value| value.unwrap().0).collect();
+		assert_eq!(read.len(), 7);
+		assert_eq!(read[2], "\"a \\\"quoted\\\" ] string\"");
+		assert_eq!(read[3], r#"{"k":["}","{","\\"]}"#);
+
+		let read: Vec<Option<(u8, bool)>> = JsonStream::array(Cursor::new(b"[[1,true], null,[2 , false]]".to_vec())).map(Result::unwrap).collect();
+		assert_eq!(read, vec![Some((1, true)), None, Some((2, false))]);
+	}
+
+	#[test]
+	fn undecodable_values_are_skipped() {
+		let read: Vec<_> = JsonStream::<_, u8>::array(Cursor::new(b"[1, 300, \"x\", 4]".to_vec())).collect();
+		assert_eq!(read.len(), 4);
+		assert_eq!(read[0].as_ref().unwrap(), &1);
+		assert!(read[1].is_err());
+		assert!(read[2].is_err());
+		assert_eq!(read[3].as_ref().unwrap(), &4);
+	}
+
+	#[test]
+	fn malformed_input_ends_stream() {
+		fn errors(json: &[u8], array: bool) -> Vec<ErrorCode> {
+			let stream = if array {JsonStream::<_, Raw>::array(Cursor::new(json.to_vec()))} else {JsonStream::new(Cursor::new(json.to_vec()))};
+			stream.filter_map(|value| match value {
+				Err(JsonError::Syntax(code, _, _)) => Some(code),
+				_ => None,
+			}).collect()
+		}
+
+		assert_eq!(errors(b"[1, 2", true), vec![ErrorCode::EOFWhileParsingList]);
+		assert_eq!(errors(b"[1 2]", true), vec![ErrorCode::ExpectedListCommaOrEnd]);
+		assert_eq!(errors(b"[1] 2", true), vec![ErrorCode::TrailingCharacters]);
+		assert_eq!(errors(b"1", true), vec![ErrorCode::ExpectedSomeValue]);
+		assert_eq!(errors(b"{\"a\": [1, 2}", false), vec![ErrorCode::EOFWhileParsingObject]);
+		assert_eq!(errors(b"\"unterminated", false), vec![ErrorCode::EOFWhileParsingString]);
+		assert_eq!(errors(b"1 , 2", false), vec![ErrorCode::ExpectedSomeValue]);
+
+		for json in &[&b"{\"a\" 1} 2"[..], b"[1 2] 3", b"tru 4", b"[{\"a\": 1,}] 5"] {
+			let read: Vec<_> = JsonStream::<_, Raw>::new(Cursor::new(json.to_vec())).collect();
+			assert_eq!(read.len(), 1, "Reading {:?}", String::from_utf8_lossy(json));
+			assert!(read[0].is_err());
+		}
+	}
+
+
+	/// Any JSON value, re-serialized
+	#[derive(Debug)]
+	struct Raw(String);
+
+	impl FromJsonnable for Raw {
+		fn from_json(json: ::serde_json::value::Value) -> Result<Self, JsonError> {
+			::serde_json::to_string(&json).map(Raw)
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod schema {
+	use random_ip;
+	use random_name;
+	use random_user_name;
+	use random_text;
+	use random_time;
+	use rand::{self, Rng};
+	use jsonschema::{self, Validator};
+	use serde_json1;
+	use std::collections::BTreeMap;
+	use std::net::IpAddr;
+	use time::{Duration, Tm};
+	use cho::*;
+	use cho::json::*;
+
+
+	fn validator(schema: ::serde_json::value::Value) -> Validator {
+		jsonschema::options().should_validate_formats(true).build(&modern(&schema)).unwrap()
+	}
+
+	fn modern(json: &::serde_json::value::Value) -> serde_json1::Value {
+		serde_json1::from_str(&::serde_json::to_string(json).unwrap()).unwrap()
+	}
+
+	fn assert_valid<T: ToJsonnable>(validator: &Validator, value: &T) {
+		assert_valid_with(validator, value, &JsonOptions::default())
+	}
+
+	fn assert_valid_with<T: ToJsonnable>(validator: &Validator, value: &T, options: &JsonOptions) {
+		let json = modern(&value.to_json_with(options));
+		if let Err(e) = validator.validate(&json) {
+			panic!("{} doesn't match the schema: {}", json, e);
+		}
+	}
+
+	fn random_payloads<R: Rng>(rng: &mut R) -> Vec<Payload> {
+		let user = ChatUser::get(random_user_name(rng), random_ip(rng));
+		let mut message = ChatMessage::new(user.clone(), random_text(rng));
+		message.receive(Timestamp::from_tm(&random_time(rng)));
+		message.id = rng.gen();
+		vec![
+			Payload::Message(message),
+			Payload::Action(ChatMessage::new(ChatUser::me(random_user_name(rng)), random_text(rng))),
+			Payload::Private{to: ChatUser::me(random_user_name(rng)), message: ChatMessage::new(user.clone(), random_text(rng))},
+			Payload::Nick(NickChange{old: random_name(rng), new: random_name(rng)}),
+			Payload::Join{room: random_name(rng)},
+			Payload::Part{room: random_name(rng), reason: None},
+			Payload::Part{room: random_name(rng), reason: Some(random_text(rng))},
+			Payload::Topic{room: random_name(rng), topic: random_text(rng)},
+			Payload::Quit{reason: None},
+			Payload::Kick(Moderation{target: Target::User(random_name(rng)), reason: None, issuer: user.clone(), expires: None}),
+			Payload::Ban(Moderation{
+				target: Target::Address(AddressRange::new(random_ip(rng).ip(), 16).unwrap()),
+				reason: Some(random_text(rng)),
+				issuer: user.clone(),
+				expires: Some(random_time(rng)),
+			}),
+			Payload::Mute(Moderation{target: Target::NamePattern(random_name(rng) + "*"), reason: None, issuer: user, expires: Some(random_time(rng))}),
+			Payload::SlowDown{kind: "message".to_string(), retry_after: Duration::milliseconds(rng.gen_range(0, 100000))},
+			Payload::Ping{time: random_time(rng)},
+			Payload::Pong{ping: random_time(rng), time: random_time(rng)},
+		]
+	}
+
+
+	#[test]
+	fn random_values_match_schemas() {
+		let mut rng = rand::thread_rng();
+		let times = if cfg!(feature = "ci") {1000} else {50};
+
+		let payload = validator(schema_document::<Payload>("Payload"));
+		let message = validator(schema_document::<ChatMessage>("ChatMessage"));
+		let user = validator(schema_document::<ChatUser>("ChatUser"));
+		let time = validator(schema_document::<Tm>("Time"));
+		for &format in &[TimeFormat::Legacy, TimeFormat::Rfc3339, TimeFormat::EpochMillis] {
+			let options = JsonOptions::default().with_time_format(format);
+			for _ in 0..times {
+				for p in random_payloads(&mut rng) {
+					assert_valid_with(&payload, &p, &options);
+					if let Payload::Message(ref m) = p {
+						assert_valid_with(&message, m, &options);
+						assert_valid_with(&user, &m.sender, &options);
+					}
+				}
+				assert_valid_with(&user, &ChatUser::me(random_user_name(&mut rng)), &options);
+				assert_valid_with(&time, &random_time(&mut rng), &options);
+			}
+		}
+	}
+
+	#[test]
+	fn audit_records_match_schema() {
+		let records = validator(schema_document::<AuditRecord>("AuditRecord"));
+		let lists = validator(schema_document::<ModerationList>("ModerationList"));
+		let admin = ChatUser::get(ValidatedName::new("admin").unwrap(), "192.0.2.1:6667");
+		let ban = Moderation{target: Target::User("troll".to_string()), reason: None, issuer: admin.clone(), expires: None};
+
+		let mut sink = AuditSink::new(Vec::new());
+		for (kind, target) in [(AuditKind::Ban, AuditTarget::User("troll".to_string())),
+		                       (AuditKind::Delete, AuditTarget::Message(42)),
+		                       (AuditKind::RoomConfig, AuditTarget::Room("#lobby".to_string()))] {
+			let event = AuditEvent::new(kind, admin.clone(), target, Tm::now()).in_room("#lobby").after(&ban);
+			assert_valid(&records, &sink.append(event).unwrap());
+		}
+
+		let mut list = ModerationList::default();
+		list.record(&Payload::Ban(ban.clone()));
+		list.record(&Payload::Mute(ban));
+		assert_valid(&lists, &list);
+	}
+
+	#[test]
+	fn schemas_declare_every_encoded_field() {
+		fn assert_declared<T: ToJsonnable + JsonSchema>(value: &T) {
+			let schema = modern(&T::json_schema());
+			let properties = schema["properties"].as_object().unwrap();
+			for key in modern(&value.to_json()).as_object().unwrap().keys() {
+				assert!(properties.contains_key(key), "{} isn't in {}", key, schema);
+			}
+		}
+
+		let mut rng = rand::thread_rng();
+		let admin = ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng));
+		let mut message = ChatMessage::new(admin.clone(), random_text(&mut rng));
+		message.receive(Timestamp::from_tm(&random_time(&mut rng)));
+		message.id = rng.gen_range(1, u64::MAX);
+		let ban = Moderation{target: Target::User(random_name(&mut rng)), reason: Some(random_text(&mut rng)), issuer: admin.clone(),
+		                     expires: Some(random_time(&mut rng))};
+		let event = AuditEvent::new(AuditKind::Ban, admin, AuditTarget::User(random_name(&mut rng)), random_time(&mut rng))
+			.in_room("#lobby").before(&message).after(&ban);
+
+		assert_declared(&message);
+		assert_declared(&ban);
+		assert_declared(&NickChange{old: random_name(&mut rng), new: random_name(&mut rng)});
+		assert_declared(&AuditSink::new(Vec::new()).append(event.clone()).unwrap());
+		assert_declared(&event);
+	}
+
+	#[test]
+	fn enums_list_every_name() {
+		let permissions = validator(Permission::json_schema());
+		for permission in Permission::all() {
+			assert_valid(&permissions, permission);
+		}
+		let roles = validator(Role::json_schema());
+		for role in Role::all() {
+			assert_valid(&roles, role);
+		}
+		assert!(!permissions.is_valid(&serde_json1::json!("fly")));
+		assert!(!roles.is_valid(&serde_json1::json!("emperor")));
+	}
+
+	#[test]
+	fn std_types_match_schemas() {
+		let mut rng = rand::thread_rng();
+
+		let mut map = BTreeMap::new();
+		map.insert(random_name(&mut rng), (rng.gen::<u8>(), 'x', Some(rng.gen::<i32>())));
+		assert_valid(&validator(<BTreeMap<String, (u8, char, Option<i32>)>>::json_schema()), &map);
+		assert_valid(&validator(<[u16; 3]>::json_schema()), &[rng.gen::<u16>(), rng.gen(), rng.gen()]);
+		assert_valid(&validator(Duration::json_schema()), &Duration::nanoseconds(-rng.gen_range(0, 1_000_000_000_000)));
+		assert_valid(&validator(IpAddr::json_schema()), &random_ip(&mut rng).ip());
+
+		assert!(!validator(u8::json_schema()).is_valid(&serde_json1::json!(256)));
+		assert!(!validator(<[u16; 3]>::json_schema()).is_valid(&serde_json1::json!([1, 2])));
+		assert!(!validator(<(u8, String)>::json_schema()).is_valid(&serde_json1::json!(["a", 1])));
+	}
+
+	#[test]
+	fn schemas_refuse_malformed_values() {
+		let payload = validator(Payload::json_schema());
+		assert!(!payload.is_valid(&serde_json1::json!({"type": "frobnicate"})));
+		assert!(!payload.is_valid(&serde_json1::json!({"type": "join"})));
+		assert!(!payload.is_valid(&serde_json1::json!({"room": "lobby"})));
+		assert!(payload.is_valid(&serde_json1::json!({"type": "join", "room": "lobby", "from": "a newer version"})));
+
+		let target = validator(Target::json_schema());
+		assert!(!target.is_valid(&serde_json1::json!({"user": "troll", "pattern": "troll*"})));
+
+		let time = validator(Tm::json_schema());
+		assert!(time.is_valid(&serde_json1::json!("2017-07-14T02:40:00Z")));
+		assert!(!time.is_valid(&serde_json1::json!("yesterday")));
+		assert!(!time.is_valid(&serde_json1::json!({"sec": 1500000000, "nsec": 1000000000})));
+		assert!(!time.is_valid(&serde_json1::json!({"sec": MAX_TIME_SEC + 1, "nsec": 0})));
+
+		let hash = validator(BlobHash::json_schema());
+		assert!(hash.is_valid(&serde_json1::json!("0".repeat(64))));
+		assert!(!hash.is_valid(&serde_json1::json!("0".repeat(63))));
+	}
+
+	#[test]
+	fn protocol_schema_is_valid() {
+		let protocol = protocol_schema();
+		assert!(jsonschema::meta::is_valid(&modern(&protocol)));
+
+		let mut rng = rand::thread_rng();
+		let mut by_ref = modern(&protocol);
+		by_ref["$ref"] = serde_json1::json!("#/$defs/Payload");
+		let payload = jsonschema::options().build(&by_ref).unwrap();
+		for p in random_payloads(&mut rng) {
+			assert!(payload.is_valid(&modern(&p.to_json())));
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod limits {
+	use std::io::{self, Cursor, Read};
+	use random_ip;
+	use random_text;
+	use random_user_name;
+	use rand;
+	use cho::*;
+	use cho::json::*;
+	use serde_json::value::Value;
+
+
+	/// Any JSON value, discarded
+	#[derive(Debug)]
+	struct Raw;
+
+	impl FromJsonnable for Raw {
+		fn from_json(_: Value) -> Result<Self, JsonError> {
+			Ok(Raw)
+		}
+	}
+
+
+	fn limited<T: FromJsonnable>(limits: DecodeLimits, json: &str) -> Result<T, Option<LimitError>> {
+		T::from_json_string_with(json, &JsonOptions::default().with_limits(limits)).map_err(|e| limit_exceeded(&e))
+	}
+
+
+	#[test]
+	fn limits_are_inclusive() {
+		let bytes = DecodeLimits{max_bytes: Some(9), ..DecodeLimits::unlimited()};
+		assert_eq!(limited::<Vec<u8>>(bytes, "[1, 2, 3]"), Ok(vec![1, 2, 3]));
+		assert_eq!(limited::<Vec<u8>>(bytes, "[1, 2, 3] "), Err(Some(LimitError::TooLarge(9))));
+
+		let depth = DecodeLimits{max_depth: Some(2), ..DecodeLimits::unlimited()};
+		assert_eq!(limited::<Vec<Vec<u8>>>(depth, "[[1], [2, [3]]]").unwrap_err(), Some(LimitError::TooDeep(2)));
+		assert!(limited::<Vec<Vec<u8>>>(depth, "[[1], [2], []]").is_ok());
+		assert!(limited::<Raw>(depth, r#"{"a": {"b": 1}, "c": [2]}"#).is_ok());
+		assert_eq!(limited::<Raw>(depth, r#"{"a": {"b": {}}}"#).unwrap_err(), Some(LimitError::TooDeep(2)));
+		assert_eq!(limited::<Raw>(DecodeLimits{max_depth: Some(0), ..DecodeLimits::unlimited()}, "[]").unwrap_err(),
+		           Some(LimitError::TooDeep(0)));
+
+		let strings = DecodeLimits{max_string_length: Some(5), ..DecodeLimits::unlimited()};
+		assert_eq!(limited::<String>(strings, r#""hello""#), Ok("hello".to_string()));
+		assert_eq!(limited::<String>(strings, r#""hello!""#), Err(Some(LimitError::StringTooLong(5))));
+		assert_eq!(limited::<Raw>(strings, r#"{"longer": 1}"#).unwrap_err(), Some(LimitError::StringTooLong(5)));
+		assert_eq!(limited::<String>(strings, r#""\"\"""#), Ok("\"\"".to_string()));
+		assert_eq!(limited::<String>(strings, r#""\u00e9""#).unwrap_err(), Some(LimitError::StringTooLong(5)));
+
+		let arrays = DecodeLimits{max_array_length: Some(2), ..DecodeLimits::unlimited()};
+		assert!(limited::<Vec<Vec<u8>>>(arrays, "[[1, 2], [3]]").is_ok());
+		assert!(limited::<Vec<String>>(arrays, r#"["a,b,c", "[1, 2, 3]"]"#).is_ok());
+		assert!(limited::<Raw>(arrays, r#"{"a": 1, "b": 2, "c": 3}"#).is_ok());
+		assert_eq!(limited::<Vec<Vec<u8>>>(arrays, "[[1, 2, 3]]").unwrap_err(), Some(LimitError::ArrayTooLong(2)));
+		assert_eq!(limited::<Vec<Vec<u8>>>(arrays, "[[], [], []]").unwrap_err(), Some(LimitError::ArrayTooLong(2)));
+	}
+
+	#[test]
+	fn other_errors_arent_limits() {
+		let limits = DecodeLimits{max_depth: Some(4), max_string_length: Some(64), ..DecodeLimits::unlimited()};
+		assert_eq!(limited::<Vec<u8>>(limits, "[1, 2"), Err(None));
+		assert_eq!(limited::<Vec<u8>>(limits, "[256]"), Err(None));
+		assert_eq!(limited::<Vec<u8>>(limits, "]]]] [1]"), Err(None));
+	}
+
+	#[test]
+	fn messages_decode_within_limits() {
+		let mut rng = rand::thread_rng();
+		let limits = DecodeLimits{max_bytes: Some(4096), max_depth: Some(3), max_string_length: Some(512), max_array_length: Some(16)};
+		let options = JsonOptions::default().with_limits(limits);
+
+		for _ in 0..100 {
+			let message = ChatMessage::new(ChatUser::get(random_user_name(&mut rng), random_ip(&mut rng)), random_text(&mut rng));
+			let trans = ChatMessage::from_json_string_with(&message.to_json_string().unwrap(), &options).unwrap();
+			assert_eq!(trans, message);
+		}
+
+		let huge = ChatMessage::new(ChatUser::me(random_user_name(&mut rng)), "spam".repeat(1000)).to_json_string().unwrap();
+		let error = ChatMessage::from_json_string_with(&huge, &options).unwrap_err();
+		assert_eq!(limit_exceeded(&error), Some(LimitError::StringTooLong(512)));
+		assert!(ChatMessage::from_json_string(&huge).is_ok());
+	}
+
+	#[test]
+	fn readers_stop_at_the_limit() {
+		let depth = DecodeLimits{max_depth: Some(64), ..DecodeLimits::unlimited()};
+		let error = Raw::from_json_reader_with(io::repeat(b'['), &JsonOptions::default().with_limits(depth)).unwrap_err();
+		assert_eq!(limit_exceeded(&error), Some(LimitError::TooDeep(64)));
+
+		let strings = DecodeLimits{max_string_length: Some(1024), ..DecodeLimits::unlimited()};
+		let error = String::from_json_reader_with(Cursor::new(b"\"").chain(io::repeat(b'a')), &JsonOptions::default().with_limits(strings)).unwrap_err();
+		assert_eq!(limit_exceeded(&error), Some(LimitError::StringTooLong(1024)));
+
+		let arrays = DecodeLimits{max_array_length: Some(100), ..DecodeLimits::unlimited()};
+		let error = Vec::<u8>::from_json_reader_with(Cursor::new(format!("[1{}]", ",1".repeat(1000))), &JsonOptions::default().with_limits(arrays)).unwrap_err();
+		assert_eq!(limit_exceeded(&error), Some(LimitError::ArrayTooLong(100)));
+	}
+
+	#[test]
+	fn streams_end_at_oversized_values() {
+		let limits = DecodeLimits{max_bytes: Some(16), ..DecodeLimits::unlimited()};
+		let json = b"[1, 2] \"short\" \"a string that's way too long\" 3".to_vec();
+
+		let mut stream = JsonStream::<_, Raw>::new(Cursor::new(json.clone())).with_options(JsonOptions::default().with_limits(limits));
+		assert!(stream.next().unwrap().is_ok());
+		assert!(stream.next().unwrap().is_ok());
+		assert_eq!(limit_exceeded(&stream.next().unwrap().unwrap_err()), Some(LimitError::TooLarge(16)));
+		assert!(stream.next().is_none());
+
+		assert_eq!(JsonStream::<_, Raw>::new(Cursor::new(json.clone())).filter(Result::is_ok).count(), 4);
+		assert_eq!(JsonStream::<_, Raw>::new(Cursor::new(json)).limited(limits).filter(Result::is_ok).count(), 2);
+
+		let depth = DecodeLimits{max_depth: Some(1), ..DecodeLimits::unlimited()};
+		let mut elements = JsonStream::<_, Raw>::array(Cursor::new(b"[[1], [[2]], [3]]".to_vec())).limited(depth);
+		assert!(elements.next().unwrap().is_ok());
+		assert_eq!(limit_exceeded(&elements.next().unwrap().unwrap_err()), Some(LimitError::TooDeep(1)));
+		assert!(elements.next().is_none());
+	}
+
+	#[test]
+	fn limited_by_default() {
+		assert!(DecodeLimits::default().is_limited());
+		assert!(!DecodeLimits::unlimited().is_limited());
+
+		let nested = format!("{}{}", "[".repeat(100), "]".repeat(100));
+		assert_eq!(limit_exceeded(&Raw::from_json_string(&nested).unwrap_err()), Some(LimitError::TooDeep(64)));
+		assert_eq!(limit_exceeded(&Raw::from_json_reader(Cursor::new(nested.clone())).unwrap_err()), Some(LimitError::TooDeep(64)));
+		assert_eq!(limit_exceeded(&JsonStream::<_, Raw>::new(Cursor::new(nested.clone())).next().unwrap().unwrap_err()),
+		           Some(LimitError::TooDeep(64)));
+		assert!(limited::<Raw>(DecodeLimits::unlimited(), &nested).is_ok());
+
+		let long = format!("[{}]", vec!["0"; 100000].join(","));
+		assert_eq!(limit_exceeded(&Vec::<u8>::from_json_string(&long).unwrap_err()), Some(LimitError::ArrayTooLong(65536)));
+		assert_eq!(limited::<Vec<u8>>(DecodeLimits::unlimited(), &long).map(|v| v.len()), Ok(100000));
+	}
 }